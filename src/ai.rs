@@ -2,16 +2,19 @@ use instant::Instant;
 use nalgebra_glm as glm;
 
 use crate::game;
+use crate::level_generator;
 
 pub trait GameAi {
     fn init(&mut self, level: &game::Level);
     fn pause(&mut self);
     fn next_move(&mut self, game: &game::Game, time: Instant) -> glm::Vec2;
+    fn handle_event(&mut self, level: &game::Level, event: &game::Event);
 }
 
 pub struct PathTracerAi {
     prev_update: Option<Instant>,
     path_index: usize,
+    verbose: bool,
 }
 
 impl PathTracerAi {
@@ -19,6 +22,15 @@ impl PathTracerAi {
         PathTracerAi {
             prev_update: None,
             path_index: 1,
+            verbose: true,
+        }
+    }
+
+    /// Like new(), but doesn't print the progress along the path
+    pub fn quiet() -> PathTracerAi {
+        PathTracerAi {
+            verbose: false,
+            ..PathTracerAi::new()
         }
     }
 }
@@ -34,6 +46,31 @@ impl GameAi for PathTracerAi {
         self.prev_update = None;
     }
 
+    fn handle_event(&mut self, level: &game::Level, event: &game::Event) {
+        // After respawning continue from the path point closest to the respawn position
+        if let game::EventKind::Respawned { pos } = event.kind {
+            let pos = glm::Vec2::from(&pos);
+            self.path_index = level
+                .path
+                .iter()
+                .enumerate()
+                .skip(1)
+                .min_by(|(_, a), (_, b)| {
+                    glm::distance(&glm::Vec2::from(*a), &pos)
+                        .partial_cmp(&glm::distance(&glm::Vec2::from(*b), &pos))
+                        .unwrap()
+                })
+                .map(|(i, _)| i)
+                .unwrap();
+            if self.verbose {
+                println!(
+                    "PathTracerAi: respawned, tracking path point {}",
+                    self.path_index
+                );
+            }
+        }
+    }
+
     fn next_move(&mut self, game: &game::Game, time: Instant) -> glm::Vec2 {
         // Determine how much rotation can be done on this update
         const MAX_ROTATION_PER_SEC: f32 = 0.25;
@@ -52,7 +89,9 @@ impl GameAi for PathTracerAi {
         if self.path_index < game.level.path.len() - 1 && glm::length(&to_target) <= TARGET_R {
             self.path_index = self.path_index + 1;
             to_target = glm::Vec2::from(&game.level.path[self.path_index]) - ball_pos;
-            println!("PathTracerAi: tracking path point {}", self.path_index);
+            if self.verbose {
+                println!("PathTracerAi: tracking path point {}", self.path_index);
+            }
         }
 
         // Calculate target velocity and target board angle
@@ -80,3 +119,63 @@ impl GameAi for PathTracerAi {
         rotation
     }
 }
+
+/// Wraps another AI and perturbs its moves with a randomly drifting board tilt, like the
+/// unsteady hands of a human player. The drift is a mean-reverting random walk whose standard
+/// deviation is noise * MAX_ANGLE. The same seed gives the same perturbations.
+pub struct NoisyAi<T: GameAi> {
+    ai: T,
+    noise: f32,
+    seed: u64,
+    rng: level_generator::Rng,
+    drift: glm::Vec2,
+    prev_update: Option<Instant>,
+}
+
+impl<T: GameAi> NoisyAi<T> {
+    pub fn new(ai: T, noise: f32, seed: u64) -> NoisyAi<T> {
+        NoisyAi {
+            ai,
+            noise,
+            seed,
+            rng: level_generator::Rng::new(seed),
+            drift: glm::zero(),
+            prev_update: None,
+        }
+    }
+}
+
+impl<T: GameAi> GameAi for NoisyAi<T> {
+    fn init(&mut self, level: &game::Level) {
+        self.ai.init(level);
+        self.rng = level_generator::Rng::new(self.seed);
+        self.drift = glm::zero();
+        self.prev_update = None;
+    }
+
+    fn pause(&mut self) {
+        self.ai.pause();
+        self.prev_update = None;
+    }
+
+    fn handle_event(&mut self, level: &game::Level, event: &game::Event) {
+        self.ai.handle_event(level, event);
+    }
+
+    fn next_move(&mut self, game: &game::Game, time: Instant) -> glm::Vec2 {
+        const REVERSION_RATE: f32 = 2.0; // 1/s
+        let dt = match self.prev_update {
+            Some(t0) => time.duration_since(t0).as_secs_f32(),
+            None => 0.0,
+        };
+        self.prev_update = Some(time);
+
+        // Uniform random steps with unit variance
+        let mut step = || (2.0 * self.rng.uniform() - 1.0) * 3f32.sqrt();
+        let step = glm::vec2(step(), step());
+        let sigma = self.noise * game::MAX_ANGLE * (2.0 * REVERSION_RATE).sqrt();
+        let prev_drift = self.drift;
+        self.drift += -REVERSION_RATE * dt * self.drift + sigma * dt.sqrt() * step;
+        self.ai.next_move(game, time) + self.drift - prev_drift
+    }
+}
@@ -0,0 +1,408 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use nalgebra_glm as glm;
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+
+use crate::game;
+
+// All the sounds are synthesized, so there are no audio assets. The Mixer does the synthesis
+// and doesn't depend on an audio device, so it can be rendered to a buffer, e.g. in tests.
+// Audio connects a Mixer to the default output device and drives it from the game events.
+
+const MAX_VOICES: usize = 16; // the oldest voice is dropped when a new one doesn't fit
+const ROLLING_FULL_SPEED: f32 = 20.0 * game::BALL_R; // ball speed where the rolling sound is loudest
+const IMPACT_FULL_IMPULSE: f32 = 20.0 * game::BALL_R; // impulse where the impact sound is loudest
+const ROLLING_GAIN: f32 = 0.3;
+const AMBIENT_GAIN: f32 = 0.03;
+
+/// Synthesizes the game sounds. Effects (rolling, impacts, drops) are scaled by the sfx volume
+/// and everything is scaled by the master volume.
+pub struct Mixer {
+    sample_rate: f32,
+    master_volume: f32,
+    sfx_volume: f32,
+    rolling_speed: f32,
+    rolling_gain: f32, // smoothed towards the gain of rolling_speed to avoid clicks
+    rolling_filter: f32,
+    ambient_filter: f32,
+    voices: Vec<Voice>,
+    rng: u32,
+}
+
+// A one-shot sound: a sum of exponentially decaying sinusoids and a noise burst
+struct Voice {
+    partials: Vec<Partial>,
+    noise_amp: f32,
+    noise_decay: f32, // 1/s
+    delay: f32,       // seconds before the voice starts
+    age: f32,         // seconds since the voice started
+    sfx: bool,
+}
+
+struct Partial {
+    freq: f32,
+    end_freq: f32, // the frequency glides linearly from freq to end_freq during glide_time
+    glide_time: f32,
+    amp: f32,
+    decay: f32, // 1/s
+    phase: f32,
+}
+
+impl Partial {
+    fn new(freq: f32, amp: f32, decay: f32) -> Partial {
+        Partial {
+            freq,
+            end_freq: freq,
+            glide_time: 1.0,
+            amp,
+            decay,
+            phase: 0.0,
+        }
+    }
+
+    fn gliding(freq: f32, end_freq: f32, glide_time: f32, amp: f32, decay: f32) -> Partial {
+        Partial {
+            end_freq,
+            glide_time,
+            ..Partial::new(freq, amp, decay)
+        }
+    }
+}
+
+impl Voice {
+    // The voice is done when all of its components have decayed below audibility
+    fn is_done(&self) -> bool {
+        let level = |amp: f32, decay: f32| amp * (-decay * self.age).exp();
+        self.age > 0.0
+            && level(self.noise_amp, self.noise_decay) < 1e-4
+            && self.partials.iter().all(|p| level(p.amp, p.decay) < 1e-4)
+    }
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Mixer {
+        Mixer {
+            sample_rate: sample_rate as f32,
+            master_volume: 1.0,
+            sfx_volume: 1.0,
+            rolling_speed: 0.0,
+            rolling_gain: 0.0,
+            rolling_filter: 0.0,
+            ambient_filter: 0.0,
+            voices: Vec::new(),
+            rng: 0x12345678,
+        }
+    }
+
+    /// Set the volumes, both in range 0...1
+    pub fn set_volumes(&mut self, master: f32, sfx: f32) {
+        self.master_volume = master.max(0.0).min(1.0);
+        self.sfx_volume = sfx.max(0.0).min(1.0);
+    }
+
+    /// Set the speed of the ball for the rolling sound, 0 silences it
+    pub fn set_rolling_speed(&mut self, speed: f32) {
+        self.rolling_speed = speed;
+    }
+
+    /// The clack of the ball hitting a wall, louder and brighter with stronger impulse
+    pub fn play_impact(&mut self, impulse: f32) {
+        self.play_clack(impulse, 1.0);
+    }
+
+    /// The ball hitting the board edge, which sounds lower than the inner walls
+    pub fn play_edge_hit(&mut self, impulse: f32) {
+        self.play_clack(impulse, 0.75);
+    }
+
+    /// The ball rattling over the hole edge and landing inside the game box
+    pub fn play_drop(&mut self) {
+        self.add_voice(Voice {
+            partials: vec![Partial::gliding(900.0, 300.0, 0.15, 0.2, 15.0)],
+            noise_amp: 0.3,
+            noise_decay: 12.0,
+            delay: 0.0,
+            age: 0.0,
+            sfx: true,
+        });
+        self.add_voice(Voice {
+            partials: vec![
+                Partial::gliding(140.0, 70.0, 0.2, 0.8, 10.0),
+                Partial::new(420.0, 0.3, 25.0),
+            ],
+            noise_amp: 0.2,
+            noise_decay: 40.0,
+            delay: 0.25,
+            age: 0.0,
+            sfx: true,
+        });
+    }
+
+    /// A rising arpeggio for reaching the goal
+    pub fn play_jingle(&mut self) {
+        for (i, freq) in [523.25, 659.25, 783.99, 1046.5].iter().enumerate() {
+            self.add_voice(Voice {
+                partials: vec![
+                    Partial::new(*freq, 0.25, 3.0),
+                    Partial::new(2.0 * freq, 0.08, 5.0),
+                    Partial::new(3.0 * freq, 0.04, 8.0),
+                ],
+                noise_amp: 0.0,
+                noise_decay: 1.0,
+                delay: 0.12 * i as f32,
+                age: 0.0,
+                sfx: false,
+            });
+        }
+    }
+
+    /// Render mono samples to every channel of an interleaved buffer
+    pub fn render(&mut self, out: &mut [f32], channels: usize) {
+        let dt = 1.0 / self.sample_rate;
+        let rolling_target =
+            ROLLING_GAIN * (self.rolling_speed / ROLLING_FULL_SPEED).max(0.0).min(1.0);
+        // Faster rolling sounds brighter
+        let rolling_cutoff = 150.0 + 1500.0 * (self.rolling_speed / ROLLING_FULL_SPEED).min(1.0);
+        let rolling_coeff = 1.0 - (-2.0 * PI * rolling_cutoff * dt).exp();
+        let ambient_coeff = 1.0 - (-2.0 * PI * 120.0 * dt).exp();
+        let smoothing = 1.0 - (-dt / 0.02).exp();
+        for frame in out.chunks_mut(channels) {
+            self.rolling_gain += (rolling_target - self.rolling_gain) * smoothing;
+            let noise = self.noise();
+            self.rolling_filter += (noise - self.rolling_filter) * rolling_coeff;
+            let noise = self.noise();
+            self.ambient_filter += (noise - self.ambient_filter) * ambient_coeff;
+
+            let mut sfx = self.rolling_gain * self.rolling_filter;
+            let mut other = AMBIENT_GAIN * self.ambient_filter;
+            for i in 0..self.voices.len() {
+                let sample = self.voice_sample(i, dt);
+                if self.voices[i].sfx {
+                    sfx += sample;
+                } else {
+                    other += sample;
+                }
+            }
+            // Soft clipping keeps many simultaneous sounds from distorting harshly
+            let sample = (self.master_volume * (self.sfx_volume * sfx + other)).tanh();
+            for s in frame.iter_mut() {
+                *s = sample;
+            }
+        }
+        self.voices.retain(|v| !v.is_done());
+    }
+
+    fn play_clack(&mut self, impulse: f32, pitch: f32) {
+        let strength = (impulse / IMPACT_FULL_IMPULSE).max(0.0).min(1.0);
+        // Slight random detuning so that repeated hits don't sound identical
+        let pitch = pitch * (0.95 + 0.1 * (0.5 + 0.5 * self.noise()));
+        // Wooden resonances (frequency, amplitude, decay, brightness). The brighter ones only
+        // come through with hard hits.
+        let partials = [
+            (820.0, 0.5, 45.0, 0.0),
+            (1930.0, 0.25, 60.0, 0.5),
+            (3150.0, 0.15, 90.0, 1.0),
+        ]
+        .iter()
+        .map(|&(freq, amp, decay, brightness)| {
+            Partial::new(
+                pitch * freq,
+                amp * strength * strength.powf(brightness),
+                decay,
+            )
+        })
+        .collect();
+        self.add_voice(Voice {
+            partials,
+            noise_amp: 0.3 * strength,
+            noise_decay: 300.0,
+            delay: 0.0,
+            age: 0.0,
+            sfx: true,
+        });
+    }
+
+    fn add_voice(&mut self, voice: Voice) {
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+        self.voices.push(voice);
+    }
+
+    fn voice_sample(&mut self, i: usize, dt: f32) -> f32 {
+        let noise = self.noise();
+        let voice = &mut self.voices[i];
+        if voice.delay > 0.0 {
+            voice.delay -= dt;
+            return 0.0;
+        }
+        let t = voice.age;
+        voice.age += dt;
+        // Short attack to avoid clicks
+        let attack = (t / 0.001).min(1.0);
+        let mut sample = voice.noise_amp * (-voice.noise_decay * t).exp() * noise;
+        for p in voice.partials.iter_mut() {
+            let freq = p.freq + (p.end_freq - p.freq) * (t / p.glide_time).min(1.0);
+            p.phase = (p.phase + 2.0 * PI * freq * dt) % (2.0 * PI);
+            sample += p.amp * (-p.decay * t).exp() * p.phase.sin();
+        }
+        attack * sample
+    }
+
+    // White noise in -1...1 from a xorshift generator
+    fn noise(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+/// Plays the Mixer output on the default audio output device. If there is no usable device,
+/// the game runs silently.
+pub struct Audio {
+    mixer: Arc<Mutex<Mixer>>,
+    stream: Option<cpal::Stream>,
+}
+
+impl Audio {
+    pub fn new() -> Audio {
+        let (mixer, stream) = match open_output() {
+            Some((mixer, stream)) => (mixer, Some(stream)),
+            None => {
+                println!("No audio output available, running without sound");
+                (Arc::new(Mutex::new(Mixer::new(44100))), None)
+            }
+        };
+        Audio { mixer, stream }
+    }
+
+    /// Start the playback. On the web, browsers only allow this after user interaction, so
+    /// this should be called again e.g. when resuming the game from the menu.
+    pub fn resume(&self) {
+        if let Some(stream) = &self.stream {
+            if let Err(e) = stream.play() {
+                println!("Failed to start audio playback: {}", e);
+            }
+        }
+    }
+
+    pub fn set_volumes(&self, master: f32, sfx: f32) {
+        self.mixer.lock().unwrap().set_volumes(master, sfx);
+    }
+
+    /// Stop the rolling sound, e.g. when the game is paused
+    pub fn stop_rolling(&self) {
+        self.mixer.lock().unwrap().set_rolling_speed(0.0);
+    }
+
+    /// Update the sounds from the game state and the events of the latest game update
+    pub fn update(&self, game: &game::Game, events: &[game::Event]) {
+        let mut mixer = self.mixer.lock().unwrap();
+        mixer.set_rolling_speed(match game.state {
+            game::State::InProgress => glm::length(&glm::Vec2::from(&game.ball_v)),
+            _ => 0.0,
+        });
+        for event in events.iter() {
+            match event.kind {
+                game::EventKind::WallImpact { impulse, .. } => mixer.play_impact(impulse),
+                game::EventKind::EdgeHit { impulse, .. } => mixer.play_edge_hit(impulse),
+                game::EventKind::Fell { .. } => mixer.play_drop(),
+                game::EventKind::Goal => mixer.play_jingle(),
+                _ => (),
+            }
+        }
+    }
+}
+
+// Open the default output device with a stream rendering from a new Mixer
+fn open_output() -> Option<(Arc<Mutex<Mixer>>, cpal::Stream)> {
+    let device = cpal::default_host().default_output_device()?;
+    let supported_config = device.default_output_config().ok()?;
+    let sample_format = supported_config.sample_format();
+    let config: cpal::StreamConfig = supported_config.into();
+    let mixer = Arc::new(Mutex::new(Mixer::new(config.sample_rate.0)));
+    let stream = match sample_format {
+        cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, &mixer),
+        cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, &mixer),
+        cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, &mixer),
+    }?;
+    stream.play().ok()?;
+    Some((mixer, stream))
+}
+
+fn build_stream<T: cpal::Sample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mixer: &Arc<Mutex<Mixer>>,
+) -> Option<cpal::Stream> {
+    let mixer = Arc::clone(mixer);
+    let channels = config.channels as usize;
+    let mut buffer = Vec::new();
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                buffer.resize(data.len(), 0.0);
+                mixer.lock().unwrap().render(&mut buffer, channels);
+                for (out, sample) in data.iter_mut().zip(buffer.iter()) {
+                    *out = cpal::Sample::from(sample);
+                }
+            },
+            |e| println!("Audio stream error: {}", e),
+        )
+        .map_err(|e| println!("Failed to open audio stream: {}", e))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    // Render the given number of seconds of stereo output
+    fn render(mixer: &mut Mixer, seconds: f32) -> Vec<f32> {
+        let mut out = vec![0.0; 2 * (seconds * SAMPLE_RATE as f32) as usize];
+        mixer.render(&mut out, 2);
+        out
+    }
+
+    #[test]
+    fn silent_at_zero_volume_without_rolling() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        mixer.set_volumes(0.0, 0.0);
+        mixer.set_rolling_speed(0.0);
+        assert!(render(&mut mixer, 0.5).iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn impact_is_audible_and_bounded() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        mixer.play_impact(IMPACT_FULL_IMPULSE);
+        let out = render(&mut mixer, 0.1);
+        assert!(out.iter().any(|s| s.abs() > 0.1));
+        assert!(out.iter().all(|s| s.abs() <= 1.0));
+    }
+
+    #[test]
+    fn voices_are_retired_after_decaying() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        mixer.play_impact(IMPACT_FULL_IMPULSE);
+        mixer.play_drop();
+        mixer.play_jingle();
+        render(&mut mixer, 0.01);
+        assert!(!mixer.voices.is_empty());
+        render(&mut mixer, 5.0);
+        assert!(mixer.voices.is_empty());
+    }
+
+    #[test]
+    fn channels_are_identical() {
+        let mut mixer = Mixer::new(SAMPLE_RATE);
+        mixer.set_rolling_speed(ROLLING_FULL_SPEED);
+        mixer.play_edge_hit(IMPACT_FULL_IMPULSE);
+        let out = render(&mut mixer, 0.1);
+        assert!(out.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+}
@@ -9,6 +9,10 @@ layout(location=0) out vec4 outputColor;
 layout(set=0, binding=0) uniform SceneUniforms {
     mat4 viewProjection;
     uvec4 numLights;
+    vec4 cameraPosWorldSpace;
+    vec4 cameraRightWorldSpace;
+    vec4 cameraUpWorldSpace;
+    uvec4 renderMode; // high contrast mode enabled
 };
 
 const int MAX_LIGHTS = 4;
@@ -25,15 +29,21 @@ layout(set=0, binding=1) uniform LightUniforms {
 layout(set=1, binding=0) uniform texture2DArray shadowMaps;
 layout(set=1, binding=1) uniform samplerShadow shadowMapSampler;
 
-layout(set=3, binding=0) uniform texture2D objectTexture;
-layout(set=3, binding=1) uniform sampler objectTextureSampler;
+layout(set=2, binding=0) uniform MaterialUniforms {
+    vec4 baseColor;
+    vec4 materialParams; // specular, roughness, alpha cutoff, normal map enabled
+    vec4 highContrastColor; // negative alpha if not set
+};
+layout(set=2, binding=1) uniform texture2D objectTexture;
+layout(set=2, binding=2) uniform sampler objectTextureSampler;
+layout(set=2, binding=3) uniform texture2D normalMap;
+layout(set=2, binding=4) uniform sampler normalMapSampler;
 
-// "Shadow coefficient": 0 (completely in shadow) ... 1 (completely in light)
-float shadowCoeff(int lightId, vec4 posLightSpaceProjected)
+// "Light visibility": 0 (completely in shadow) ... 1 (completely in light)
+float lightVisibility(int lightId, vec4 posLightSpaceProjected)
 {
     vec3 posLightSpaceNdc = posLightSpaceProjected.xyz / posLightSpaceProjected.w;
-    const float DARKNESS_COEFF = 0.25;
-    return 1.0 - DARKNESS_COEFF + DARKNESS_COEFF * texture(sampler2DArrayShadow(shadowMaps, shadowMapSampler),
+    return texture(sampler2DArrayShadow(shadowMaps, shadowMapSampler),
         vec4(
             // Transform NDC coordinates to texture UV coordinates
             0.5 * posLightSpaceNdc.x + 0.5,
@@ -46,19 +56,61 @@ float shadowCoeff(int lightId, vec4 posLightSpaceProjected)
     );
 }
 
+// Perturb the surface normal based on the normal map. The tangent space basis is derived from
+// screen space derivatives of the position and texture coordinates, so no tangents are needed
+// in the vertex data.
+vec3 perturbNormal(vec3 normal)
+{
+    vec3 dp1 = dFdx(fragPosWorldSpace);
+    vec3 dp2 = dFdy(fragPosWorldSpace);
+    vec2 duv1 = dFdx(fragTexCoords);
+    vec2 duv2 = dFdy(fragTexCoords);
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+    float invMax = inversesqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 1e-12));
+    mat3 tbn = mat3(tangent * invMax, bitangent * invMax, normal);
+    vec3 mapped = 2.0 * texture(sampler2D(normalMap, normalMapSampler), fragTexCoords).xyz - 1.0;
+    return normalize(tbn * mapped);
+}
+
 void main()
 {
     vec3 normal = normalize(fragNormalWorldSpace);
-    
+    if (materialParams.w > 0.5) {
+        normal = perturbNormal(normal);
+    }
+    vec3 viewDir = normalize(cameraPosWorldSpace.xyz - fragPosWorldSpace);
+
+    // Blinn-Phong specular exponent approximating the given roughness
+    float roughness = clamp(materialParams.y, 0.05, 1.0);
+    float shininess = 2.0 / pow(roughness, 4.0) - 2.0;
+
+    const float DARKNESS_COEFF = 0.25;
     float luminance = 0.15;
+    float specular = 0.0;
     for (int i = 0; i < numLights.x; ++i) {
         Light light = lights[i];
         vec3 lightDir = normalize(light.posWorldSpace.xyz - fragPosWorldSpace);
         float diffuse = max(0.0, dot(normal, lightDir));
-        float shadow = shadowCoeff(i, light.viewProjection * vec4(fragPosWorldSpace, 1.0));
-        luminance += shadow * diffuse / numLights.x;
+        float visibility = lightVisibility(i, light.viewProjection * vec4(fragPosWorldSpace, 1.0));
+        luminance += (1.0 - DARKNESS_COEFF + DARKNESS_COEFF * visibility) * diffuse / numLights.x;
+        if (diffuse > 0.0) {
+            vec3 halfway = normalize(lightDir + viewDir);
+            specular += visibility * pow(max(0.0, dot(normal, halfway)), shininess);
+        }
     }
 
-    vec4 materialColor = texture(sampler2D(objectTexture, objectTextureSampler), fragTexCoords);
-    outputColor = vec4(luminance * materialColor.rgb, materialColor.a);
+    vec4 materialColor = baseColor * texture(sampler2D(objectTexture, objectTextureSampler), fragTexCoords);
+    if (renderMode.x != 0 && highContrastColor.a >= 0.0) {
+        // Flat colors with only mild shading, so that the surfaces are told apart by color alone
+        materialColor = highContrastColor;
+        luminance = 0.7 + 0.3 * luminance;
+        specular = 0.0;
+    }
+    if (materialColor.a < materialParams.z) {
+        discard;
+    }
+    outputColor = vec4(luminance * materialColor.rgb + materialParams.x * specular, materialColor.a);
 }
@@ -4,6 +4,10 @@ layout(location=0) in vec3 position;
 layout(location=1) in vec3 normal;
 layout(location=2) in vec2 texCoords;
 
+// Per-instance model matrices
+layout(location=3) in mat4 model;
+layout(location=7) in mat4 normalModel;
+
 layout(location=0) out vec3 fragPosWorldSpace;
 layout(location=1) out vec3 fragNormalWorldSpace;
 layout(location=2) out vec2 fragTexCoords;
@@ -11,11 +15,7 @@ layout(location=2) out vec2 fragTexCoords;
 layout(set=0, binding=0) uniform SceneUniforms {
     mat4 viewProjection;
     uvec4 numLights;
-};
-
-layout(set=2, binding=0) uniform ObjectUniforms {
-    mat4 model;
-    mat4 normalModel;
+    vec4 cameraPosWorldSpace;
 };
 
 void main()
@@ -0,0 +1,545 @@
+use nalgebra_glm as glm;
+
+use crate::game;
+
+// Editing logic of the in-app level editor. Editor works in level coordinates and doesn't know
+// anything about the rendering or the UI: GameLoop converts the pointer positions and draws the
+// selection and the validation messages.
+
+const PICK_R: f32 = 0.5 * game::BALL_R; // how close to a point item a click selects it
+const HANDLE_R: f32 = 0.5 * game::BALL_R; // size of the resize handle at the bottom-right corner of rects
+const MIN_WALL_SIZE: f32 = 4.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Tool {
+    Select, // select, move and resize existing items
+    Wall,
+    Hole,
+    Path, // append points to the AI path
+}
+
+/// An editable item of the level
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Item {
+    Start,
+    End,
+    Wall(usize),
+    Hole(usize),
+    PathPoint(usize),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum DragKind {
+    Move(Item),
+    Resize(Item),
+    NewWall(game::Point), // the corner where the drag started
+}
+
+struct Drag {
+    kind: DragKind,
+    start: glm::Vec2, // pointer position when the drag started
+    before: game::Level,
+}
+
+pub struct Editor {
+    pub level: game::Level,
+    pub tool: Tool,
+    pub snap: bool,
+    pub grid: f32,
+    pub selection: Option<Item>,
+    drag: Option<Drag>,
+    undo_stack: Vec<game::Level>,
+    redo_stack: Vec<game::Level>,
+    changed: bool,
+}
+
+impl Editor {
+    pub fn new(level: &game::Level) -> Editor {
+        Editor {
+            level: level.clone(),
+            tool: Tool::Select,
+            snap: true,
+            grid: game::BALL_R / 2.0,
+            selection: None,
+            drag: None,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            changed: false,
+        }
+    }
+
+    /// Pointer pressed at level coordinates p
+    pub fn press(&mut self, p: glm::Vec2) {
+        let before = self.level.clone();
+        let kind = match self.tool {
+            Tool::Select => {
+                let handle = self
+                    .selection
+                    .filter(|item| self.is_on_resize_handle(*item, p));
+                match handle {
+                    Some(item) => DragKind::Resize(item),
+                    None => {
+                        self.selection = self.pick(p);
+                        match self.selection {
+                            Some(item) => DragKind::Move(item),
+                            None => return,
+                        }
+                    }
+                }
+            }
+            Tool::Wall => {
+                let corner = game::Point::from(&self.snap_point(p));
+                self.level.walls.push(game::Wall::new(game::Rect {
+                    pos: corner,
+                    size: game::Size { w: 0.0, h: 0.0 },
+                }));
+                self.selection = Some(Item::Wall(self.level.walls.len() - 1));
+                DragKind::NewWall(corner)
+            }
+            Tool::Hole => {
+                self.level
+                    .holes
+                    .push(game::Point::from(&self.snap_point(p)));
+                self.selection = Some(Item::Hole(self.level.holes.len() - 1));
+                DragKind::Move(Item::Hole(self.level.holes.len() - 1))
+            }
+            Tool::Path => {
+                self.level.path.push(game::Point::from(&self.snap_point(p)));
+                self.selection = Some(Item::PathPoint(self.level.path.len() - 1));
+                DragKind::Move(Item::PathPoint(self.level.path.len() - 1))
+            }
+        };
+        self.drag = Some(Drag {
+            kind,
+            start: p,
+            before,
+        });
+    }
+
+    /// Pointer moved to level coordinates p
+    pub fn drag_to(&mut self, p: glm::Vec2) {
+        let drag = match &self.drag {
+            Some(d) => d,
+            None => return,
+        };
+        let delta = p - drag.start;
+        match drag.kind {
+            DragKind::Move(item) => {
+                let pos = self.snap_point(glm::Vec2::from(&item_pos(&drag.before, item)) + delta);
+                set_item_pos(&mut self.level, item, game::Point::from(&pos));
+            }
+            DragKind::Resize(item) => {
+                let rect = match item {
+                    Item::Wall(i) => drag.before.walls[i].rect,
+                    Item::End => drag.before.end,
+                    _ => return,
+                };
+                let corner = glm::vec2(
+                    rect.pos.x + rect.size.w + delta.x,
+                    rect.pos.y + rect.size.h + delta.y,
+                );
+                let corner = self.snap_point(corner);
+                let size = game::Size {
+                    w: (corner.x - rect.pos.x).max(MIN_WALL_SIZE),
+                    h: (corner.y - rect.pos.y).max(MIN_WALL_SIZE),
+                };
+                match item {
+                    Item::Wall(i) => self.level.walls[i].rect.size = size,
+                    _ => self.level.end.size = size,
+                }
+            }
+            DragKind::NewWall(corner) => {
+                let p = self.snap_point(p);
+                let i = self.level.walls.len() - 1;
+                self.level.walls[i].rect = game::Rect {
+                    pos: game::Point {
+                        x: corner.x.min(p.x),
+                        y: corner.y.min(p.y),
+                    },
+                    size: game::Size {
+                        w: (p.x - corner.x).abs(),
+                        h: (p.y - corner.y).abs(),
+                    },
+                };
+            }
+        }
+        self.changed = true;
+    }
+
+    /// Pointer released, finishing the current edit
+    pub fn release(&mut self) {
+        let drag = match self.drag.take() {
+            Some(d) => d,
+            None => return,
+        };
+        if let DragKind::NewWall(_) = drag.kind {
+            // A click without dragging doesn't create a wall
+            let size = self.level.walls.last().unwrap().rect.size;
+            if size.w < MIN_WALL_SIZE || size.h < MIN_WALL_SIZE {
+                self.level.walls.pop();
+                self.selection = None;
+            }
+        }
+        if drag.before != self.level {
+            self.undo_stack.push(drag.before);
+            self.redo_stack.clear();
+            self.changed = true;
+        }
+    }
+
+    /// Remove the selected item. Start and end can't be removed.
+    pub fn delete_selected(&mut self) {
+        let before = self.level.clone();
+        match self.selection {
+            Some(Item::Wall(i)) => {
+                self.level.walls.remove(i);
+            }
+            Some(Item::Hole(i)) => {
+                self.level.holes.remove(i);
+            }
+            Some(Item::PathPoint(i)) => {
+                self.level.path.remove(i);
+            }
+            Some(Item::Start) | Some(Item::End) | None => return,
+        }
+        self.selection = None;
+        self.undo_stack.push(before);
+        self.redo_stack.clear();
+        self.changed = true;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(level) = self.undo_stack.pop() {
+            self.redo_stack
+                .push(std::mem::replace(&mut self.level, level));
+            self.selection = None;
+            self.changed = true;
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(level) = self.redo_stack.pop() {
+            self.undo_stack
+                .push(std::mem::replace(&mut self.level, level));
+            self.selection = None;
+            self.changed = true;
+        }
+    }
+
+    /// Returns true if the level has changed since the previous call, i.e. its scene needs updating
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+
+    /// Returns true while an item is being dragged
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Check the level for problems that make it unplayable, and return their descriptions
+    pub fn validate(&self) -> Vec<String> {
+        let level = &self.level;
+        let mut problems = Vec::new();
+        let board = game::Rect {
+            pos: game::Point { x: 0.0, y: 0.0 },
+            size: level.size,
+        };
+        let inside_board = |p: &game::Point, r: f32| {
+            p.x >= r && p.y >= r && p.x <= level.size.w - r && p.y <= level.size.h - r
+        };
+        let ball_hits_wall = |p: &game::Point| {
+            level
+                .walls
+                .iter()
+                .position(|w| circle_overlaps_rect(p, game::BALL_R, &w.rect))
+        };
+        if !inside_board(&level.start, game::BALL_R) {
+            problems.push("The start is outside the board".to_string());
+        }
+        if let Some(i) = ball_hits_wall(&level.start) {
+            problems.push(format!("The ball at the start overlaps wall {}", i));
+        }
+        if !rect_inside(&level.end, &board) {
+            problems.push("The goal is outside the board".to_string());
+        }
+        for (i, wall) in level.walls.iter().enumerate() {
+            if !rect_inside(&wall.rect, &board) {
+                problems.push(format!("Wall {} extends outside the board", i));
+            }
+        }
+        for (i, hole) in level.holes.iter().enumerate() {
+            if !inside_board(hole, 0.0) {
+                problems.push(format!("Hole {} is outside the board", i));
+            }
+            if glm::distance(&glm::Vec2::from(hole), &glm::Vec2::from(&level.start))
+                < game::HOLE_R + game::BALL_R
+            {
+                problems.push(format!("Hole {} is at the start", i));
+            }
+            if circle_overlaps_rect(hole, game::HOLE_R, &level.end) {
+                problems.push(format!("Hole {} overlaps the goal", i));
+            }
+        }
+        if level.path.len() < 2 {
+            problems.push("The AI path needs at least 2 points".to_string());
+        } else if !level
+            .end
+            .contains(glm::Vec2::from(level.path.last().unwrap()))
+        {
+            problems.push("The AI path doesn't end in the goal".to_string());
+        }
+        for (i, p) in level.path.iter().enumerate() {
+            let p = glm::Vec2::from(p);
+            if let Some(w) = level.walls.iter().position(|w| w.rect.contains(p)) {
+                problems.push(format!("AI path point {} is inside wall {}", i, w));
+            }
+        }
+        problems
+    }
+
+    /// The top-left and bottom-right corners of the selection, and whether it can be resized
+    pub fn selection_bounds(&self) -> Option<(glm::Vec2, glm::Vec2, bool)> {
+        let point = |p: &game::Point, r: f32| {
+            let p = glm::Vec2::from(p);
+            (p - glm::vec2(r, r), p + glm::vec2(r, r), false)
+        };
+        let rect = |r: &game::Rect| {
+            let pos = glm::Vec2::from(&r.pos);
+            (pos, pos + glm::vec2(r.size.w, r.size.h), true)
+        };
+        Some(match self.selection? {
+            Item::Start => point(&self.level.start, game::BALL_R),
+            Item::End => rect(&self.level.end),
+            Item::Wall(i) => rect(&self.level.walls[i].rect),
+            Item::Hole(i) => point(&self.level.holes[i], game::HOLE_R),
+            Item::PathPoint(i) => point(&self.level.path[i], PICK_R),
+        })
+    }
+
+    // The topmost item at p. Point items are picked before the rects they may be on top of.
+    fn pick(&self, p: glm::Vec2) -> Option<Item> {
+        let near = |q: &game::Point, r: f32| glm::distance(&glm::Vec2::from(q), &p) <= r;
+        let level = &self.level;
+        // The path usually begins at the start position, so the start wins
+        if near(&level.start, game::BALL_R) {
+            return Some(Item::Start);
+        }
+        if let Some(i) = level.path.iter().rposition(|q| near(q, PICK_R)) {
+            return Some(Item::PathPoint(i));
+        }
+        if let Some(i) = level.holes.iter().rposition(|q| near(q, game::HOLE_R)) {
+            return Some(Item::Hole(i));
+        }
+        if level.end.contains(p) {
+            return Some(Item::End);
+        }
+        level
+            .walls
+            .iter()
+            .rposition(|w| w.rect.contains(p))
+            .map(Item::Wall)
+    }
+
+    fn is_on_resize_handle(&self, item: Item, p: glm::Vec2) -> bool {
+        match self.selection_bounds() {
+            Some((_, corner, true)) if self.selection == Some(item) => {
+                (p.x - corner.x).abs() <= HANDLE_R && (p.y - corner.y).abs() <= HANDLE_R
+            }
+            _ => false,
+        }
+    }
+
+    fn snap_point(&self, p: glm::Vec2) -> glm::Vec2 {
+        if self.snap {
+            (p / self.grid).map(|c| c.round()) * self.grid
+        } else {
+            p
+        }
+    }
+}
+
+fn item_pos(level: &game::Level, item: Item) -> game::Point {
+    match item {
+        Item::Start => level.start,
+        Item::End => level.end.pos,
+        Item::Wall(i) => level.walls[i].rect.pos,
+        Item::Hole(i) => level.holes[i],
+        Item::PathPoint(i) => level.path[i],
+    }
+}
+
+fn set_item_pos(level: &mut game::Level, item: Item, pos: game::Point) {
+    match item {
+        Item::Start => level.start = pos,
+        Item::End => level.end.pos = pos,
+        Item::Wall(i) => level.walls[i].rect.pos = pos,
+        Item::Hole(i) => level.holes[i] = pos,
+        Item::PathPoint(i) => level.path[i] = pos,
+    }
+}
+
+fn rect_inside(inner: &game::Rect, outer: &game::Rect) -> bool {
+    inner.pos.x >= outer.pos.x
+        && inner.pos.y >= outer.pos.y
+        && inner.pos.x + inner.size.w <= outer.pos.x + outer.size.w
+        && inner.pos.y + inner.size.h <= outer.pos.y + outer.size.h
+}
+
+fn circle_overlaps_rect(center: &game::Point, r: f32, rect: &game::Rect) -> bool {
+    let c = glm::Vec2::from(center);
+    let min = glm::Vec2::from(&rect.pos);
+    let closest = glm::clamp_vec(&c, &min, &(min + glm::vec2(rect.size.w, rect.size.h)));
+    glm::distance(&c, &closest) < r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A valid 400x400 level with a wall, a hole and a straight AI path to the goal
+    fn level() -> game::Level {
+        game::Level::from_json(
+            r#"{
+                "name": "Test",
+                "size": { "w": 400, "h": 400 },
+                "start": { "x": 50, "y": 50 },
+                "end": { "pos": { "x": 340, "y": 340 }, "size": { "w": 40, "h": 40 } },
+                "walls": [{ "pos": { "x": 150, "y": 0 }, "size": { "w": 20, "h": 200 } }],
+                "holes": [{ "x": 300, "y": 100 }],
+                "path": [{ "x": 50, "y": 50 }, { "x": 360, "y": 360 }]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn click(editor: &mut Editor, from: (f32, f32), to: (f32, f32)) {
+        editor.press(glm::vec2(from.0, from.1));
+        editor.drag_to(glm::vec2(to.0, to.1));
+        editor.release();
+    }
+
+    fn rect(x: f32, y: f32, w: f32, h: f32) -> game::Rect {
+        game::Rect {
+            pos: game::Point { x, y },
+            size: game::Size { w, h },
+        }
+    }
+
+    #[test]
+    fn new_wall_undo_redo() {
+        let mut editor = Editor::new(&level());
+        editor.tool = Tool::Wall;
+        click(&mut editor, (200.0, 300.0), (100.0, 250.0));
+        assert_eq!(editor.level.walls.len(), 2);
+        assert_eq!(editor.level.walls[1].rect, rect(100.0, 250.0, 100.0, 50.0));
+        assert!(editor.can_undo() && !editor.can_redo());
+
+        editor.undo();
+        assert_eq!(editor.level, level());
+        assert!(!editor.can_undo() && editor.can_redo());
+
+        editor.redo();
+        assert_eq!(editor.level.walls[1].rect, rect(100.0, 250.0, 100.0, 50.0));
+        assert!(editor.can_undo() && !editor.can_redo());
+    }
+
+    #[test]
+    fn click_without_drag_adds_no_wall() {
+        let mut editor = Editor::new(&level());
+        editor.tool = Tool::Wall;
+        click(&mut editor, (200.0, 300.0), (201.0, 300.0));
+        assert_eq!(editor.level, level());
+        assert_eq!(editor.selection, None);
+        assert!(!editor.can_undo());
+    }
+
+    #[test]
+    fn move_and_resize_with_undo() {
+        let mut editor = Editor::new(&level());
+        click(&mut editor, (300.0, 100.0), (250.0, 120.0));
+        assert_eq!(editor.selection, Some(Item::Hole(0)));
+        assert_eq!(editor.level.holes[0], game::Point { x: 250.0, y: 120.0 });
+
+        // Dragging the bottom-right corner of the selected wall resizes it
+        click(&mut editor, (160.0, 100.0), (160.0, 100.0));
+        assert_eq!(editor.selection, Some(Item::Wall(0)));
+        click(&mut editor, (170.0, 200.0), (190.0, 150.0));
+        assert_eq!(editor.level.walls[0].rect, rect(150.0, 0.0, 40.0, 150.0));
+
+        editor.undo();
+        assert_eq!(editor.level.walls[0].rect, rect(150.0, 0.0, 20.0, 200.0));
+        editor.undo();
+        assert_eq!(editor.level, level());
+        assert!(!editor.can_undo());
+    }
+
+    #[test]
+    fn delete_and_undo() {
+        let mut editor = Editor::new(&level());
+        click(&mut editor, (300.0, 100.0), (300.0, 100.0));
+        editor.delete_selected();
+        assert!(editor.level.holes.is_empty());
+        editor.undo();
+        assert_eq!(editor.level, level());
+    }
+
+    // Validation problems of the test level after a change
+    fn problems(change: impl Fn(&mut game::Level)) -> Vec<String> {
+        let mut level = level();
+        change(&mut level);
+        Editor::new(&level).validate()
+    }
+
+    #[test]
+    fn validation_messages() {
+        assert!(problems(|_| ()).is_empty());
+        assert_eq!(
+            problems(|l| l.start = game::Point { x: 5.0, y: 50.0 }),
+            ["The start is outside the board"]
+        );
+        assert_eq!(
+            problems(|l| l.start = game::Point { x: 140.0, y: 50.0 }),
+            ["The ball at the start overlaps wall 0"]
+        );
+        assert_eq!(
+            problems(|l| l.end.size.w = 70.0),
+            ["The goal is outside the board"]
+        );
+        assert_eq!(
+            problems(|l| l.walls[0].rect.pos.y = 300.0),
+            ["Wall 0 extends outside the board"]
+        );
+        assert_eq!(
+            problems(|l| l.holes[0] = game::Point { x: 300.0, y: 410.0 }),
+            ["Hole 0 is outside the board"]
+        );
+        assert_eq!(
+            problems(|l| l.holes[0] = game::Point { x: 60.0, y: 60.0 }),
+            ["Hole 0 is at the start"]
+        );
+        assert_eq!(
+            problems(|l| l.holes[0] = game::Point { x: 330.0, y: 330.0 }),
+            ["Hole 0 overlaps the goal"]
+        );
+        assert_eq!(
+            problems(|l| l.path.truncate(1)),
+            ["The AI path needs at least 2 points"]
+        );
+        assert_eq!(
+            problems(|l| l.path[1] = game::Point { x: 300.0, y: 300.0 }),
+            ["The AI path doesn't end in the goal"]
+        );
+        assert_eq!(
+            problems(|l| l.path.insert(1, game::Point { x: 160.0, y: 100.0 })),
+            ["AI path point 1 is inside wall 0"]
+        );
+    }
+}
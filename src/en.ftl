@@ -0,0 +1,69 @@
+# UI strings in English. This is also the fallback for messages missing from other languages.
+
+## Main menu
+
+game-title = Labyrinth
+play = Play
+edit-level = Edit level
+quit = Quit
+
+## Level intro
+
+level-author = by { $author }
+level-difficulty = Difficulty: { $difficulty }
+difficulty-easy = Easy
+difficulty-medium = Medium
+difficulty-hard = Hard
+difficulty-expert = Expert
+par-time = Par time: { $time }
+click-to-start = Click to start
+
+## HUD
+
+lives = Lives: { $lives }
+items = Items: { $collected }/{ $total }
+split = Split { $waypoint }/{ $waypoints }: { $time }
+
+## Pause menu
+
+game-paused = Game paused
+resume = Resume
+restart = Restart
+main-menu = Main menu
+
+## Results
+
+game-won = You made it through!
+game-lost = Game over
+your-time = Your time: { $time }
+collected = Collected: { $collected }/{ $total }
+score = Score: { $points }
+no-balls-left = Oops... no balls left
+play-again = Play again
+try-again = Try again
+
+## Settings
+
+master-volume = Master volume
+effects-volume = Effects volume
+vibration = Vibration
+language = Language
+accessibility = Accessibility
+high-contrast = High contrast
+reduced-motion = Reduced motion
+large-ui = Large UI
+game-speed = Game speed
+
+## Level editor
+
+level-editor = Level editor
+tool-select = Select
+tool-wall = Wall
+tool-hole = Hole
+tool-path = Path
+snap-to-grid = Snap to grid
+undo = Undo
+redo = Redo
+delete = Delete
+save = Save
+level-playable = The level is playable
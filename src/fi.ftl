@@ -0,0 +1,69 @@
+# UI strings in Finnish
+
+## Main menu
+
+game-title = Labyrintti
+play = Pelaa
+edit-level = Muokkaa tasoa
+quit = Lopeta
+
+## Level intro
+
+level-author = Tekijä: { $author }
+level-difficulty = Vaikeus: { $difficulty }
+difficulty-easy = Helppo
+difficulty-medium = Keskitaso
+difficulty-hard = Vaikea
+difficulty-expert = Erittäin vaikea
+par-time = Tavoiteaika: { $time }
+click-to-start = Aloita napauttamalla
+
+## HUD
+
+lives = Palloja: { $lives }
+items = Esineet: { $collected }/{ $total }
+split = Väliaika { $waypoint }/{ $waypoints }: { $time }
+
+## Pause menu
+
+game-paused = Peli tauolla
+resume = Jatka
+restart = Aloita alusta
+main-menu = Päävalikko
+
+## Results
+
+game-won = Selvisit läpi!
+game-lost = Peli päättyi
+your-time = Aikasi: { $time }
+collected = Kerätty: { $collected }/{ $total }
+score = Pisteet: { $points }
+no-balls-left = Hups... pallot loppuivat
+play-again = Pelaa uudelleen
+try-again = Yritä uudelleen
+
+## Settings
+
+master-volume = Päävoimakkuus
+effects-volume = Tehosteiden voimakkuus
+vibration = Värinä
+language = Kieli
+accessibility = Saavutettavuus
+high-contrast = Suuri kontrasti
+reduced-motion = Vähennetty liike
+large-ui = Suuri käyttöliittymä
+game-speed = Pelin nopeus
+
+## Level editor
+
+level-editor = Tasoeditori
+tool-select = Valitse
+tool-wall = Seinä
+tool-hole = Reikä
+tool-path = Reitti
+snap-to-grid = Kohdista ruudukkoon
+undo = Kumoa
+redo = Tee uudelleen
+delete = Poista
+save = Tallenna
+level-playable = Taso on pelattavissa
@@ -0,0 +1,196 @@
+use instant::Instant;
+
+// The application flow: which screen is shown and how the game gets from one to another. The
+// transitions only depend on the current state, the event and the time, so GameLoop translates
+// window events and UI actions to flow events, and does the side effects of entering and leaving
+// the states.
+
+const INTRO_DURATION: f32 = 3.0; // seconds the level intro is shown unless skipped
+const COUNTDOWN_DURATION: f32 = 3.0;
+const LEVEL_OUT_DURATION: f32 = 1.0; // the board levels out during the beginning of the countdown
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum State {
+    MainMenu,
+    /// The level name and details before the first countdown
+    LevelIntro {
+        start: Instant,
+    },
+    /// 3-2-1 before the game starts or continues. The board levels out, but the game doesn't
+    /// advance.
+    Countdown {
+        start: Instant,
+    },
+    Playing,
+    Paused,
+    /// The game has been won or lost
+    Results,
+    Editing,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    /// Play the level from the start, from the main menu or the editor
+    Play,
+    /// Skip the level intro
+    Skip,
+    Pause,
+    Resume,
+    /// Play the level again from the start, from the pause menu or the results screen
+    Restart,
+    /// The game was won or lost
+    Finished,
+    Edit,
+    MainMenu,
+    /// Time has passed, ends the timed states
+    Tick,
+}
+
+impl State {
+    /// The state after event at time now. Events that don't apply to the state are ignored.
+    pub fn next(self, event: Event, now: Instant) -> State {
+        let elapsed = |start: Instant| now.duration_since(start).as_secs_f32();
+        match (self, event) {
+            (State::MainMenu, Event::Play) => State::LevelIntro { start: now },
+            (State::LevelIntro { .. }, Event::Skip) => State::Countdown { start: now },
+            (State::LevelIntro { start }, Event::Tick) if elapsed(start) >= INTRO_DURATION => {
+                State::Countdown { start: now }
+            }
+            (State::Countdown { start }, Event::Tick) if elapsed(start) >= COUNTDOWN_DURATION => {
+                State::Playing
+            }
+            (State::Countdown { .. }, Event::Pause) | (State::Playing, Event::Pause) => {
+                State::Paused
+            }
+            (State::Playing, Event::Finished) => State::Results,
+            (State::Paused, Event::Resume)
+            | (State::Paused, Event::Restart)
+            | (State::Results, Event::Restart)
+            | (State::Editing, Event::Play) => State::Countdown { start: now },
+            (State::MainMenu, Event::Edit)
+            | (State::Paused, Event::Edit)
+            | (State::Results, Event::Edit) => State::Editing,
+            (State::Paused, Event::MainMenu) | (State::Results, Event::MainMenu) => State::MainMenu,
+            (state, _) => state,
+        }
+    }
+
+    /// Number shown in the countdown at time now
+    pub fn countdown_number(&self, now: Instant) -> Option<u32> {
+        match self {
+            State::Countdown { start } => {
+                let remaining = COUNTDOWN_DURATION - now.duration_since(*start).as_secs_f32();
+                Some(remaining.ceil().max(1.0) as u32)
+            }
+            _ => None,
+        }
+    }
+
+    /// How much the board has leveled out at time now during the countdown, 0...1. Eases in
+    /// and out.
+    pub fn level_out(&self, now: Instant) -> Option<f32> {
+        match self {
+            State::Countdown { start } => {
+                let t = (now.duration_since(*start).as_secs_f32() / LEVEL_OUT_DURATION).min(1.0);
+                Some(t * t * (3.0 - 2.0 * t))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if the scene is animated in the state even without any game events
+    pub fn is_animated(&self) -> bool {
+        match self {
+            State::LevelIntro { .. } | State::Countdown { .. } | State::Playing => true,
+            State::MainMenu | State::Paused | State::Results | State::Editing => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn after(start: Instant, seconds: f32) -> Instant {
+        start + Duration::from_secs_f32(seconds)
+    }
+
+    #[test]
+    fn main_menu_to_playing() {
+        let t0 = Instant::now();
+        let intro = State::MainMenu.next(Event::Play, t0);
+        assert_eq!(intro, State::LevelIntro { start: t0 });
+        assert_eq!(intro.next(Event::Tick, after(t0, 1.0)), intro);
+        let t1 = after(t0, INTRO_DURATION);
+        let countdown = intro.next(Event::Tick, t1);
+        assert_eq!(countdown, State::Countdown { start: t1 });
+        assert_eq!(countdown.next(Event::Tick, after(t1, 1.0)), countdown);
+        assert_eq!(
+            countdown.next(Event::Tick, after(t1, COUNTDOWN_DURATION)),
+            State::Playing
+        );
+    }
+
+    #[test]
+    fn skipping_the_intro_starts_the_countdown() {
+        let t0 = Instant::now();
+        let t1 = after(t0, 0.5);
+        assert_eq!(
+            State::LevelIntro { start: t0 }.next(Event::Skip, t1),
+            State::Countdown { start: t1 }
+        );
+    }
+
+    #[test]
+    fn pause_from_countdown_and_playing() {
+        let t0 = Instant::now();
+        assert_eq!(
+            State::Countdown { start: t0 }.next(Event::Pause, t0),
+            State::Paused
+        );
+        assert_eq!(State::Playing.next(Event::Pause, t0), State::Paused);
+    }
+
+    #[test]
+    fn resume_and_restart_count_down_again() {
+        let t0 = Instant::now();
+        for event in [Event::Resume, Event::Restart].iter() {
+            assert_eq!(
+                State::Paused.next(*event, t0),
+                State::Countdown { start: t0 }
+            );
+        }
+        assert_eq!(
+            State::Results.next(Event::Restart, t0),
+            State::Countdown { start: t0 }
+        );
+    }
+
+    #[test]
+    fn finished_game_shows_results() {
+        assert_eq!(
+            State::Playing.next(Event::Finished, Instant::now()),
+            State::Results
+        );
+    }
+
+    #[test]
+    fn ignored_events_keep_the_state() {
+        let t0 = Instant::now();
+        let ignored = [
+            (State::MainMenu, Event::Pause),
+            (State::MainMenu, Event::Tick),
+            (State::LevelIntro { start: t0 }, Event::Pause),
+            (State::Countdown { start: t0 }, Event::Finished),
+            (State::Playing, Event::Play),
+            (State::Playing, Event::Tick),
+            (State::Paused, Event::Tick),
+            (State::Results, Event::Pause),
+            (State::Editing, Event::MainMenu),
+        ];
+        for (state, event) in ignored.iter() {
+            assert_eq!(state.next(*event, after(t0, 10.0)), *state);
+        }
+    }
+}
@@ -1,25 +1,89 @@
 use instant::Instant;
 use nalgebra_glm as glm;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::f32::consts::PI;
+use std::fmt;
 
 pub const BALL_R: f32 = 20.0;
 pub const HOLE_R: f32 = 1.2 * BALL_R;
 pub const MAX_ANGLE: f32 = PI / 32.0;
 const ACCEL_COEFF: f32 = 300.0 * BALL_R / PI;
-const BOUNCE_COEFF: f32 = 0.2;
+const BOUNCE_COEFF: f32 = 0.2; // restitution of ordinary walls
+const ROLLING_RESISTANCE: f32 = 0.5 * BALL_R; // deceleration on the plain board surface
 
+// Constants of the realistic physics model
+const UNITS_PER_METER: f32 = BALL_R / 0.006; // the ball of a real labyrinth game has a 6 mm radius
+const GRAVITY: f32 = 9.81 * UNITS_PER_METER;
+const ROLLING_INERTIA_FACTOR: f32 = 5.0 / 7.0; // m / (m + I / r^2) for a solid sphere, I = 2/5 m r^2
+const SLIDING_FRICTION: f32 = 0.2; // between the ball and the plain board surface
+const WALL_FRICTION: f32 = 0.3;
+const MIN_IMPACT_IMPULSE: f32 = 0.1 * BALL_R; // weaker collisions, e.g. resting against a wall, aren't events
+const NEAR_MISS_R: f32 = HOLE_R + BALL_R; // ball center closer than this to a hole center is a near miss
+const MAX_ROLLING_SLIP: f32 = 1.0; // contact point speed below which the ball is considered rolling
+const MIN_WALL_DISTANCE: f32 = 1e-3; // ball center closer than this to a wall is inside it
+const MAX_STEP_MOTION: f32 = 0.25 * BALL_R; // obstacles and the ball move at most this much per physics step
+const MAX_PHYSICS_STEPS: u32 = 100; // per update
+const RESPAWN_DELAY: f32 = 1.0; // seconds from falling in a hole to respawning at a checkpoint
+const WAYPOINT_R: f32 = 3.0 * BALL_R; // ball center closer than this to a path point passes it
+const WAYPOINT_LOOKAHEAD: usize = 2; // path points that can be skipped, e.g. when cutting a corner
+pub const COLLECTIBLE_R: f32 = 0.5 * BALL_R;
+const POINTS_PER_SEC_UNDER_PAR: f32 = 10.0;
+
+/// How the ball motion is simulated
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PhysicsModel {
+    /// The ball moves like a point mass with tuned acceleration, its spin just follows the velocity
+    Arcade,
+    /// The ball is a solid sphere rolling under gravity. Its spin can slip against the board and
+    /// the walls, e.g. after a bounce the ball keeps spinning forward until board friction catches up.
+    Realistic,
+}
+
+/// Game rules that are not part of the level definition
 #[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// Number of balls available. Falling in a hole with the last one ends the game. Must be at least 1.
+    pub lives: u32,
+    /// Seconds added to the completion time for every ball lost in a hole
+    pub fall_penalty: f32,
+    pub physics: PhysicsModel,
+    /// Speed of the simulation relative to real time. Values below 1 slow the game down as an
+    /// assist.
+    pub time_scale: f32,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            lives: 3,
+            fall_penalty: 5.0,
+            physics: PhysicsModel::Arcade,
+            time_scale: 1.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Point {
     pub x: f32,
     pub y: f32,
 }
 
-impl From<&json::JsonValue> for Point {
-    fn from(json_val: &json::JsonValue) -> Point {
-        Point {
-            x: json_val["x"].as_f32().unwrap(),
-            y: json_val["y"].as_f32().unwrap(),
-        }
+impl TryFrom<&json::JsonValue> for Point {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Point, LevelError> {
+        Ok(Point {
+            x: number(json_val, "x")?,
+            y: number(json_val, "y")?,
+        })
+    }
+}
+
+impl From<&Point> for json::JsonValue {
+    fn from(p: &Point) -> json::JsonValue {
+        json::object! { x: p.x, y: p.y }
     }
 }
 
@@ -53,105 +117,1385 @@ impl From<&Velocity> for glm::Vec2 {
     }
 }
 
+/// Angular velocity in radians per second around the level x- and y-axes, and the z-axis that
+/// points into the board
 #[derive(Copy, Clone, Debug)]
+pub struct AngularVelocity {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl From<&glm::Vec3> for AngularVelocity {
+    fn from(w: &glm::Vec3) -> AngularVelocity {
+        AngularVelocity {
+            x: w.x,
+            y: w.y,
+            z: w.z,
+        }
+    }
+}
+
+impl From<&AngularVelocity> for glm::Vec3 {
+    fn from(w: &AngularVelocity) -> glm::Vec3 {
+        glm::vec3(w.x, w.y, w.z)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Size {
     pub w: f32,
     pub h: f32,
 }
 
-impl From<&json::JsonValue> for Size {
-    fn from(json_val: &json::JsonValue) -> Size {
-        Size {
-            w: json_val["w"].as_f32().unwrap(),
-            h: json_val["h"].as_f32().unwrap(),
-        }
+impl TryFrom<&json::JsonValue> for Size {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Size, LevelError> {
+        Ok(Size {
+            w: positive_number(json_val, "w")?,
+            h: positive_number(json_val, "h")?,
+        })
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+impl From<&Size> for json::JsonValue {
+    fn from(size: &Size) -> json::JsonValue {
+        json::object! { w: size.w, h: size.h }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Rect {
     pub pos: Point, // top-left corner
     pub size: Size,
 }
 
-impl From<&json::JsonValue> for Rect {
-    fn from(json_val: &json::JsonValue) -> Rect {
-        Rect {
-            pos: Point::from(&json_val["pos"]),
-            size: Size::from(&json_val["size"]),
-        }
+impl TryFrom<&json::JsonValue> for Rect {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Rect, LevelError> {
+        Ok(Rect {
+            pos: Point::try_from(&json_val["pos"])?,
+            size: Size::try_from(&json_val["size"])?,
+        })
+    }
+}
+
+impl From<&Rect> for json::JsonValue {
+    fn from(rect: &Rect) -> json::JsonValue {
+        json::object! { pos: &rect.pos, size: &rect.size }
     }
 }
 
 impl Rect {
-    // Check if self contains Point p
-    fn contains(&self, p: glm::Vec2) -> bool {
+    /// Check if self contains Point p
+    pub fn contains(&self, p: glm::Vec2) -> bool {
         p.x >= self.pos.x
             && p.x < self.pos.x + self.size.w
             && p.y >= self.pos.y
             && p.y < self.pos.y + self.size.h
     }
+
+    fn center(&self) -> Point {
+        Point {
+            x: self.pos.x + self.size.w / 2.0,
+            y: self.pos.y + self.size.h / 2.0,
+        }
+    }
+
+    // Point inside self closest to p
+    fn closest_point(&self, p: glm::Vec2) -> glm::Vec2 {
+        let min = glm::Vec2::from(&self.pos);
+        glm::clamp_vec(&p, &min, &(min + glm::vec2(self.size.w, self.size.h)))
+    }
+
+    // Point on the edge of self closest to p inside self, and the outward normal there
+    fn nearest_surface(&self, p: glm::Vec2) -> (glm::Vec2, glm::Vec2) {
+        let min = glm::Vec2::from(&self.pos);
+        let max = min + glm::vec2(self.size.w, self.size.h);
+        nearest_face(
+            p,
+            &[
+                (p.x - min.x, glm::vec2(-1.0, 0.0)),
+                (max.x - p.x, glm::vec2(1.0, 0.0)),
+                (p.y - min.y, glm::vec2(0.0, -1.0)),
+                (max.y - p.y, glm::vec2(0.0, 1.0)),
+            ],
+        )
+    }
+}
+
+/// A rectangular wall. Walls with restitution greater than 1 are bumpers that speed the ball up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Wall {
+    pub rect: Rect,
+    pub restitution: f32,
+}
+
+impl TryFrom<&json::JsonValue> for Wall {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Wall, LevelError> {
+        Ok(Wall {
+            rect: Rect::try_from(json_val)?,
+            restitution: restitution_from_json(json_val),
+        })
+    }
+}
+
+impl From<&Wall> for json::JsonValue {
+    fn from(wall: &Wall) -> json::JsonValue {
+        let mut json_val = json::JsonValue::from(&wall.rect);
+        restitution_to_json(&mut json_val, wall.restitution);
+        json_val
+    }
+}
+
+impl Wall {
+    /// An ordinary wall, i.e. not a bumper
+    pub fn new(rect: Rect) -> Wall {
+        Wall {
+            rect,
+            restitution: BOUNCE_COEFF,
+        }
+    }
+}
+
+/// Returns true if an obstacle with the given restitution is a bumper
+pub fn is_bumper(restitution: f32) -> bool {
+    restitution > 1.0
+}
+
+// Obstacles have optional restitution in the level JSON
+fn restitution_from_json(json_val: &json::JsonValue) -> f32 {
+    json_val["restitution"].as_f32().unwrap_or(BOUNCE_COEFF)
+}
+
+// The restitution is left out of the JSON if it has the default value
+fn restitution_to_json(json_val: &mut json::JsonValue, restitution: f32) {
+    if restitution != BOUNCE_COEFF {
+        json_val["restitution"] = restitution.into();
+    }
+}
+
+/// A round post standing on the board
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Post {
+    pub pos: Point, // center
+    pub r: f32,
+    pub restitution: f32,
+}
+
+impl TryFrom<&json::JsonValue> for Post {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Post, LevelError> {
+        Ok(Post {
+            pos: Point::try_from(&json_val["pos"])?,
+            r: positive_number(json_val, "r")?,
+            restitution: restitution_from_json(json_val),
+        })
+    }
+}
+
+impl From<&Post> for json::JsonValue {
+    fn from(post: &Post) -> json::JsonValue {
+        let mut json_val = json::object! { pos: &post.pos, r: post.r };
+        restitution_to_json(&mut json_val, post.restitution);
+        json_val
+    }
+}
+
+impl Post {
+    // Point inside self closest to p
+    fn closest_point(&self, p: glm::Vec2) -> glm::Vec2 {
+        let center = glm::Vec2::from(&self.pos);
+        if glm::distance(&p, &center) <= self.r {
+            p
+        } else {
+            center + self.r * glm::normalize(&(p - center))
+        }
+    }
+
+    // Point on the edge of self closest to p inside self, and the outward normal there
+    fn nearest_surface(&self, p: glm::Vec2) -> (glm::Vec2, glm::Vec2) {
+        let center = glm::Vec2::from(&self.pos);
+        let n = if p != center {
+            glm::normalize(&(p - center))
+        } else {
+            glm::vec2(1.0, 0.0)
+        };
+        (center + self.r * n, n)
+    }
+}
+
+/// A straight wall of width w from a to b with flat ends, e.g. a diagonal deflector
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub a: Point,
+    pub b: Point,
+    pub w: f32,
+    pub restitution: f32,
+}
+
+impl TryFrom<&json::JsonValue> for Segment {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Segment, LevelError> {
+        let segment = Segment {
+            a: Point::try_from(&json_val["a"])?,
+            b: Point::try_from(&json_val["b"])?,
+            w: positive_number(json_val, "w")?,
+            restitution: restitution_from_json(json_val),
+        };
+        if segment.a == segment.b {
+            return Err(LevelError::InvalidValue(format!(
+                "Segment with the same start and end point in {}",
+                json_val
+            )));
+        }
+        Ok(segment)
+    }
+}
+
+impl From<&Segment> for json::JsonValue {
+    fn from(segment: &Segment) -> json::JsonValue {
+        let mut json_val = json::object! { a: &segment.a, b: &segment.b, w: segment.w };
+        restitution_to_json(&mut json_val, segment.restitution);
+        json_val
+    }
+}
+
+impl Segment {
+    // Point inside self closest to p
+    fn closest_point(&self, p: glm::Vec2) -> glm::Vec2 {
+        let a = glm::Vec2::from(&self.a);
+        let b = glm::Vec2::from(&self.b);
+        let len = glm::distance(&a, &b);
+        let along = (b - a) / len;
+        let across = glm::vec2(-along.y, along.x);
+        // Clamp in the coordinate system of the segment
+        let s = glm::clamp_scalar(glm::dot(&(p - a), &along), 0.0, len);
+        let t = glm::clamp_scalar(glm::dot(&(p - a), &across), -self.w / 2.0, self.w / 2.0);
+        a + s * along + t * across
+    }
+
+    // Point on the edge of self closest to p inside self, and the outward normal there
+    fn nearest_surface(&self, p: glm::Vec2) -> (glm::Vec2, glm::Vec2) {
+        let a = glm::Vec2::from(&self.a);
+        let b = glm::Vec2::from(&self.b);
+        let len = glm::distance(&a, &b);
+        let along = (b - a) / len;
+        let across = glm::vec2(-along.y, along.x);
+        let (s, t) = (glm::dot(&(p - a), &along), glm::dot(&(p - a), &across));
+        nearest_face(
+            p,
+            &[
+                (s, -along),
+                (len - s, along),
+                (t + self.w / 2.0, -across),
+                (self.w / 2.0 - t, across),
+            ],
+        )
+    }
+}
+
+/// A curved wall of width w following a circular arc of radius r around center. The arc goes
+/// from start_angle to end_angle in the direction of increasing angle. Angle 0 points in the
+/// positive x-axis direction and PI/2 in the positive y-axis direction. The angles are in
+/// radians, but in the level JSON they are given in degrees.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Arc {
+    pub center: Point,
+    pub r: f32,
+    pub w: f32,
+    pub start_angle: f32,
+    pub end_angle: f32, // always greater than start_angle
+    pub restitution: f32,
+}
+
+impl TryFrom<&json::JsonValue> for Arc {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Arc, LevelError> {
+        let start_angle = number(json_val, "start_angle")?.to_radians();
+        let end_angle = number(json_val, "end_angle")?.to_radians();
+        Ok(Arc {
+            center: Point::try_from(&json_val["center"])?,
+            r: positive_number(json_val, "r")?,
+            w: positive_number(json_val, "w")?,
+            start_angle,
+            end_angle: if end_angle > start_angle {
+                end_angle
+            } else {
+                end_angle + 2.0 * PI
+            },
+            restitution: restitution_from_json(json_val),
+        })
+    }
+}
+
+impl From<&Arc> for json::JsonValue {
+    fn from(arc: &Arc) -> json::JsonValue {
+        let mut json_val = json::object! {
+            center: &arc.center,
+            r: arc.r,
+            w: arc.w,
+            start_angle: arc.start_angle.to_degrees(),
+            end_angle: arc.end_angle.to_degrees(),
+        };
+        restitution_to_json(&mut json_val, arc.restitution);
+        json_val
+    }
+}
+
+impl Arc {
+    // Point inside self closest to p
+    fn closest_point(&self, p: glm::Vec2) -> glm::Vec2 {
+        let center = glm::Vec2::from(&self.center);
+        let dir = |angle: f32| glm::vec2(angle.cos(), angle.sin());
+        let (r_inner, r_outer) = (self.r - self.w / 2.0, self.r + self.w / 2.0);
+        let angle = (p.y - center.y).atan2(p.x - center.x);
+        if (angle - self.start_angle).rem_euclid(2.0 * PI) <= self.end_angle - self.start_angle {
+            let r = glm::clamp_scalar(glm::distance(&p, &center), r_inner, r_outer);
+            center + r * dir(angle)
+        } else {
+            // Closest point is on one of the flat ends
+            let end_point = |angle: f32| {
+                closest_point_on_line(
+                    p,
+                    center + r_inner * dir(angle),
+                    center + r_outer * dir(angle),
+                )
+            };
+            let start = end_point(self.start_angle);
+            let end = end_point(self.end_angle);
+            if glm::distance2(&p, &start) < glm::distance2(&p, &end) {
+                start
+            } else {
+                end
+            }
+        }
+    }
+
+    // Point on the edge of self closest to p inside self, and the outward normal there
+    fn nearest_surface(&self, p: glm::Vec2) -> (glm::Vec2, glm::Vec2) {
+        let center = glm::Vec2::from(&self.center);
+        let (r_inner, r_outer) = (self.r - self.w / 2.0, self.r + self.w / 2.0);
+        let r = glm::distance(&p, &center);
+        let radial = (p - center) / r;
+        let tangent = glm::vec2(-radial.y, radial.x);
+        let angle = (p.y - center.y).atan2(p.x - center.x);
+        let from_start = (angle - self.start_angle).rem_euclid(2.0 * PI);
+        nearest_face(
+            p,
+            &[
+                (r_outer - r, radial),
+                (r - r_inner, -radial),
+                (r * from_start, -tangent),
+                (
+                    r * (self.end_angle - self.start_angle - from_start),
+                    tangent,
+                ),
+            ],
+        )
+    }
+}
+
+/// Position of a moving wall at a point of time, in seconds from the start of the game
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    pub t: f32,
+    pub pos: Point, // top-left corner
+}
+
+impl TryFrom<&json::JsonValue> for Keyframe {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Keyframe, LevelError> {
+        Ok(Keyframe {
+            t: number(json_val, "t")?,
+            pos: Point::try_from(&json_val["pos"])?,
+        })
+    }
+}
+
+impl From<&Keyframe> for json::JsonValue {
+    fn from(keyframe: &Keyframe) -> json::JsonValue {
+        json::object! { t: keyframe.t, pos: &keyframe.pos }
+    }
+}
+
+/// A rectangular wall moving linearly between keyframes. The movement repeats with the time of
+/// the last keyframe as the period, so for a smooth loop the last keyframe position should equal
+/// the first one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MovingWall {
+    pub size: Size,
+    pub keyframes: Vec<Keyframe>, // sorted by time
+    pub restitution: f32,
+}
+
+impl TryFrom<&json::JsonValue> for MovingWall {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<MovingWall, LevelError> {
+        let mut keyframes: Vec<Keyframe> = members(&json_val["keyframes"])?;
+        if keyframes.is_empty() {
+            return Err(LevelError::InvalidValue(String::from(
+                "Moving wall without keyframes",
+            )));
+        }
+        // JSON numbers are always finite
+        keyframes.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+        Ok(MovingWall {
+            size: Size::try_from(&json_val["size"])?,
+            keyframes,
+            restitution: restitution_from_json(json_val),
+        })
+    }
+}
+
+impl From<&MovingWall> for json::JsonValue {
+    fn from(wall: &MovingWall) -> json::JsonValue {
+        let mut json_val = json::object! {
+            size: &wall.size,
+            keyframes: json_array(&wall.keyframes),
+        };
+        restitution_to_json(&mut json_val, wall.restitution);
+        json_val
+    }
+}
+
+impl MovingWall {
+    /// The area covered by the wall at time t
+    pub fn rect_at(&self, t: f32) -> Rect {
+        let (k0, k1, f) = self.keyframes_at(t);
+        let p = glm::lerp(&glm::Vec2::from(&k0.pos), &glm::Vec2::from(&k1.pos), f);
+        Rect {
+            pos: Point::from(&p),
+            size: self.size,
+        }
+    }
+
+    pub fn velocity_at(&self, t: f32) -> glm::Vec2 {
+        let (k0, k1, _) = self.keyframes_at(t);
+        if k1.t > k0.t {
+            (glm::Vec2::from(&k1.pos) - glm::Vec2::from(&k0.pos)) / (k1.t - k0.t)
+        } else {
+            glm::vec2(0.0, 0.0)
+        }
+    }
+
+    // The keyframes before and after time t, and the interpolation factor between them
+    fn keyframes_at(&self, t: f32) -> (&Keyframe, &Keyframe, f32) {
+        let last = self.keyframes.last().unwrap();
+        let t = if last.t > 0.0 {
+            t.rem_euclid(last.t)
+        } else {
+            0.0
+        };
+        match self.keyframes.windows(2).find(|k| t < k[1].t) {
+            Some(k) if t >= k[0].t => (&k[0], &k[1], (t - k[0].t) / (k[1].t - k[0].t)),
+            Some(k) => (&k[0], &k[0], 0.0), // before the first keyframe
+            None => (last, last, 0.0),
+        }
+    }
+}
+
+/// A bar rotating around its center point at a constant angular velocity (radians/s, positive
+/// from the positive x-axis towards the positive y-axis). In the level JSON the angles are given
+/// in degrees.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RotatingBar {
+    pub center: Point,
+    pub length: f32,
+    pub w: f32,
+    pub start_angle: f32,
+    pub angular_velocity: f32,
+    pub restitution: f32,
+}
+
+impl TryFrom<&json::JsonValue> for RotatingBar {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<RotatingBar, LevelError> {
+        Ok(RotatingBar {
+            center: Point::try_from(&json_val["center"])?,
+            length: positive_number(json_val, "length")?,
+            w: positive_number(json_val, "w")?,
+            start_angle: json_val["start_angle"].as_f32().unwrap_or(0.0).to_radians(),
+            angular_velocity: number(json_val, "speed")?.to_radians(),
+            restitution: restitution_from_json(json_val),
+        })
+    }
+}
+
+impl From<&RotatingBar> for json::JsonValue {
+    fn from(bar: &RotatingBar) -> json::JsonValue {
+        let mut json_val = json::object! {
+            center: &bar.center,
+            length: bar.length,
+            w: bar.w,
+            start_angle: bar.start_angle.to_degrees(),
+            speed: bar.angular_velocity.to_degrees(),
+        };
+        restitution_to_json(&mut json_val, bar.restitution);
+        json_val
+    }
+}
+
+impl RotatingBar {
+    pub fn angle_at(&self, t: f32) -> f32 {
+        self.start_angle + self.angular_velocity * t
+    }
+
+    fn segment_at(&self, t: f32) -> Segment {
+        let angle = self.angle_at(t);
+        let half = self.length / 2.0 * glm::vec2(angle.cos(), angle.sin());
+        let center = glm::Vec2::from(&self.center);
+        Segment {
+            a: Point::from(&(center - half)),
+            b: Point::from(&(center + half)),
+            w: self.w,
+            restitution: self.restitution,
+        }
+    }
+
+    // Velocity of the bar at point p
+    fn velocity_at(&self, p: glm::Vec2) -> glm::Vec2 {
+        let r = p - glm::Vec2::from(&self.center);
+        self.angular_velocity * glm::vec2(-r.y, r.x)
+    }
+}
+
+/// A hole that is open for open_time seconds once every period seconds. The schedule starts
+/// with the hole opening at time phase.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Trapdoor {
+    pub pos: Point,
+    pub period: f32,
+    pub open_time: f32,
+    pub phase: f32,
+}
+
+impl TryFrom<&json::JsonValue> for Trapdoor {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Trapdoor, LevelError> {
+        Ok(Trapdoor {
+            pos: Point::try_from(&json_val["pos"])?,
+            period: positive_number(json_val, "period")?,
+            open_time: number(json_val, "open")?,
+            phase: json_val["phase"].as_f32().unwrap_or(0.0),
+        })
+    }
+}
+
+impl From<&Trapdoor> for json::JsonValue {
+    fn from(trapdoor: &Trapdoor) -> json::JsonValue {
+        json::object! {
+            pos: &trapdoor.pos,
+            period: trapdoor.period,
+            open: trapdoor.open_time,
+            phase: trapdoor.phase,
+        }
+    }
+}
+
+impl Trapdoor {
+    pub fn is_open_at(&self, t: f32) -> bool {
+        (t - self.phase).rem_euclid(self.period) < self.open_time
+    }
+}
+
+/// Board surface material. The friction factor scales the rolling resistance of the plain board.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Surface {
+    Board,
+    Ice,
+    Carpet,
+    Sticky,
+}
+
+impl TryFrom<&json::JsonValue> for Surface {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Surface, LevelError> {
+        match json_val.as_str() {
+            Some("board") => Ok(Surface::Board),
+            Some("ice") => Ok(Surface::Ice),
+            Some("carpet") => Ok(Surface::Carpet),
+            Some("sticky") => Ok(Surface::Sticky),
+            _ => Err(LevelError::InvalidValue(format!(
+                "Unknown surface type {}",
+                json_val
+            ))),
+        }
+    }
+}
+
+impl From<&Surface> for json::JsonValue {
+    fn from(surface: &Surface) -> json::JsonValue {
+        match surface {
+            Surface::Board => "board",
+            Surface::Ice => "ice",
+            Surface::Carpet => "carpet",
+            Surface::Sticky => "sticky",
+        }
+        .into()
+    }
+}
+
+impl Surface {
+    pub fn friction(&self) -> f32 {
+        match self {
+            Surface::Board => 1.0,
+            Surface::Ice => 0.1,
+            Surface::Carpet => 4.0,
+            Surface::Sticky => 15.0,
+        }
+    }
+}
+
+/// An area of the board with a different surface
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Zone {
+    pub rect: Rect,
+    pub surface: Surface,
+}
+
+impl TryFrom<&json::JsonValue> for Zone {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Zone, LevelError> {
+        Ok(Zone {
+            rect: Rect::try_from(json_val)?,
+            surface: Surface::try_from(&json_val["surface"])?,
+        })
+    }
+}
+
+impl From<&Zone> for json::JsonValue {
+    fn from(zone: &Zone) -> json::JsonValue {
+        let mut json_val = json::JsonValue::from(&zone.rect);
+        json_val["surface"] = (&zone.surface).into();
+        json_val
+    }
+}
+
+/// An item that is collected by rolling the ball over it. Collecting an item gives `points`
+/// and adds `time` seconds to the completion time, so negative `time` is a bonus.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Collectible {
+    pub pos: Point,
+    pub points: u32,
+    pub time: f32,
+}
+
+impl TryFrom<&json::JsonValue> for Collectible {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Collectible, LevelError> {
+        Ok(Collectible {
+            pos: Point::try_from(&json_val["pos"])?,
+            points: json_val["points"].as_u32().unwrap_or(100),
+            time: json_val["time"].as_f32().unwrap_or(0.0),
+        })
+    }
+}
+
+impl From<&Collectible> for json::JsonValue {
+    fn from(collectible: &Collectible) -> json::JsonValue {
+        json::object! {
+            pos: &collectible.pos,
+            points: collectible.points,
+            time: collectible.time,
+        }
+    }
+}
+
+/// Version of the level JSON format written by Level::to_json(). Version 1 is the original
+/// format without a version field, which is migrated when reading.
+pub const LEVEL_FORMAT_VERSION: u32 = 3;
+
+// The units of the level JSON. Lengths are board pixels, in which the ball radius is BALL_R.
+const LENGTH_UNIT: &str = "px";
+const ANGLE_UNIT: &str = "deg";
+const TIME_UNIT: &str = "s";
+
+/// Difficulty rating given by the level author
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+    Expert,
+}
+
+impl TryFrom<&json::JsonValue> for Difficulty {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Difficulty, LevelError> {
+        match json_val.as_str() {
+            Some("easy") => Ok(Difficulty::Easy),
+            Some("medium") => Ok(Difficulty::Medium),
+            Some("hard") => Ok(Difficulty::Hard),
+            Some("expert") => Ok(Difficulty::Expert),
+            _ => Err(LevelError::InvalidValue(format!(
+                "Unknown difficulty {}",
+                json_val
+            ))),
+        }
+    }
+}
+
+impl From<&Difficulty> for json::JsonValue {
+    fn from(difficulty: &Difficulty) -> json::JsonValue {
+        match difficulty {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+            Difficulty::Expert => "expert",
+        }
+        .into()
+    }
+}
+
+/// Descriptive information about a level. Doesn't affect the game.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub author: Option<String>,
+    pub difficulty: Option<Difficulty>,
+    pub description: Option<String>,
+    pub theme: Option<String>,
+}
+
+impl TryFrom<&json::JsonValue> for Metadata {
+    type Error = LevelError;
+
+    fn try_from(json_val: &json::JsonValue) -> Result<Metadata, LevelError> {
+        Ok(Metadata {
+            author: json_val["author"].as_str().map(String::from),
+            difficulty: optional_from_json(&json_val["difficulty"])?,
+            description: json_val["description"].as_str().map(String::from),
+            theme: json_val["theme"].as_str().map(String::from),
+        })
+    }
+}
+
+impl From<&Metadata> for json::JsonValue {
+    fn from(meta: &Metadata) -> json::JsonValue {
+        let mut json_val = json::JsonValue::new_object();
+        optional_to_json(&mut json_val, "author", meta.author.as_deref());
+        optional_to_json(&mut json_val, "difficulty", meta.difficulty.as_ref());
+        optional_to_json(&mut json_val, "description", meta.description.as_deref());
+        optional_to_json(&mut json_val, "theme", meta.theme.as_deref());
+        json_val
+    }
+}
+
+/// Texts of a level translated to one language. Missing texts are shown untranslated.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Translation {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+impl From<&json::JsonValue> for Translation {
+    fn from(json_val: &json::JsonValue) -> Translation {
+        Translation {
+            name: json_val["name"].as_str().map(String::from),
+            description: json_val["description"].as_str().map(String::from),
+        }
+    }
+}
+
+impl From<&Translation> for json::JsonValue {
+    fn from(translation: &Translation) -> json::JsonValue {
+        let mut json_val = json::JsonValue::new_object();
+        optional_to_json(&mut json_val, "name", translation.name.as_deref());
+        optional_to_json(
+            &mut json_val,
+            "description",
+            translation.description.as_deref(),
+        );
+        json_val
+    }
+}
+
+/// Image files replacing the built-in textures, as paths relative to the level file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Assets {
+    pub board_texture: Option<String>,
+    pub markings: Option<String>, // transparent image drawn on top of the board texture
+    pub wall_texture: Option<String>,
+}
+
+impl From<&json::JsonValue> for Assets {
+    fn from(json_val: &json::JsonValue) -> Assets {
+        Assets {
+            board_texture: json_val["board_texture"].as_str().map(String::from),
+            markings: json_val["markings"].as_str().map(String::from),
+            wall_texture: json_val["wall_texture"].as_str().map(String::from),
+        }
+    }
+}
+
+impl From<&Assets> for json::JsonValue {
+    fn from(assets: &Assets) -> json::JsonValue {
+        let mut json_val = json::JsonValue::new_object();
+        optional_to_json(
+            &mut json_val,
+            "board_texture",
+            assets.board_texture.as_deref(),
+        );
+        optional_to_json(&mut json_val, "markings", assets.markings.as_deref());
+        optional_to_json(
+            &mut json_val,
+            "wall_texture",
+            assets.wall_texture.as_deref(),
+        );
+        json_val
+    }
+}
+
+fn optional_from_json<'a, T>(json_val: &'a json::JsonValue) -> Result<Option<T>, LevelError>
+where
+    T: TryFrom<&'a json::JsonValue, Error = LevelError>,
+{
+    if json_val.is_null() {
+        Ok(None)
+    } else {
+        T::try_from(json_val).map(Some)
+    }
+}
+
+// The elements of an optional array in the level JSON
+fn members<'a, T>(json_val: &'a json::JsonValue) -> Result<Vec<T>, LevelError>
+where
+    T: TryFrom<&'a json::JsonValue, Error = LevelError>,
+{
+    json_val.members().map(T::try_from).collect()
+}
+
+// A required number field of an object in the level JSON
+fn number(json_val: &json::JsonValue, key: &str) -> Result<f32, LevelError> {
+    json_val[key].as_f32().ok_or_else(|| {
+        LevelError::InvalidValue(format!(
+            "Missing or invalid number \"{}\" in {}",
+            key, json_val
+        ))
+    })
+}
+
+// A required number field that must be greater than zero, e.g. a length or a period
+fn positive_number(json_val: &json::JsonValue, key: &str) -> Result<f32, LevelError> {
+    let val = number(json_val, key)?;
+    if val > 0.0 {
+        Ok(val)
+    } else {
+        Err(LevelError::InvalidValue(format!(
+            "Number \"{}\" must be positive in {}",
+            key, json_val
+        )))
+    }
+}
+
+fn optional_to_json<T>(json_val: &mut json::JsonValue, key: &str, val: Option<T>)
+where
+    json::JsonValue: From<T>,
+{
+    if let Some(val) = val {
+        json_val[key] = val.into();
+    }
+}
+
+// The fields of the objects in the level JSON, by the key under which the objects are found.
+// Elements of arrays are found under the key of the array.
+const LEVEL_FIELDS: [(&str, &[&str]); 25] = [
+    (
+        "",
+        &[
+            "$schema",
+            "version",
+            "name",
+            "units",
+            "meta",
+            "translations",
+            "assets",
+            "size",
+            "start",
+            "end",
+            "walls",
+            "posts",
+            "segments",
+            "arcs",
+            "moving_walls",
+            "rotating_bars",
+            "holes",
+            "trapdoors",
+            "checkpoints",
+            "zones",
+            "collectibles",
+            "par_time",
+            "path",
+        ],
+    ),
+    ("units", &["length", "angle", "time"]),
+    ("meta", &["author", "difficulty", "description", "theme"]),
+    ("translation", &["name", "description"]),
+    ("assets", &["board_texture", "markings", "wall_texture"]),
+    ("size", &["w", "h"]),
+    ("start", &["x", "y"]),
+    ("pos", &["x", "y"]),
+    ("center", &["x", "y"]),
+    ("a", &["x", "y"]),
+    ("b", &["x", "y"]),
+    ("holes", &["x", "y"]),
+    ("path", &["x", "y"]),
+    ("end", &["pos", "size"]),
+    ("checkpoints", &["pos", "size"]),
+    ("walls", &["pos", "size", "restitution"]),
+    ("posts", &["pos", "r", "restitution"]),
+    ("segments", &["a", "b", "w", "restitution"]),
+    (
+        "arcs",
+        &[
+            "center",
+            "r",
+            "w",
+            "start_angle",
+            "end_angle",
+            "restitution",
+        ],
+    ),
+    ("moving_walls", &["size", "keyframes", "restitution"]),
+    ("keyframes", &["t", "pos"]),
+    (
+        "rotating_bars",
+        &[
+            "center",
+            "length",
+            "w",
+            "start_angle",
+            "speed",
+            "restitution",
+        ],
+    ),
+    ("trapdoors", &["pos", "period", "open", "phase"]),
+    ("zones", &["pos", "size", "surface"]),
+    ("collectibles", &["pos", "points", "time"]),
+];
+
+// Objects whose keys are not fixed, e.g. language codes, by their key. Their values are checked
+// like the objects under the given key.
+const LEVEL_MAPS: [(&str, &str); 1] = [("translations", "translation")];
+
+fn level_map_values(key: &str) -> Option<&'static str> {
+    LEVEL_MAPS
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|&(_, value_key)| value_key)
+}
+
+fn level_fields(key: &str) -> &'static [&'static str] {
+    LEVEL_FIELDS
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|&(_, fields)| fields)
+        .unwrap_or(&[])
+}
+
+// Reject fields that are not part of the level format, so that typos don't go unnoticed
+fn check_level_fields(key: &str, json_val: &json::JsonValue) -> Result<(), LevelError> {
+    if json_val.is_array() {
+        for member in json_val.members() {
+            check_level_fields(key, member)?;
+        }
+    } else if let Some(value_key) = level_map_values(key) {
+        for (_, v) in json_val.entries() {
+            check_level_fields(value_key, v)?;
+        }
+    } else if json_val.is_object() {
+        let fields = level_fields(key);
+        for (k, v) in json_val.entries() {
+            if !fields.contains(&k) {
+                return Err(LevelError::UnknownField {
+                    field: String::from(k),
+                    object: String::from(key),
+                });
+            }
+            check_level_fields(k, v)?;
+        }
+    }
+    Ok(())
+}
+
+// Version 1 levels ignored unknown fields, so they are dropped in the migration
+fn remove_unknown_level_fields(key: &str, json_val: &mut json::JsonValue) {
+    if json_val.is_array() {
+        for member in json_val.members_mut() {
+            remove_unknown_level_fields(key, member);
+        }
+    } else if json_val.is_object() {
+        let fields = level_fields(key);
+        let unknown: Vec<String> = json_val
+            .entries()
+            .map(|(k, _)| String::from(k))
+            .filter(|k| !fields.contains(&k.as_str()))
+            .collect();
+        for k in unknown.iter() {
+            println!("Ignoring unknown field \"{}\" in version 1 level", k);
+            json_val.remove(k);
+        }
+        for (k, v) in json_val.entries_mut() {
+            remove_unknown_level_fields(k, v);
+        }
+    }
+}
+
+// Convert level JSON of an older format version to the current version
+fn migrate_level(data: &mut json::JsonValue) -> Result<(), LevelError> {
+    let version = if data["version"].is_null() {
+        Some(1)
+    } else {
+        data["version"].as_u32()
+    };
+    let version = match version {
+        Some(version) if (1..=LEVEL_FORMAT_VERSION).contains(&version) => version,
+        _ => return Err(LevelError::UnsupportedVersion(data["version"].dump())),
+    };
+    if version == 1 {
+        remove_unknown_level_fields("", data);
+    }
+    // The later versions only add optional fields
+    data["version"] = LEVEL_FORMAT_VERSION.into();
+    Ok(())
+}
+
+/// The reasons why level JSON can't be read
+#[derive(Debug)]
+pub enum LevelError {
+    Json(json::Error),
+    UnsupportedVersion(String),
+    UnknownField { field: String, object: String }, // object is empty for top-level fields
+    UnsupportedUnit { quantity: String, unit: String },
+    InvalidValue(String),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::Json(e) => write!(f, "Invalid JSON: {}", e),
+            LevelError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported level format version {}, the latest supported version is {}",
+                version, LEVEL_FORMAT_VERSION
+            ),
+            LevelError::UnknownField { field, object } if object.is_empty() => {
+                write!(f, "Unknown field \"{}\" in level", field)
+            }
+            LevelError::UnknownField { field, object } => write!(
+                f,
+                "Unknown field \"{}\" in level object \"{}\"",
+                field, object
+            ),
+            LevelError::UnsupportedUnit { quantity, unit } => {
+                write!(f, "Unsupported {} unit {}", quantity, unit)
+            }
+            LevelError::InvalidValue(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl From<json::Error> for LevelError {
+    fn from(e: json::Error) -> LevelError {
+        LevelError::Json(e)
+    }
 }
 
 /// Holds the information about single Labyrinth level.
 /// Level coordinate system origin is in top-left corner of the board.
 /// Positive x-axis direction is right and positive y-axis direction down.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Level {
     pub name: String,
+    pub meta: Metadata,
+    pub translations: BTreeMap<String, Translation>, // by language code
+    pub assets: Assets,
     pub size: Size,
     pub start: Point,
     pub end: Rect,
-    pub walls: Vec<Rect>,
+    pub walls: Vec<Wall>,
+    pub posts: Vec<Post>,
+    pub segments: Vec<Segment>,
+    pub arcs: Vec<Arc>,
+    pub moving_walls: Vec<MovingWall>,
+    pub rotating_bars: Vec<RotatingBar>,
     pub holes: Vec<Point>,
+    pub trapdoors: Vec<Trapdoor>,
+    pub checkpoints: Vec<Rect>, // in the order they are reached along the route
+    pub zones: Vec<Zone>,       // later zones are on top of earlier ones where they overlap
+    pub collectibles: Vec<Collectible>,
+    pub par_time: Option<f32>, // finishing faster than this (in seconds) gives extra points
     pub path: Vec<Point>,
 }
 
 impl Level {
-    pub fn from_json(json: &str) -> Level {
-        let data = json::parse(json).unwrap();
-        Level {
-            name: String::from(data["name"].as_str().unwrap()),
-            size: Size::from(&data["size"]),
-            start: Point::from(&data["start"]),
-            end: Rect::from(&data["end"]),
-            walls: data["walls"].members().map(|j| Rect::from(j)).collect(),
-            holes: data["holes"].members().map(|j| Point::from(j)).collect(),
-            path: data["path"].members().map(|j| Point::from(j)).collect(),
+    /// Read a level from JSON in any supported format version
+    pub fn from_json(json: &str) -> Result<Level, LevelError> {
+        let mut data = json::parse(json)?;
+        migrate_level(&mut data)?;
+        check_level_fields("", &data)?;
+        let units = &data["units"];
+        for (key, unit) in [
+            ("length", LENGTH_UNIT),
+            ("angle", ANGLE_UNIT),
+            ("time", TIME_UNIT),
+        ]
+        .iter()
+        {
+            let val = units[*key].as_str().unwrap_or(*unit);
+            if val != *unit {
+                return Err(LevelError::UnsupportedUnit {
+                    quantity: String::from(*key),
+                    unit: String::from(val),
+                });
+            }
+        }
+        Ok(Level {
+            name: match data["name"].as_str() {
+                Some(name) => String::from(name),
+                None => {
+                    return Err(LevelError::InvalidValue(String::from(
+                        "Level without a name",
+                    )))
+                }
+            },
+            meta: Metadata::try_from(&data["meta"])?,
+            translations: data["translations"]
+                .entries()
+                .map(|(lang, j)| (String::from(lang), Translation::from(j)))
+                .collect(),
+            assets: Assets::from(&data["assets"]),
+            size: Size::try_from(&data["size"])?,
+            start: Point::try_from(&data["start"])?,
+            end: Rect::try_from(&data["end"])?,
+            walls: members(&data["walls"])?,
+            // Optional obstacle types
+            posts: members(&data["posts"])?,
+            segments: members(&data["segments"])?,
+            arcs: members(&data["arcs"])?,
+            moving_walls: members(&data["moving_walls"])?,
+            rotating_bars: members(&data["rotating_bars"])?,
+            holes: members(&data["holes"])?,
+            trapdoors: members(&data["trapdoors"])?,
+            zones: members(&data["zones"])?,
+            checkpoints: members(&data["checkpoints"])?,
+            collectibles: members(&data["collectibles"])?,
+            par_time: data["par_time"].as_f32(),
+            path: members(&data["path"])?,
+        })
+    }
+
+    /// The name of the level in the given language, the untranslated name if there's no
+    /// translation
+    pub fn localized_name(&self, lang: &str) -> &str {
+        self.translations
+            .get(lang)
+            .and_then(|t| t.name.as_deref())
+            .unwrap_or(&self.name)
+    }
+
+    /// The description of the level in the given language, or the untranslated description
+    pub fn localized_description(&self, lang: &str) -> Option<&str> {
+        self.translations
+            .get(lang)
+            .and_then(|t| t.description.as_deref())
+            .or(self.meta.description.as_deref())
+    }
+
+    /// Distance from p to the closest static obstacle or board edge, 0 if p is inside an
+    /// obstacle. Moving walls and rotating bars are not included.
+    pub fn obstacle_distance(&self, p: glm::Vec2) -> f32 {
+        let closest_points = self
+            .walls
+            .iter()
+            .map(|w| w.rect.closest_point(p))
+            .chain(self.posts.iter().map(|post| post.closest_point(p)))
+            .chain(self.segments.iter().map(|s| s.closest_point(p)))
+            .chain(self.arcs.iter().map(|arc| arc.closest_point(p)));
+        let edge_distance =
+            p.x.min(p.y)
+                .min(self.size.w - p.x)
+                .min(self.size.h - p.y)
+                .max(0.0);
+        closest_points
+            .map(|c| glm::distance(&p, &c))
+            .fold(edge_distance, f32::min)
+    }
+
+    /// Serialize the level in the latest format version. Empty optional lists and objects are
+    /// left out.
+    pub fn to_json(&self) -> String {
+        let mut data = json::object! {
+            version: LEVEL_FORMAT_VERSION,
+            name: self.name.as_str(),
+            units: json::object! { length: LENGTH_UNIT, angle: ANGLE_UNIT, time: TIME_UNIT },
+            meta: &self.meta,
+            translations: json::JsonValue::Object(
+                self.translations
+                    .iter()
+                    .map(|(lang, t)| (lang.as_str(), json::JsonValue::from(t)))
+                    .collect()
+            ),
+            assets: &self.assets,
+            size: &self.size,
+            start: &self.start,
+            end: &self.end,
+            walls: json_array(&self.walls),
+        };
+        for key in ["meta", "translations", "assets"].iter() {
+            if data[*key].is_empty() {
+                data.remove(key);
+            }
         }
+        let mut optional = |key: &str, val: json::JsonValue| {
+            if !val.is_empty() {
+                data[key] = val;
+            }
+        };
+        optional("posts", json_array(&self.posts));
+        optional("segments", json_array(&self.segments));
+        optional("arcs", json_array(&self.arcs));
+        optional("moving_walls", json_array(&self.moving_walls));
+        optional("rotating_bars", json_array(&self.rotating_bars));
+        optional("holes", json_array(&self.holes));
+        optional("trapdoors", json_array(&self.trapdoors));
+        optional("checkpoints", json_array(&self.checkpoints));
+        optional("zones", json_array(&self.zones));
+        optional("collectibles", json_array(&self.collectibles));
+        if let Some(t) = self.par_time {
+            data["par_time"] = t.into();
+        }
+        data["path"] = json_array(&self.path);
+        data.pretty(4)
     }
 }
 
+fn json_array<'a, T: 'a>(items: &'a [T]) -> json::JsonValue
+where
+    json::JsonValue: From<&'a T>,
+{
+    json::JsonValue::Array(items.iter().map(|item| item.into()).collect())
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum State {
     InProgress,
     Won,
+    Fell { hole: Point, t_fell: Instant }, // lost a ball but has lives left, respawns after a delay
     Lost { hole: Point, t_lost: Instant },
 }
 
+/// Something that happened in a game, at simulated time `time` (see `Game::time`)
+#[derive(Copy, Clone, Debug)]
+pub struct Event {
+    pub time: f32,
+    pub kind: EventKind,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum EventKind {
+    /// The ball hit a wall or another obstacle. The ball was at pos after the collision, and
+    /// impulse is the change of its velocity, i.e. the impulse per unit mass.
+    WallImpact { pos: Point, impulse: f32 },
+    /// The ball hit an edge of the board
+    EdgeHit { pos: Point, impulse: f32 },
+    /// The ball went past a hole without falling in. distance is the closest distance between
+    /// the ball center and the hole center.
+    NearMiss { hole: Point, distance: f32 },
+    /// The ball reached the goal area
+    Goal,
+    /// The ball fell in a hole, leaving the given number of lives
+    Fell { hole: Point, lives: u32 },
+    /// A new ball was put in play at pos
+    Respawned { pos: Point },
+    /// The ball reached the checkpoint with the given index
+    Checkpoint(usize),
+    /// The ball passed the AI path point with the given index
+    Waypoint(usize),
+    /// The collectible with the given index was picked up
+    Collectible(usize),
+}
+
+/// Result of a game. `time` is the completion time in seconds including the bonuses and penalties.
+#[derive(Copy, Clone, Debug)]
+pub struct Score {
+    pub collected: usize,
+    pub total: usize,
+    pub time: f32,
+    pub points: u32,
+}
+
 /// Maintains the state of a single game of Labyrinth. A Game always starts with the ball in the
 /// start position defined by the Level instance associated with the Game, and ends when the
-/// ball either falls in a hole or reaches the goal area of the Level. The Game instance needs
+/// ball either reaches the goal area of the Level or falls in a hole with no lives left. While
+/// there are lives left, a ball that falls in a hole respawns at the furthest checkpoint reached,
+/// or at the start position if no checkpoint has been reached yet. The Game instance needs
 /// to be periodically updated to advance the simulation.
 pub struct Game {
     pub state: State,
     pub ball_pos: Point,
     pub ball_v: Velocity,
+    pub ball_w: AngularVelocity,
     pub angle_x: f32, // Board angle w.r.t. x-axis in radians
     pub angle_y: f32,
     pub level: Level,
+    pub time: f32, // Simulated time in seconds, doesn't advance while the game is paused
+    pub time_scale: f32, // Simulated seconds per real second
+    pub lives: u32, // Balls left, including the one in play
+    pub checkpoint: Option<usize>, // Index of the furthest checkpoint reached
+    pub waypoints: usize, // Number of the level's AI path points passed
+    pub collected: Vec<bool>, // Which of the level's collectibles have been picked up
+    pub time_adjustment: f32, // Seconds added to the completion time by collectibles and falls
+    events: Vec<Event>, // Events since the last drain_events call
+    near_miss: Option<(Point, f32)>, // Hole the ball is passing, and the closest distance so far
+    fall_penalty: f32,
+    physics: PhysicsModel,
     prev_update: Option<Instant>,
 }
 
 impl Game {
-    pub fn new(lvl: &Level) -> Game {
+    pub fn new(lvl: &Level, cfg: Config) -> Game {
+        assert!(cfg.lives >= 1);
         Game {
             state: State::InProgress,
             ball_pos: lvl.start,
             ball_v: Velocity { x: 0.0, y: 0.0 },
+            ball_w: AngularVelocity {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
             angle_x: 0.0,
             angle_y: 0.0,
             level: lvl.clone(),
+            time: 0.0,
+            time_scale: cfg.time_scale,
+            lives: cfg.lives,
+            checkpoint: None,
+            waypoints: 0,
+            collected: vec![false; lvl.collectibles.len()],
+            time_adjustment: 0.0,
+            events: Vec::new(),
+            near_miss: None,
+            fall_penalty: cfg.fall_penalty,
+            physics: cfg.physics,
             prev_update: None,
         }
     }
@@ -167,60 +1511,357 @@ impl Game {
     pub fn update(&mut self, time: Instant) {
         match self.state {
             State::InProgress => (),
+            State::Fell { t_fell, .. } => {
+                if time.duration_since(t_fell).as_secs_f32() >= RESPAWN_DELAY {
+                    self.respawn(time);
+                }
+                return;
+            }
             _ => return, // nothing to update if the game is not in progress anymore
         }
 
-        let (p, v) = self.detect_collisions(self.do_physics(time));
+        let dt = self.time_scale
+            * time
+                .duration_since(self.prev_update.unwrap_or(time))
+                .as_secs_f32();
+        // Long updates are split into steps short enough that fast obstacles can't pass
+        // through the ball
+        let max_speed = glm::length(&glm::Vec2::from(&self.ball_v)) + self.max_obstacle_speed();
+        let steps = ((dt * max_speed / MAX_STEP_MOTION).ceil() as u32)
+            .max(1)
+            .min(MAX_PHYSICS_STEPS);
+        let step_dt = dt / steps as f32;
+        for _ in 0..steps {
+            self.time += step_dt;
+            let (p, v0, w) = self.do_physics(step_dt);
+            let (p, v, impacts) = self.detect_collisions((p, v0));
+            for kind in impacts {
+                self.push_event(kind);
+            }
+            let (v, w) = match self.physics {
+                PhysicsModel::Arcade => (v, rolling_spin(v, 0.0)),
+                // The velocity change in the collisions is the normal impulse from the walls hit
+                PhysicsModel::Realistic => apply_wall_friction(v, w, v - v0),
+            };
+            self.ball_pos = Point::from(&p);
+            self.ball_v = Velocity::from(&v);
+            self.ball_w = AngularVelocity::from(&w);
+        }
+        let p = glm::Vec2::from(&self.ball_pos);
 
         if self.level.end.contains(p) {
             self.state = State::Won;
+            self.push_event(EventKind::Goal);
+        }
+
+        for i in 0..self.level.collectibles.len() {
+            let c = &self.level.collectibles[i];
+            if !self.collected[i]
+                && glm::distance(&p, &glm::Vec2::from(&c.pos)) < BALL_R + COLLECTIBLE_R
+            {
+                self.collected[i] = true;
+                self.time_adjustment += c.time;
+                self.push_event(EventKind::Collectible(i));
+            }
+        }
+
+        // Only checkpoints further along the route than the current one count
+        if let Some(i) = self.level.checkpoints.iter().rposition(|c| c.contains(p)) {
+            if self.checkpoint.map_or(true, |reached| i > reached) {
+                self.checkpoint = Some(i);
+                self.push_event(EventKind::Checkpoint(i));
+            }
+        }
+
+        // The path points are passed in order. Getting close to a point a bit further along the
+        // route passes the skipped ones too.
+        let lookahead = (self.waypoints + WAYPOINT_LOOKAHEAD + 1).min(self.level.path.len());
+        if let Some(i) = (self.waypoints..lookahead)
+            .rev()
+            .find(|&i| glm::distance(&p, &glm::Vec2::from(&self.level.path[i])) < WAYPOINT_R)
+        {
+            for j in self.waypoints..=i {
+                self.push_event(EventKind::Waypoint(j));
+            }
+            self.waypoints = i + 1;
         }
 
-        let hole = self
+        let open_trapdoors = self
+            .level
+            .trapdoors
+            .iter()
+            .filter(|d| d.is_open_at(self.time))
+            .map(|d| d.pos);
+        let (hole, distance) = self
             .level
             .holes
             .iter()
-            .find(|h| glm::distance(&p, &glm::Vec2::from(*h)) < HOLE_R);
-        if hole.is_some() {
-            self.state = State::Lost {
-                hole: *hole.unwrap(),
-                t_lost: time,
+            .cloned()
+            .chain(open_trapdoors)
+            .map(|h| (h, glm::distance(&p, &glm::Vec2::from(&h))))
+            .fold((None, f32::MAX), |closest, (h, d)| {
+                if d < closest.1 {
+                    (Some(h), d)
+                } else {
+                    closest
+                }
+            });
+        if distance < HOLE_R {
+            let hole = hole.unwrap();
+            self.lives -= 1;
+            self.time_adjustment += self.fall_penalty;
+            self.state = if self.lives > 0 {
+                State::Fell { hole, t_fell: time }
+            } else {
+                State::Lost { hole, t_lost: time }
             };
+            self.near_miss = None;
+            self.push_event(EventKind::Fell {
+                hole,
+                lives: self.lives,
+            });
+        } else if distance < NEAR_MISS_R {
+            let closest = self.near_miss.map_or(distance, |(_, d)| d.min(distance));
+            self.near_miss = Some((hole.unwrap(), closest));
+        } else if let Some((hole, distance)) = self.near_miss.take() {
+            self.push_event(EventKind::NearMiss { hole, distance });
         }
 
         self.prev_update = Some(time);
-        self.ball_pos = Point::from(&p);
-        self.ball_v = Velocity::from(&v);
     }
 
     pub fn reset_time(&mut self) {
         self.prev_update = None;
     }
 
-    // Calculate ball (position, velocity) based on current time
-    fn do_physics(&self, time: Instant) -> (glm::Vec2, glm::Vec2) {
-        let dt = time
-            .duration_since(self.prev_update.unwrap_or(time))
-            .as_secs_f32();
-        let v = glm::Vec2::from(&self.ball_v)
-            + glm::vec2(self.angle_x, self.angle_y) * ACCEL_COEFF * dt;
-        let p = glm::Vec2::from(&self.ball_pos) + v * dt;
-        (p, v)
-    }
-
-    // Detect collisions to walls and update ball (position, velocity) accordingly
-    fn detect_collisions(&self, pv: (glm::Vec2, glm::Vec2)) -> (glm::Vec2, glm::Vec2) {
-        let pv = apply_collision_response(pv, detect_board_left_edge_collision(pv.0));
-        let pv =
-            apply_collision_response(pv, detect_board_right_edge_collision(pv.0, self.level.size));
-        let pv = apply_collision_response(pv, detect_board_top_edge_collision(pv.0));
-        let pv = apply_collision_response(
+    /// Returns the events since the previous call. The events accumulate until drained.
+    pub fn drain_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn push_event(&mut self, kind: EventKind) {
+        self.events.push(Event {
+            time: self.time,
+            kind,
+        });
+    }
+
+    /// Calculates the score for a game that has been played for `elapsed` seconds. Points are given
+    /// for the collected items and for every second the adjusted time is under the level's par time.
+    pub fn score(&self, elapsed: f32) -> Score {
+        let time = (elapsed + self.time_adjustment).max(0.0);
+        let item_points: u32 = self
+            .level
+            .collectibles
+            .iter()
+            .zip(self.collected.iter())
+            .filter(|(_, collected)| **collected)
+            .map(|(c, _)| c.points)
+            .sum();
+        let time_points = match self.level.par_time {
+            Some(par) => ((par - time).max(0.0) * POINTS_PER_SEC_UNDER_PAR) as u32,
+            None => 0,
+        };
+        Score {
+            collected: self.collected.iter().filter(|c| **c).count(),
+            total: self.collected.len(),
+            time,
+            points: item_points + time_points,
+        }
+    }
+
+    // Put a new ball in play at the furthest checkpoint reached
+    fn respawn(&mut self, time: Instant) {
+        self.state = State::InProgress;
+        self.ball_pos = match self.checkpoint {
+            Some(i) => self.level.checkpoints[i].center(),
+            None => self.level.start,
+        };
+        self.ball_v = Velocity { x: 0.0, y: 0.0 };
+        self.ball_w = AngularVelocity {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        self.prev_update = Some(time);
+        self.push_event(EventKind::Respawned { pos: self.ball_pos });
+    }
+
+    /// The surface under point p
+    pub fn surface_at(&self, p: glm::Vec2) -> Surface {
+        self.level
+            .zones
+            .iter()
+            .rev()
+            .find(|z| z.rect.contains(p))
+            .map_or(Surface::Board, |z| z.surface)
+    }
+
+    // Calculate ball (position, velocity, angular velocity) after dt seconds
+    fn do_physics(&self, dt: f32) -> (glm::Vec2, glm::Vec2, glm::Vec3) {
+        let p0 = glm::Vec2::from(&self.ball_pos);
+        let friction = self.surface_at(p0).friction();
+        let (v, w) = match self.physics {
+            PhysicsModel::Arcade => (
+                glm::Vec2::from(&self.ball_v)
+                    + glm::vec2(self.angle_x, self.angle_y) * ACCEL_COEFF * dt,
+                glm::Vec3::from(&self.ball_w),
+            ),
+            PhysicsModel::Realistic => roll(
+                glm::Vec2::from(&self.ball_v),
+                glm::Vec3::from(&self.ball_w),
+                GRAVITY * glm::vec2(self.angle_x.sin(), self.angle_y.sin()),
+                SLIDING_FRICTION * friction,
+                dt,
+            ),
+        };
+        // Rolling resistance slows the ball down but never reverses its direction
+        let speed = glm::length(&v);
+        let speed_loss = ROLLING_RESISTANCE * friction * dt;
+        let v_resisted = if speed > speed_loss {
+            v * (speed - speed_loss) / speed
+        } else {
+            glm::zero()
+        };
+        // A rolling ball loses spin together with speed
+        let w = if glm::length(&contact_slip(v, w)) < MAX_ROLLING_SLIP {
+            rolling_spin(v_resisted, w.z)
+        } else {
+            w
+        };
+        let p = p0 + v_resisted * dt;
+        (p, v_resisted, w)
+    }
+
+    // The highest speed of any point of the moving walls and rotating bars
+    fn max_obstacle_speed(&self) -> f32 {
+        let walls = self
+            .level
+            .moving_walls
+            .iter()
+            .map(|w| glm::length(&w.velocity_at(self.time)));
+        let bars = self
+            .level
+            .rotating_bars
+            .iter()
+            .map(|bar| bar.angular_velocity.abs() * bar.length / 2.0);
+        walls.chain(bars).fold(0.0, f32::max)
+    }
+
+    // Detect collisions to walls and update ball (position, velocity) accordingly. Also returns
+    // the impact events of the collisions.
+    fn detect_collisions(
+        &self,
+        pv: (glm::Vec2, glm::Vec2),
+    ) -> (glm::Vec2, glm::Vec2, Vec<EventKind>) {
+        let mut impacts = Vec::new();
+        let mut collide = |pv: (glm::Vec2, glm::Vec2),
+                           resp: Option<glm::Vec2>,
+                           wall_v: glm::Vec2,
+                           restitution: f32,
+                           edge: bool| {
+            let (p, v) = apply_moving_collision_response(pv, resp, wall_v, restitution);
+            let impulse = glm::distance(&pv.1, &v);
+            if impulse >= MIN_IMPACT_IMPULSE {
+                let pos = Point::from(&p);
+                impacts.push(if edge {
+                    EventKind::EdgeHit { pos, impulse }
+                } else {
+                    EventKind::WallImpact { pos, impulse }
+                });
+            }
+            (p, v)
+        };
+        let still = glm::vec2(0.0, 0.0);
+        let pv = collide(
+            pv,
+            detect_board_left_edge_collision(pv.0),
+            still,
+            BOUNCE_COEFF,
+            true,
+        );
+        let pv = collide(
+            pv,
+            detect_board_right_edge_collision(pv.0, self.level.size),
+            still,
+            BOUNCE_COEFF,
+            true,
+        );
+        let pv = collide(
+            pv,
+            detect_board_top_edge_collision(pv.0),
+            still,
+            BOUNCE_COEFF,
+            true,
+        );
+        let pv = collide(
             pv,
             detect_board_bottom_edge_collision(pv.0, self.level.size),
+            still,
+            BOUNCE_COEFF,
+            true,
         );
-        return self.level.walls.iter().fold(pv, |pv, w| {
-            apply_collision_response(pv, detect_wall_collision(pv.0, *w))
+        let pv = self.level.walls.iter().fold(pv, |pv, w| {
+            collide(
+                pv,
+                detect_wall_collision(pv.0, w.rect.closest_point(pv.0), |p| {
+                    w.rect.nearest_surface(p)
+                }),
+                still,
+                w.restitution,
+                false,
+            )
         });
+        let pv = self.level.posts.iter().fold(pv, |pv, post| {
+            collide(
+                pv,
+                detect_wall_collision(pv.0, post.closest_point(pv.0), |p| post.nearest_surface(p)),
+                still,
+                post.restitution,
+                false,
+            )
+        });
+        let pv = self.level.segments.iter().fold(pv, |pv, s| {
+            collide(
+                pv,
+                detect_wall_collision(pv.0, s.closest_point(pv.0), |p| s.nearest_surface(p)),
+                still,
+                s.restitution,
+                false,
+            )
+        });
+        let pv = self.level.arcs.iter().fold(pv, |pv, arc| {
+            collide(
+                pv,
+                detect_wall_collision(pv.0, arc.closest_point(pv.0), |p| arc.nearest_surface(p)),
+                still,
+                arc.restitution,
+                false,
+            )
+        });
+        // Moving obstacles push the ball with their own velocity
+        let pv = self.level.moving_walls.iter().fold(pv, |pv, w| {
+            let rect = w.rect_at(self.time);
+            collide(
+                pv,
+                detect_wall_collision(pv.0, rect.closest_point(pv.0), |p| rect.nearest_surface(p)),
+                w.velocity_at(self.time),
+                w.restitution,
+                false,
+            )
+        });
+        let pv = self.level.rotating_bars.iter().fold(pv, |pv, bar| {
+            let segment = bar.segment_at(self.time);
+            let closest_point = segment.closest_point(pv.0);
+            collide(
+                pv,
+                detect_wall_collision(pv.0, closest_point, |p| segment.nearest_surface(p)),
+                bar.velocity_at(closest_point),
+                bar.restitution,
+                false,
+            )
+        });
+        (pv.0, pv.1, impacts)
     }
 }
 
@@ -256,36 +1897,531 @@ fn detect_board_bottom_edge_collision(ball_pos: glm::Vec2, board_size: Size) ->
     }
 }
 
-fn detect_wall_collision(ball_pos: glm::Vec2, wall: Rect) -> Option<glm::Vec2> {
-    let wall_half_size = glm::vec2(wall.size.w / 2.0, wall.size.h / 2.0);
-    let wall_center = glm::Vec2::from(&wall.pos) + wall_half_size;
-    let closest_point_in_wall = wall_center
-        + glm::clamp_vec(
-            &(ball_pos - wall_center),
-            &(-wall_half_size),
-            &wall_half_size,
-        );
+// Detect a collision to any kind of wall, given the point of the wall closest to the ball. A
+// moving obstacle can push the ball center into itself or into another wall, and then the ball
+// is pushed out through the nearest surface, given by nearest_surface.
+fn detect_wall_collision(
+    ball_pos: glm::Vec2,
+    closest_point_in_wall: glm::Vec2,
+    nearest_surface: impl FnOnce(glm::Vec2) -> (glm::Vec2, glm::Vec2),
+) -> Option<glm::Vec2> {
     let distance_to_wall = glm::distance(&ball_pos, &closest_point_in_wall);
-    if distance_to_wall < BALL_R {
+    if distance_to_wall < MIN_WALL_DISTANCE {
+        let (surface, normal) = nearest_surface(ball_pos);
+        Some(surface - ball_pos + BALL_R * normal)
+    } else if distance_to_wall < BALL_R {
         Some((BALL_R - distance_to_wall) * glm::normalize(&(ball_pos - closest_point_in_wall)))
     } else {
         None
     }
 }
 
-fn apply_collision_response(
+// The nearest face to a point p inside a shape, given the faces as the distance from p and the
+// outward normal. Returns the point on the face closest to p and the normal.
+fn nearest_face(p: glm::Vec2, faces: &[(f32, glm::Vec2)]) -> (glm::Vec2, glm::Vec2) {
+    let (d, n) = faces
+        .iter()
+        .fold((f32::MAX, glm::vec2(1.0, 0.0)), |nearest, &(d, n)| {
+            if d < nearest.0 {
+                (d, n)
+            } else {
+                nearest
+            }
+        });
+    (p + d.max(0.0) * n, n)
+}
+
+// Point on the line segment from a to b closest to p
+fn closest_point_on_line(p: glm::Vec2, a: glm::Vec2, b: glm::Vec2) -> glm::Vec2 {
+    let ab = b - a;
+    a + glm::clamp_scalar(glm::dot(&(p - a), &ab) / glm::dot(&ab, &ab), 0.0, 1.0) * ab
+}
+
+// Velocity of the ball surface at the point touching the board, relative to the board
+fn contact_slip(v: glm::Vec2, w: glm::Vec3) -> glm::Vec2 {
+    v + BALL_R * glm::vec2(w.y, -w.x)
+}
+
+// Angular velocity of a ball rolling without slipping with velocity v, keeping the spin w_z
+// around the vertical axis as the board can't affect it
+fn rolling_spin(v: glm::Vec2, w_z: f32) -> glm::Vec3 {
+    glm::vec3(v.y / BALL_R, -v.x / BALL_R, w_z)
+}
+
+// Advance a solid sphere on the tilted board by dt seconds. gravity is the component of the
+// gravitational acceleration along the board, and mu the friction coefficient of the surface.
+fn roll(
+    v: glm::Vec2,
+    w: glm::Vec3,
+    gravity: glm::Vec2,
+    mu: f32,
+    dt: f32,
+) -> (glm::Vec2, glm::Vec3) {
+    let slip = contact_slip(v, w);
+    if glm::length(&slip) < MAX_ROLLING_SLIP {
+        // Rolling without slipping: part of the gravity goes to spinning the ball up
+        let v = v + ROLLING_INERTIA_FACTOR * gravity * dt;
+        return (v, rolling_spin(v, w.z));
+    }
+    // Sliding: kinetic friction at the contact point opposes the slip, changing both the
+    // velocity and the spin (torque r x F divided by the moment of inertia 2/5 m r^2)
+    let f = -mu * GRAVITY * glm::normalize(&slip);
+    let slide = |t: f32| {
+        (
+            v + (gravity + f) * t,
+            w + 5.0 / (2.0 * BALL_R) * glm::vec3(-f.y, f.x, 0.0) * t,
+        )
+    };
+    let (v1, w1) = slide(dt);
+    if glm::dot(&contact_slip(v1, w1), &slip) <= 0.0 {
+        // The slip vanished during the step. It changes linearly, so slide until it vanishes
+        // and roll for the rest of the step.
+        let slip_rate = (contact_slip(v1, w1) - slip) / dt;
+        let t = -glm::dot(&slip, &slip) / glm::dot(&slip_rate, &slip);
+        let (v1, w1) = slide(t);
+        let v1 = v1 + ROLLING_INERTIA_FACTOR * gravity * (dt - t);
+        (v1, rolling_spin(v1, w1.z))
+    } else {
+        (v1, w1)
+    }
+}
+
+// Friction between the ball and the walls it hit. dv is the velocity change of the collisions,
+// i.e. the normal impulse per unit mass. The tangential impulse tries to stop the ball surface
+// from slipping along the wall, exchanging speed along the wall with spin around the vertical
+// axis, but is limited by the wall friction.
+fn apply_wall_friction(v: glm::Vec2, w: glm::Vec3, dv: glm::Vec2) -> (glm::Vec2, glm::Vec3) {
+    let normal_impulse = glm::length(&dv);
+    if normal_impulse == 0.0 {
+        return (v, w);
+    }
+    let n = dv / normal_impulse;
+    let t = glm::vec2(-n.y, n.x);
+    let slip = glm::dot(&v, &t) - BALL_R * w.z;
+    let max_impulse = WALL_FRICTION * normal_impulse;
+    let impulse = glm::clamp_scalar(-2.0 / 7.0 * slip, -max_impulse, max_impulse);
+    (
+        v + impulse * t,
+        w - glm::vec3(0.0, 0.0, 5.0 * impulse / (2.0 * BALL_R)),
+    )
+}
+
+// Collision response for a wall moving with velocity wall_v at the point of collision
+fn apply_moving_collision_response(
     pv: (glm::Vec2, glm::Vec2),
     resp: Option<glm::Vec2>,
+    wall_v: glm::Vec2,
+    restitution: f32,
 ) -> (glm::Vec2, glm::Vec2) {
     match resp {
         Some(r) => {
             let dir = glm::normalize(&r);
+            let v_normal = glm::dot(&(pv.1 - wall_v), &dir);
             (
                 pv.0 + r,
-                // "Damped reflection" around the collision response direction
-                pv.1 - (1.0 + BOUNCE_COEFF) * glm::dot(&pv.1, &dir) * dir,
+                // "Damped reflection" around the collision response direction, in the frame of
+                // reference of the wall. A ball already moving away from the wall isn't
+                // reflected, as bumpers would otherwise push it back towards themselves.
+                if v_normal < 0.0 {
+                    pv.1 - (1.0 + restitution) * v_normal * dir
+                } else {
+                    pv.1
+                },
             )
         }
         None => pv,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // A flat, empty 400x400 board with the ball in the middle and the goal out of the way
+    fn empty_level() -> Level {
+        Level {
+            name: String::from("Test"),
+            meta: Metadata::default(),
+            translations: BTreeMap::new(),
+            assets: Assets::default(),
+            size: Size { w: 400.0, h: 400.0 },
+            start: Point { x: 200.0, y: 200.0 },
+            end: Rect {
+                pos: Point { x: 380.0, y: 380.0 },
+                size: Size { w: 20.0, h: 20.0 },
+            },
+            walls: Vec::new(),
+            posts: Vec::new(),
+            segments: Vec::new(),
+            arcs: Vec::new(),
+            moving_walls: Vec::new(),
+            rotating_bars: Vec::new(),
+            holes: Vec::new(),
+            trapdoors: Vec::new(),
+            checkpoints: Vec::new(),
+            zones: Vec::new(),
+            collectibles: Vec::new(),
+            par_time: None,
+            path: Vec::new(),
+        }
+    }
+
+    // Update the game frames times with frame_dt seconds between the updates
+    fn run(game: &mut Game, frames: u32, frame_dt: f32) {
+        let t0 = Instant::now();
+        game.update(t0);
+        for i in 1..=frames {
+            game.update(t0 + Duration::from_secs_f32(i as f32 * frame_dt));
+        }
+    }
+
+    #[test]
+    fn ball_pinned_by_moving_wall_stays_finite() {
+        let mut level = empty_level();
+        level.start = Point { x: 150.0, y: 200.0 };
+        level.walls.push(Wall {
+            rect: Rect {
+                pos: Point { x: 200.0, y: 0.0 },
+                size: Size { w: 20.0, h: 400.0 },
+            },
+            restitution: BOUNCE_COEFF,
+        });
+        // Closes the gap to the static wall completely and opens it again
+        level.moving_walls.push(MovingWall {
+            size: Size { w: 20.0, h: 400.0 },
+            keyframes: vec![
+                Keyframe {
+                    t: 0.0,
+                    pos: Point { x: 0.0, y: 0.0 },
+                },
+                Keyframe {
+                    t: 1.0,
+                    pos: Point { x: 180.0, y: 0.0 },
+                },
+                Keyframe {
+                    t: 2.0,
+                    pos: Point { x: 0.0, y: 0.0 },
+                },
+            ],
+            restitution: BOUNCE_COEFF,
+        });
+        let mut game = Game::new(&level, Config::new());
+        run(&mut game, 120, 1.0 / 60.0);
+        assert!(game.ball_pos.x.is_finite() && game.ball_pos.y.is_finite());
+        assert!(game.ball_v.x.is_finite() && game.ball_v.y.is_finite());
+    }
+
+    #[test]
+    fn fast_rotating_bar_pushes_ball_instead_of_passing_through() {
+        let mut level = empty_level();
+        level.start = Point { x: 300.0, y: 260.0 };
+        level.rotating_bars.push(RotatingBar {
+            center: Point { x: 200.0, y: 200.0 },
+            length: 300.0,
+            w: 10.0,
+            start_angle: 0.0,
+            angular_velocity: 4.0 * PI,
+            restitution: BOUNCE_COEFF,
+        });
+        let mut game = Game::new(&level, Config::new());
+        // Long frames, the bar sweeps over the ball within the first one
+        run(&mut game, 1, 0.05);
+        let bar = &game.level.rotating_bars[0];
+        let angle = bar.angle_at(game.time);
+        let r = glm::Vec2::from(&game.ball_pos) - glm::Vec2::from(&bar.center);
+        // The ball is still ahead of the bar in the direction of rotation
+        assert!(angle.cos() * r.y - angle.sin() * r.x > 0.0);
+    }
+
+    // The built-in level as it was before the format was versioned, with a field that version
+    // 1 ignored
+    fn level1_v1() -> String {
+        let mut data = json::parse(include_str!("level1.json")).unwrap();
+        data.remove("version");
+        data.remove("units");
+        data.remove("translations");
+        data["comment"] = "ignored".into();
+        data.dump()
+    }
+
+    #[test]
+    fn migrated_level_round_trips() {
+        let level = Level::from_json(&level1_v1()).unwrap();
+        assert!(level.translations.is_empty());
+        assert_eq!(
+            level.walls.len(),
+            Level::from_json(include_str!("level1.json"))
+                .unwrap()
+                .walls
+                .len()
+        );
+        assert_eq!(Level::from_json(&level.to_json()).unwrap(), level);
+    }
+
+    // Level 1 in the current format with the value at key replaced
+    fn level1_with(key: &str, val: json::JsonValue) -> String {
+        let mut data = json::parse(include_str!("level1.json")).unwrap();
+        data[key] = val;
+        data.dump()
+    }
+
+    #[test]
+    fn invalid_levels_are_errors() {
+        let invalid = [
+            String::from("{"),
+            level1_with("comment", "unknown field".into()),
+            level1_with("version", (LEVEL_FORMAT_VERSION + 1).into()),
+            level1_with("units", json::object! { length: "mm" }),
+            level1_with("meta", json::object! { difficulty: "impossible" }),
+            level1_with(
+                "zones",
+                json::array![json::object! {
+                    pos: json::object! { x: 0, y: 0 },
+                    size: json::object! { w: 10, h: 10 },
+                    surface: "lava"
+                }],
+            ),
+            level1_with(
+                "moving_walls",
+                json::array![
+                    json::object! { size: json::object! { w: 10, h: 10 }, keyframes: json::array![] }
+                ],
+            ),
+            level1_with("size", json::object! { w: 100 }),
+            level1_with("size", json::object! { w: 0, h: 0 }),
+            level1_with(
+                "posts",
+                json::array![json::object! { pos: json::object! { x: 50, y: 50 }, r: 0 }],
+            ),
+            level1_with(
+                "segments",
+                json::array![json::object! {
+                    a: json::object! { x: 50, y: 50 },
+                    b: json::object! { x: 50, y: 50 },
+                    w: 10
+                }],
+            ),
+            level1_with(
+                "arcs",
+                json::array![json::object! {
+                    center: json::object! { x: 50, y: 50 },
+                    r: 40,
+                    w: -10,
+                    start_angle: 0,
+                    end_angle: 90
+                }],
+            ),
+            level1_with(
+                "rotating_bars",
+                json::array![json::object! {
+                    center: json::object! { x: 50, y: 50 },
+                    length: 0,
+                    w: 10,
+                    speed: 90
+                }],
+            ),
+            level1_with(
+                "trapdoors",
+                json::array![json::object! {
+                    pos: json::object! { x: 50, y: 50 },
+                    period: 0,
+                    open: 1
+                }],
+            ),
+        ];
+        for json in invalid.iter() {
+            assert!(Level::from_json(json).is_err(), "{}", json);
+        }
+    }
+
+    // Roll the ball from the left at an obstacle in the middle of the board for a second
+    fn roll_at_obstacle(level: &mut Level) -> (Game, Vec<Event>) {
+        level.start = Point { x: 60.0, y: 200.0 };
+        let mut game = Game::new(level, Config::new());
+        game.ball_v = Velocity { x: 300.0, y: 0.0 };
+        run(&mut game, 60, 1.0 / 60.0);
+        let events = game.drain_events();
+        (game, events)
+    }
+
+    fn assert_bounced_back(game: &Game, events: &[Event], face_x: f32) {
+        assert!(game.ball_v.x < 0.0, "{:?}", game.ball_v);
+        assert!(game.ball_pos.x + BALL_R <= face_x, "{:?}", game.ball_pos);
+        assert!(events
+            .iter()
+            .any(|e| matches!(e.kind, EventKind::WallImpact { .. })));
+    }
+
+    #[test]
+    fn ball_bounces_off_post() {
+        let mut level = empty_level();
+        level.posts.push(Post {
+            pos: Point { x: 200.0, y: 200.0 },
+            r: 20.0,
+            restitution: BOUNCE_COEFF,
+        });
+        let (game, events) = roll_at_obstacle(&mut level);
+        assert_bounced_back(&game, &events, 180.0);
+    }
+
+    #[test]
+    fn ball_bounces_off_segment() {
+        let mut level = empty_level();
+        level.segments.push(Segment {
+            a: Point { x: 200.0, y: 150.0 },
+            b: Point { x: 200.0, y: 250.0 },
+            w: 10.0,
+            restitution: BOUNCE_COEFF,
+        });
+        let (game, events) = roll_at_obstacle(&mut level);
+        assert_bounced_back(&game, &events, 195.0);
+    }
+
+    #[test]
+    fn ball_bounces_off_arc() {
+        // Opens to the right, the ball hits the outside of its leftmost point
+        let mut level = empty_level();
+        level.arcs.push(Arc {
+            center: Point { x: 200.0, y: 200.0 },
+            r: 60.0,
+            w: 10.0,
+            start_angle: PI / 2.0,
+            end_angle: 3.0 * PI / 2.0,
+            restitution: BOUNCE_COEFF,
+        });
+        let (game, events) = roll_at_obstacle(&mut level);
+        assert_bounced_back(&game, &events, 135.0);
+    }
+
+    #[test]
+    fn diagonal_segment_deflects_ball() {
+        // At 45 degrees, the ball rolling in the x direction is turned along the segment
+        let mut level = empty_level();
+        level.segments.push(Segment {
+            a: Point { x: 150.0, y: 150.0 },
+            b: Point { x: 250.0, y: 250.0 },
+            w: 10.0,
+            restitution: BOUNCE_COEFF,
+        });
+        let (game, _) = roll_at_obstacle(&mut level);
+        assert!(game.ball_v.y > 0.0, "{:?}", game.ball_v);
+        assert!(game.ball_v.y > game.ball_v.x.abs(), "{:?}", game.ball_v);
+    }
+
+    // Speed of the ball after rolling for a second on a board covered with the given surface
+    fn speed_after_rolling_on(surface: Surface) -> f32 {
+        let mut level = empty_level();
+        level.start = Point { x: 100.0, y: 200.0 };
+        level.zones.push(Zone {
+            rect: Rect {
+                pos: Point { x: 0.0, y: 0.0 },
+                size: level.size,
+            },
+            surface,
+        });
+        let mut game = Game::new(&level, Config::new());
+        game.ball_v = Velocity { x: 100.0, y: 0.0 };
+        run(&mut game, 60, 1.0 / 60.0);
+        glm::length(&glm::Vec2::from(&game.ball_v))
+    }
+
+    #[test]
+    fn ball_decelerates_more_slowly_on_ice_than_on_carpet() {
+        let ice = speed_after_rolling_on(Surface::Ice);
+        let carpet = speed_after_rolling_on(Surface::Carpet);
+        assert!(ice > carpet, "ice {} carpet {}", ice, carpet);
+        let expected_ice = 100.0 - ROLLING_RESISTANCE * Surface::Ice.friction();
+        let expected_carpet = 100.0 - ROLLING_RESISTANCE * Surface::Carpet.friction();
+        assert!((ice - expected_ice).abs() < 0.1, "ice {}", ice);
+        assert!((carpet - expected_carpet).abs() < 0.1, "carpet {}", carpet);
+    }
+
+    #[test]
+    fn ball_stops_on_sticky_surface() {
+        assert_eq!(speed_after_rolling_on(Surface::Sticky), 0.0);
+    }
+
+    #[test]
+    fn zones_of_every_surface_round_trip() {
+        let mut level = empty_level();
+        for (i, surface) in [
+            Surface::Board,
+            Surface::Ice,
+            Surface::Carpet,
+            Surface::Sticky,
+        ]
+        .iter()
+        .enumerate()
+        {
+            level.zones.push(Zone {
+                rect: Rect {
+                    pos: Point {
+                        x: 100.0 * i as f32,
+                        y: 0.0,
+                    },
+                    size: Size { w: 100.0, h: 100.0 },
+                },
+                surface: *surface,
+            });
+        }
+        assert_eq!(Level::from_json(&level.to_json()).unwrap(), level);
+    }
+
+    #[test]
+    fn bumper_returns_more_speed_than_it_received() {
+        let mut level = empty_level();
+        level.walls.push(Wall {
+            rect: Rect {
+                pos: Point { x: 300.0, y: 100.0 },
+                size: Size { w: 20.0, h: 200.0 },
+            },
+            restitution: 1.5,
+        });
+        let mut game = Game::new(&level, Config::new());
+        game.ball_v = Velocity { x: 200.0, y: 0.0 };
+        run(&mut game, 36, 1.0 / 60.0);
+        assert!(game.ball_v.x < -200.0, "{:?}", game.ball_v);
+    }
+
+    fn realistic_game(level: &Level) -> Game {
+        let mut cfg = Config::new();
+        cfg.physics = PhysicsModel::Realistic;
+        Game::new(level, cfg)
+    }
+
+    #[test]
+    fn rolling_ball_accelerates_at_five_sevenths_of_gravity() {
+        let mut game = realistic_game(&empty_level());
+        game.angle_x = MAX_ANGLE / 2.0;
+        let dt = 0.1;
+        run(&mut game, 10, dt / 10.0);
+        let a = game.ball_v.x / dt;
+        let expected = 5.0 / 7.0 * GRAVITY * game.angle_x.sin() - ROLLING_RESISTANCE;
+        assert!((a - expected).abs() < 0.01 * expected, "{} {}", a, expected);
+        assert_eq!(game.ball_v.y, 0.0);
+        // Rolling without slipping
+        assert!(
+            (game.ball_w.y + game.ball_v.x / BALL_R).abs() < 1e-3,
+            "{:?}",
+            game.ball_w
+        );
+    }
+
+    #[test]
+    fn sliding_ball_starts_rolling() {
+        // Thrown without spin, board friction slows the ball down and spins it up until the
+        // contact point stops slipping, after which it keeps 5/7 of its speed
+        let mut game = realistic_game(&empty_level());
+        game.ball_pos = Point { x: 60.0, y: 200.0 };
+        game.ball_v = Velocity { x: 100.0, y: 0.0 };
+        run(&mut game, 10, 0.1 / 10.0);
+        let slip = contact_slip(glm::Vec2::from(&game.ball_v), glm::Vec3::from(&game.ball_w));
+        assert!(glm::length(&slip) < MAX_ROLLING_SLIP, "{:?}", slip);
+        assert!(
+            (game.ball_v.x - 5.0 / 7.0 * 100.0).abs() < 2.0,
+            "{:?}",
+            game.ball_v
+        );
+    }
+}
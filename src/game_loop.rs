@@ -12,67 +12,96 @@ use winit::event_loop::ControlFlow;
 use winit::window::Window;
 
 use crate::ai;
+use crate::audio;
+use crate::editor;
+use crate::flow;
 use crate::game;
 use crate::graphics;
+use crate::haptics;
+use crate::locale;
 
 type WinitEvent<'a> = Event<'a, ()>;
 
+/// Creates the scene for a level. Used again whenever the level is edited.
+pub type SceneFactory = Box<dyn Fn(&graphics::Instance, &game::Level) -> graphics::Scene>;
+
 pub struct GameLoop {
     window: Window,
     level: game::Level,
+    game_cfg: game::Config,
     game: game::Game,
     gfx: graphics::Instance,
     ui: Ui,
+    create_scene: SceneFactory,
     scene: graphics::Scene,
-    board_node_id: graphics::NodeId,
-    ball_node_id: graphics::NodeId,
+    nodes: SceneNodes,
+    last_frame_t: Instant,
     static_camera: bool,
-    state: State,
+    state: flow::State,
+    countdown_tilt: glm::Vec2, // board angles when the countdown started
     last_cursor_pos: Option<PhysicalPosition<f64>>,
     double_tap_start_t: Option<Instant>,
     last_touch_pos: Option<PhysicalPosition<f64>>,
     timer: Stopwatch,
+    splits: Vec<f32>, // score time at each AI path point passed in the current game
+    best_splits: Option<(Vec<f32>, f32)>, // splits and completion time of the fastest won game
     stats: Option<Stats>,
     ai: Option<Box<dyn ai::GameAi>>,
+    audio: audio::Audio,
+    haptics: Option<Box<dyn haptics::Haptics>>,
+    haptics_enabled: bool,
+    accessibility: Accessibility,
+    camera_target: Option<(glm::Vec3, Instant)>, // smoothed follow camera target in reduced motion
+    editor: Option<editor::Editor>, // Kept between playtests to preserve the undo history
+    level_file: String,             // Where the editor saves the level
 }
 
 impl GameLoop {
     pub fn new(
         window: Window,
         level: game::Level,
+        game_cfg: game::Config,
         gfx: graphics::Instance,
         width_pixels: u32,
         height_pixels: u32,
-        scene: graphics::Scene,
-        board_node_id: graphics::NodeId,
-        ball_node_id: graphics::NodeId,
+        create_scene: SceneFactory,
+        level_file: String,
         static_camera: bool,
         print_stats: bool,
         mut ai: Option<Box<dyn ai::GameAi>>,
+        haptics: Option<Box<dyn haptics::Haptics>>,
     ) -> GameLoop {
-        let game = game::Game::new(&level);
+        let game = game::Game::new(&level, game_cfg);
+        let scene = create_scene(&gfx, &level);
+        let nodes = SceneNodes::find(&scene, &level);
         if let Some(ai) = &mut ai {
             ai.init(&level);
         }
         GameLoop {
             window,
             level,
+            game_cfg,
             game,
             gfx,
             ui: Ui::new(
                 width_pixels,
                 height_pixels,
                 width_pixels as f32 / 800.0, // Scale the UI to always take the same relative amount of the available space
+                haptics.is_some(),
             ),
+            create_scene,
             scene,
-            board_node_id,
-            ball_node_id,
+            nodes,
+            last_frame_t: Instant::now(),
             static_camera,
-            state: State::GameInProgress,
+            state: flow::State::MainMenu,
+            countdown_tilt: glm::zero(),
             last_cursor_pos: None,
             double_tap_start_t: None,
             last_touch_pos: None,
-            timer: Stopwatch::start_new(),
+            timer: Stopwatch::new(),
+            splits: Vec::new(),
+            best_splits: None,
             stats: if print_stats {
                 Some(Stats {
                     frame_count: 0,
@@ -82,6 +111,13 @@ impl GameLoop {
                 None
             },
             ai,
+            audio: audio::Audio::new(),
+            haptics,
+            haptics_enabled: true,
+            accessibility: Accessibility::new(),
+            camera_target: None,
+            editor: None,
+            level_file,
         }
     }
 
@@ -109,38 +145,96 @@ impl GameLoop {
         }
 
         return match self.state {
-            State::GameInProgress => ControlFlow::Poll,
-            State::GamePaused => ControlFlow::Wait,
+            state if state.is_animated() => ControlFlow::Poll,
+            // Keep rendering until the particle effects have faded, e.g. after winning
+            _ if self.scene.has_particles() => ControlFlow::Poll,
+            _ => ControlFlow::Wait,
         };
     }
 
     fn do_frame(&mut self) -> bool {
+        let now = Instant::now();
+        self.scene
+            .update_particles(now.duration_since(self.last_frame_t).as_secs_f32());
+        self.last_frame_t = now;
+        self.transition(flow::Event::Tick);
         match self.state {
-            State::GameInProgress => {
-                let now = Instant::now();
+            flow::State::Playing => {
                 if let Some(ai) = &mut self.ai {
                     let next_move = ai.next_move(&self.game, now);
                     self.game.rotate_x(next_move.x);
                     self.game.rotate_y(next_move.y);
                 }
                 self.update_frame_stats(now);
-                let ball_pos_delta = self.update_game(now);
-                if !self.update_scene(now, ball_pos_delta) {
+                let ball_rotation = self.update_game(now);
+                let events = self.game.drain_events();
+                if let Some(ai) = &mut self.ai {
+                    for event in events.iter() {
+                        ai.handle_event(&self.game.level, event);
+                    }
+                }
+                self.update_splits(&events);
+                self.audio.update(&self.game, &events);
+                if let Some(haptics) = &mut self.haptics {
+                    if self.haptics_enabled {
+                        haptics::handle_events(haptics.as_mut(), &events);
+                    }
+                }
+                if !self.update_scene(now, ball_rotation, &events) {
                     // Scene is not alive anymore, meaning the game has been won/lost.
-                    // Pause the game to stop the timer and show the menu.
-                    self.pause_game();
+                    // Show the results, which also stops the timer.
+                    self.transition(flow::Event::Finished);
+                }
+            }
+            flow::State::LevelIntro { .. } | flow::State::Countdown { .. } => {
+                if let Some(f) = self.state.level_out(now) {
+                    self.game.angle_x = (1.0 - f) * self.countdown_tilt.x;
+                    self.game.angle_y = (1.0 - f) * self.countdown_tilt.y;
                 }
+                self.update_board();
+                self.update_dynamic_elements();
+                let ball_pos = self.ball_pos_in_scene();
+                self.update_ball(ball_pos, glm::zero());
+                self.update_camera(ball_pos, now);
             }
-            State::GamePaused => (),
+            flow::State::MainMenu
+            | flow::State::Paused
+            | flow::State::Results
+            | flow::State::Editing => (),
         }
-        let ui_output =
-            self.ui
-                .update(&self.gfx, self.timer.elapsed(), self.state, self.game.state);
-        self.gfx.render_scene(&self.scene, &ui_output.objects);
+        let scene = &self.scene;
+        let level_size = self.level.size;
+        let ui_output = self.ui.update(
+            &self.gfx,
+            self.timer.elapsed(),
+            self.state,
+            now,
+            &self.level,
+            &self.game,
+            self.latest_split(),
+            match self.state {
+                flow::State::Editing => self.editor.as_mut(),
+                _ => None,
+            },
+            &|p: &game::Point| scene.world_to_screen(&level_to_scene(p, level_size)),
+        );
+        if let flow::State::Editing = self.state {
+            // Includes the edits made in the editor window during this frame
+            self.update_editor_scene();
+        }
+        self.gfx.render_scene(&mut self.scene, &ui_output.objects);
         for action in ui_output.actions.iter() {
             match action {
-                UiAction::ResumeGame => self.resume_game(),
+                UiAction::StartGame => self.start_game(),
+                UiAction::ResumeGame => self.transition(flow::Event::Resume),
                 UiAction::RestartLevel => self.restart_level(),
+                UiAction::MainMenu => self.transition(flow::Event::MainMenu),
+                UiAction::SetVolumes { master, sfx } => self.audio.set_volumes(*master, *sfx),
+                UiAction::SetHaptics(enabled) => self.haptics_enabled = *enabled,
+                UiAction::SetAccessibility(accessibility) => self.set_accessibility(*accessibility),
+                UiAction::EditLevel => self.start_editing(),
+                UiAction::SaveLevel => self.save_level(),
+                UiAction::PlayLevel => self.play_edited_level(),
                 UiAction::Quit => return false,
             }
         }
@@ -151,10 +245,7 @@ impl GameLoop {
         // When the application is suspended (on mobile platforms), the window object becomes
         // unusable, so reset the handle used in graphics code.
         self.gfx.set_window(None as Option<&winit::window::Window>);
-        match self.state {
-            State::GameInProgress => self.pause_game(),
-            State::GamePaused => (),
-        }
+        self.transition(flow::Event::Pause);
     }
 
     fn resumed(&mut self) {
@@ -163,11 +254,16 @@ impl GameLoop {
 
     fn mouse_click(&mut self, state: &ElementState) {
         match self.state {
-            State::GameInProgress => match state {
+            flow::State::Playing => match state {
                 ElementState::Pressed => self.pause_game(),
                 ElementState::Released => (),
             },
-            State::GamePaused => {
+            flow::State::LevelIntro { .. } => match state {
+                ElementState::Pressed => self.transition(flow::Event::Skip),
+                ElementState::Released => (),
+            },
+            flow::State::Countdown { .. } => (),
+            flow::State::MainMenu | flow::State::Paused | flow::State::Results => {
                 if let Some(pos) = self.last_cursor_pos {
                     self.ui.click(
                         pos.x as f32,
@@ -179,20 +275,22 @@ impl GameLoop {
                     );
                 }
             }
+            flow::State::Editing => {
+                if let Some(pos) = self.last_cursor_pos {
+                    self.editor_pointer_button(pos, *state == ElementState::Pressed);
+                }
+            }
         }
     }
 
     // Any mouse movement -> control game
     fn mouse_moved(&mut self, delta: &(f64, f64)) {
-        match self.state {
-            State::GameInProgress => {
-                const ROTATE_COEFF: f32 = 0.0002;
-                if self.ai.is_none() {
-                    self.game.rotate_x(ROTATE_COEFF * delta.0 as f32);
-                    self.game.rotate_y(ROTATE_COEFF * delta.1 as f32);
-                }
+        if let flow::State::Playing = self.state {
+            const ROTATE_COEFF: f32 = 0.0002;
+            if self.ai.is_none() {
+                self.game.rotate_x(ROTATE_COEFF * delta.0 as f32);
+                self.game.rotate_y(ROTATE_COEFF * delta.1 as f32);
             }
-            State::GamePaused => (),
         }
     }
 
@@ -200,8 +298,13 @@ impl GameLoop {
     fn cursor_moved(&mut self, pos: &PhysicalPosition<f64>) {
         self.last_cursor_pos = Some(*pos);
         match self.state {
-            State::GameInProgress => (),
-            State::GamePaused => self.ui.cursor_moved(pos.x as f32, pos.y as f32),
+            flow::State::MainMenu | flow::State::Paused | flow::State::Results => {
+                self.ui.cursor_moved(pos.x as f32, pos.y as f32)
+            }
+            flow::State::Editing => self.editor_pointer_moved(*pos),
+            flow::State::LevelIntro { .. }
+            | flow::State::Countdown { .. }
+            | flow::State::Playing => (),
         }
     }
 
@@ -214,24 +317,40 @@ impl GameLoop {
         }
     }
 
+    // Returns the rotation of the ball during the update in scene coordinates, as the rotation
+    // axis scaled by the angle
     fn update_game(&mut self, now: Instant) -> glm::Vec3 {
-        let p0 = self.game.ball_pos;
+        let t0 = self.game.time;
         self.game.update(now);
-        return glm::vec3(
-            self.game.ball_pos.x - p0.x,
-            0.0,
-            self.game.ball_pos.y - p0.y,
-        );
+        // The game's z-axis points into the board, i.e. down in the scene
+        let w = &self.game.ball_w;
+        (self.game.time - t0) * glm::vec3(w.x, -w.z, w.y)
     }
 
     // Return true if the scene is still alive, false if it has reached a static state
-    fn update_scene(&mut self, now: Instant, ball_pos_delta: glm::Vec3) -> bool {
+    fn update_scene(
+        &mut self,
+        now: Instant,
+        ball_rotation: glm::Vec3,
+        events: &[game::Event],
+    ) -> bool {
+        self.update_collectibles(events);
+        self.emit_particles(events);
         match self.game.state {
             game::State::InProgress => {
+                self.scene.set_visible(self.nodes.ball, true); // Hidden if the ball was respawned
                 self.update_board();
+                self.update_dynamic_elements();
                 let ball_pos = self.ball_pos_in_scene();
-                self.update_ball(ball_pos, ball_pos_delta);
-                self.update_camera(ball_pos);
+                self.update_ball(ball_pos, ball_rotation);
+                self.update_camera(ball_pos, now);
+                true
+            }
+            game::State::Fell { hole, t_fell } => {
+                // The game respawns the ball after a delay, keep the board moving until then
+                self.update_board();
+                self.update_dynamic_elements();
+                self.update_ball_game_lost(now, t_fell, hole);
                 true
             }
             game::State::Lost { hole, t_lost } => {
@@ -259,13 +378,105 @@ impl GameLoop {
     }
 
     fn update_board(&mut self) {
-        self.scene.get_node(self.board_node_id).set_rotation(
+        self.scene.get_node(self.nodes.board).set_rotation(
             self.game.angle_y,
             0.0,
             -self.game.angle_x,
         );
     }
 
+    // Move the scene nodes of the moving walls, rotating bars and trapdoors to match the game
+    fn update_dynamic_elements(&mut self) {
+        let t = self.game.time;
+        let level = &self.game.level;
+        for (wall, id) in level
+            .moving_walls
+            .iter()
+            .zip(self.nodes.moving_walls.iter())
+        {
+            let rect = wall.rect_at(t);
+            self.scene.get_node(*id).set_position(
+                rect.pos.x + rect.size.w / 2.0 - level.size.w / 2.0,
+                0.0,
+                rect.pos.y + rect.size.h / 2.0 - level.size.h / 2.0,
+            );
+        }
+        for (bar, id) in level
+            .rotating_bars
+            .iter()
+            .zip(self.nodes.rotating_bars.iter())
+        {
+            // Positive level angles turn from x-axis towards z-axis, i.e. negative around y-axis
+            self.scene
+                .get_node(*id)
+                .set_rotation(0.0, -bar.angle_at(t), 0.0);
+        }
+        for (trapdoor, id) in level.trapdoors.iter().zip(self.nodes.trapdoor_lids.iter()) {
+            self.scene.set_visible(*id, !trapdoor.is_open_at(t));
+        }
+    }
+
+    // Hide the collectibles picked up in this frame's events and spin the rest
+    fn update_collectibles(&mut self, events: &[game::Event]) {
+        for event in events.iter() {
+            if let game::EventKind::Collectible(i) = event.kind {
+                self.scene.set_visible(self.nodes.collectibles[i], false);
+            }
+        }
+        const SPIN_SPEED: f32 = 2.0; // rad/s
+        for (id, collected) in self
+            .nodes
+            .collectibles
+            .iter()
+            .zip(self.game.collected.iter())
+        {
+            if !collected {
+                self.scene
+                    .get_node(*id)
+                    .set_rotation(0.0, SPIN_SPEED * self.game.time, 0.0);
+            }
+        }
+    }
+
+    // Start the particle effects for this frame's events. There are none in reduced motion.
+    fn emit_particles(&mut self, events: &[game::Event]) {
+        if self.accessibility.reduced_motion {
+            return;
+        }
+        // Only hard impacts raise dust, the amount growing with the impulse
+        const DUST_MIN_IMPULSE: f32 = 5.0 * game::BALL_R;
+        const DUST_PER_IMPULSE: f32 = 1.0 / game::BALL_R;
+        let level_size = self.game.level.size;
+        for event in events.iter() {
+            match event.kind {
+                game::EventKind::WallImpact { pos, impulse }
+                | game::EventKind::EdgeHit { pos, impulse }
+                    if impulse >= DUST_MIN_IMPULSE =>
+                {
+                    self.scene.get_node(self.nodes.dust_emitter).set_position(
+                        pos.x - level_size.w / 2.0,
+                        game::BALL_R,
+                        pos.y - level_size.h / 2.0,
+                    );
+                    self.scene.emit_particles(
+                        self.nodes.dust_emitter,
+                        (DUST_PER_IMPULSE * impulse) as usize,
+                    );
+                }
+                game::EventKind::Goal => self.scene.emit_particles(self.nodes.goal_emitter, 150),
+                game::EventKind::Fell { hole, .. } => {
+                    self.scene.get_node(self.nodes.hole_emitter).set_position(
+                        hole.x - level_size.w / 2.0,
+                        0.0,
+                        hole.y - level_size.h / 2.0,
+                    );
+                    self.scene.emit_particles(self.nodes.hole_emitter, 30);
+                }
+                _ => (),
+            }
+        }
+    }
+
     fn ball_pos_in_scene(&self) -> glm::Vec3 {
         glm::vec3(
             self.game.ball_pos.x - self.game.level.size.w / 2.0,
@@ -274,36 +485,45 @@ impl GameLoop {
         )
     }
 
-    fn update_ball(&mut self, ball_pos: glm::Vec3, ball_pos_delta: glm::Vec3) {
+    fn update_ball(&mut self, ball_pos: glm::Vec3, ball_rotation: glm::Vec3) {
         self.scene
-            .get_node(self.ball_node_id)
+            .get_node(self.nodes.ball)
             .set_position(ball_pos.x, ball_pos.y, ball_pos.z);
-        let axis_world_space = glm::normalize(&glm::rotate_vec3(
-            &ball_pos_delta,
-            std::f32::consts::PI / 2.0,
-            &glm::vec3(0.0, 1.0, 0.0),
-        ));
-        self.scene
-            .get_node(self.ball_node_id)
-            .rotate_in_world_space(
-                glm::length(&ball_pos_delta) / game::BALL_R,
+        let angle = glm::length(&ball_rotation);
+        if angle > 0.0 {
+            let axis_world_space = ball_rotation / angle;
+            self.scene.get_node(self.nodes.ball).rotate_in_world_space(
+                angle,
                 axis_world_space.x,
                 axis_world_space.y,
                 axis_world_space.z,
             );
+        }
     }
 
-    fn update_camera(&mut self, ball_pos: glm::Vec3) {
-        if !self.static_camera {
-            self.scene.look_at(
-                ball_pos.x,
-                40.0 * game::BALL_R,
-                ball_pos.z + 10.0 * game::BALL_R,
-                ball_pos.x,
-                0.0,
-                ball_pos.z,
-            );
+    // The camera follows the ball from above. Normally it is locked to the ball, so the view
+    // jerks with every bounce. In reduced motion the camera eases towards the ball instead.
+    fn update_camera(&mut self, ball_pos: glm::Vec3, now: Instant) {
+        const SMOOTHING_TIME: f32 = 0.3; // seconds for the camera to cover ~63 % of the distance
+        if self.static_camera {
+            return;
         }
+        let target = match self.camera_target {
+            Some((prev, t)) if self.accessibility.reduced_motion => {
+                let dt = now.duration_since(t).as_secs_f32();
+                glm::lerp(&prev, &ball_pos, 1.0 - (-dt / SMOOTHING_TIME).exp())
+            }
+            _ => ball_pos,
+        };
+        self.camera_target = Some((target, now));
+        self.scene.look_at(
+            target.x,
+            40.0 * game::BALL_R,
+            target.z + 10.0 * game::BALL_R,
+            target.x,
+            0.0,
+            target.z,
+        );
     }
 
     // Return true if the animation is in progress, false if it's done
@@ -319,7 +539,7 @@ impl GameLoop {
             hole_pos,
         ) {
             Some((x, y, z)) => {
-                self.scene.get_node(self.ball_node_id).set_position(
+                self.scene.get_node(self.nodes.ball).set_position(
                     x - self.game.level.size.w / 2.0,
                     z,
                     y - self.game.level.size.h / 2.0,
@@ -328,11 +548,7 @@ impl GameLoop {
             }
             None => {
                 // Hide the ball after the animation is done
-                self.scene.get_node(self.ball_node_id).set_position(
-                    10.0 * self.game.level.size.w,
-                    0.0,
-                    0.0,
-                );
+                self.scene.set_visible(self.nodes.ball, false);
                 false
             }
         }
@@ -340,7 +556,7 @@ impl GameLoop {
 
     fn touch_started(&mut self, pos: PhysicalPosition<f64>) {
         match self.state {
-            State::GameInProgress => {
+            flow::State::Playing => {
                 let now = Instant::now();
                 self.double_tap_start_t = match self.double_tap_start_t {
                     Some(t0) if now.duration_since(t0) < Duration::from_millis(400) => {
@@ -351,13 +567,21 @@ impl GameLoop {
                 };
                 self.last_touch_pos = Some(pos);
             }
-            State::GamePaused => self.ui.click(pos.x as f32, pos.y as f32, true),
+            flow::State::LevelIntro { .. } => self.transition(flow::Event::Skip),
+            flow::State::Countdown { .. } => (),
+            flow::State::MainMenu | flow::State::Paused | flow::State::Results => {
+                self.ui.click(pos.x as f32, pos.y as f32, true)
+            }
+            flow::State::Editing => {
+                self.editor_pointer_moved(pos); // Touches don't move the pointer before pressing
+                self.editor_pointer_button(pos, true);
+            }
         }
     }
 
     fn swipe(&mut self, pos: PhysicalPosition<f64>) {
         match self.state {
-            State::GameInProgress => {
+            flow::State::Playing => {
                 if let Some(p0) = self.last_touch_pos {
                     const ROTATE_COEFF: f32 = 0.0004;
                     if self.ai.is_none() {
@@ -367,27 +591,58 @@ impl GameLoop {
                 }
                 self.last_touch_pos = Some(pos);
             }
-            State::GamePaused => self.ui.cursor_moved(pos.x as f32, pos.y as f32),
+            flow::State::MainMenu | flow::State::Paused | flow::State::Results => {
+                self.ui.cursor_moved(pos.x as f32, pos.y as f32)
+            }
+            flow::State::Editing => self.editor_pointer_moved(pos),
+            flow::State::LevelIntro { .. } | flow::State::Countdown { .. } => (),
         }
     }
 
     fn touch_ended(&mut self, pos: PhysicalPosition<f64>) {
         match self.state {
-            State::GameInProgress => (),
-            State::GamePaused => self.ui.click(pos.x as f32, pos.y as f32, false),
+            flow::State::MainMenu | flow::State::Paused | flow::State::Results => {
+                self.ui.click(pos.x as f32, pos.y as f32, false)
+            }
+            flow::State::Editing => self.editor_pointer_button(pos, false),
+            flow::State::LevelIntro { .. }
+            | flow::State::Countdown { .. }
+            | flow::State::Playing => (),
         }
     }
 
     fn double_tap(&mut self) {
-        match self.state {
-            State::GameInProgress => self.pause_game(),
-            State::GamePaused => (),
+        if let flow::State::Playing = self.state {
+            self.pause_game();
         }
     }
 
     fn pause_game(&mut self) {
         println!("Pausing game");
-        self.state = State::GamePaused;
+        self.transition(flow::Event::Pause);
+    }
+
+    // Move to the next state of the application flow, and do the side effects of leaving the
+    // previous state and entering the new one
+    fn transition(&mut self, event: flow::Event) {
+        let prev = self.state;
+        self.state = prev.next(event, Instant::now());
+        if self.state == prev {
+            return;
+        }
+        if let flow::State::Playing = prev {
+            self.stop_playing();
+        }
+        match self.state {
+            flow::State::Countdown { .. } => {
+                self.countdown_tilt = glm::vec2(self.game.angle_x, self.game.angle_y)
+            }
+            flow::State::Playing => self.start_playing(),
+            _ => (),
+        }
+    }
+
+    fn stop_playing(&mut self) {
         self.game.reset_time();
         self.timer.stop();
         self.double_tap_start_t = None;
@@ -395,6 +650,7 @@ impl GameLoop {
         if let Some(ai) = &mut self.ai {
             ai.pause();
         }
+        self.audio.stop_rolling();
         #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
         {
             self.window.set_cursor_visible(true);
@@ -402,9 +658,9 @@ impl GameLoop {
         }
     }
 
-    fn resume_game(&mut self) {
-        self.state = State::GameInProgress;
+    fn start_playing(&mut self) {
         self.timer.start();
+        self.audio.resume();
         #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
         {
             self.window.set_cursor_visible(false);
@@ -412,14 +668,224 @@ impl GameLoop {
         }
     }
 
-    fn restart_level(&mut self) {
-        self.game = game::Game::new(&self.level);
-        self.timer = Stopwatch::start_new();
+    // Reset the game to the start of the level. The game starts after the countdown.
+    fn reset_game(&mut self) {
+        self.game = game::Game::new(&self.level, self.game_cfg);
+        self.scene.set_visible(self.nodes.ball, true); // Hidden if the previous game was lost
+        for id in self.nodes.collectibles.iter() {
+            self.scene.set_visible(*id, true);
+        }
+        self.timer = Stopwatch::new();
+        self.splits.clear();
+        self.camera_target = None;
         if let Some(ai) = &mut self.ai {
             ai.init(&self.level);
         }
-        self.resume_game(); // Ensure the game is in progress
     }
+
+    fn set_accessibility(&mut self, accessibility: Accessibility) {
+        self.accessibility = accessibility;
+        self.scene.set_high_contrast(accessibility.high_contrast);
+        self.game_cfg.time_scale = accessibility.game_speed;
+        self.game.time_scale = accessibility.game_speed;
+    }
+
+    // Record the split times of this frame's events, using the same clock and time adjustments
+    // as the score. A won game faster than the personal best becomes the new personal best. Games
+    // played at a changed game speed don't count for the personal best.
+    fn update_splits(&mut self, events: &[game::Event]) {
+        let time = self.game.score(self.timer.elapsed().as_secs_f32()).time;
+        for event in events.iter() {
+            match event.kind {
+                game::EventKind::Waypoint(_) => self.splits.push(time),
+                game::EventKind::Goal if self.game.time_scale == 1.0 => {
+                    if self
+                        .best_splits
+                        .as_ref()
+                        .map_or(true, |(_, best)| time < *best)
+                    {
+                        println!("New personal best {:.2} s", time);
+                        self.best_splits = Some((self.splits.clone(), time));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // The split at the latest path point passed after the start
+    fn latest_split(&self) -> Option<Split> {
+        match self.splits.len() {
+            0 | 1 => None,
+            n => Some(Split {
+                waypoint: n - 1,
+                time: self.splits[n - 1],
+                best: self
+                    .best_splits
+                    .as_ref()
+                    .filter(|_| self.game.time_scale == 1.0)
+                    .and_then(|(splits, _)| splits.get(n - 1).copied()),
+            }),
+        }
+    }
+
+    // Start the level from the main menu, showing the level intro first
+    fn start_game(&mut self) {
+        println!("Starting game");
+        self.reset_game();
+        self.transition(flow::Event::Play);
+    }
+
+    fn restart_level(&mut self) {
+        self.reset_game();
+        self.transition(flow::Event::Restart);
+    }
+
+    // Replace the scene with one created for level, and find the nodes to animate in it
+    fn load_scene(&mut self, level: &game::Level) {
+        self.scene = (self.create_scene)(&self.gfx, level);
+        self.scene
+            .set_high_contrast(self.accessibility.high_contrast);
+        self.nodes = SceneNodes::find(&self.scene, level);
+        self.camera_target = None;
+    }
+
+    // Rebuild the scene when the edited level has changed. The scene is not rebuilt during a
+    // drag, the overlay shows the item being dragged until it's dropped.
+    fn update_editor_scene(&mut self) {
+        let editor = self.editor.as_mut().unwrap();
+        if !editor.is_dragging() && editor.take_changed() {
+            let level = editor.level.clone();
+            self.load_editor_scene(&level);
+        }
+    }
+
+    // Load the scene for the edited level and show the whole board from above
+    fn load_editor_scene(&mut self, level: &game::Level) {
+        const MARGIN: f32 = 0.9; // Fraction of the screen the board is fitted in
+        self.load_scene(level);
+        // The projected size of the board is inversely proportional to the camera height
+        let height = level.size.w.max(level.size.h);
+        self.scene.look_down_at(0.0, 0.0, height);
+        let corner =
+            self.scene
+                .world_to_screen(&glm::vec3(level.size.w / 2.0, 0.0, level.size.h / 2.0));
+        self.scene
+            .look_down_at(0.0, 0.0, height * corner.x.max(corner.y) / MARGIN);
+    }
+
+    fn start_editing(&mut self) {
+        println!("Editing level");
+        self.transition(flow::Event::Edit);
+        if self.editor.is_none() {
+            self.editor = Some(editor::Editor::new(&self.level));
+        }
+        let level = self.editor.as_ref().unwrap().level.clone();
+        self.load_editor_scene(&level);
+    }
+
+    fn save_level(&mut self) {
+        let json = self.editor.as_ref().unwrap().level.to_json();
+        match std::fs::write(&self.level_file, &json) {
+            Ok(()) => println!("Level saved to {}", self.level_file),
+            Err(e) => println!(
+                "Failed to save level to {}: {}\nLevel JSON:\n{}",
+                self.level_file, e, json
+            ),
+        }
+    }
+
+    // Play the edited level from the start. The editor keeps its state for continuing editing.
+    fn play_edited_level(&mut self) {
+        println!("Playing edited level");
+        self.level = self.editor.as_ref().unwrap().level.clone();
+        self.best_splits = None; // The level may have changed
+        let level = self.level.clone();
+        self.load_scene(&level);
+        self.reset_game();
+        self.transition(flow::Event::Play);
+    }
+
+    // Pointer moved in editing mode, either with the mouse or by touch
+    fn editor_pointer_moved(&mut self, pos: PhysicalPosition<f64>) {
+        self.ui.cursor_moved(pos.x as f32, pos.y as f32);
+        if let Some(p) = self.level_pos_at(pos) {
+            self.editor.as_mut().unwrap().drag_to(p);
+        }
+    }
+
+    // Pointer pressed or released in editing mode. Presses on the editor window don't edit the level.
+    fn editor_pointer_button(&mut self, pos: PhysicalPosition<f64>, pressed: bool) {
+        self.ui.click(pos.x as f32, pos.y as f32, pressed);
+        if !pressed {
+            self.editor.as_mut().unwrap().release();
+        } else if !self.ui.is_over_window(pos.x as f32, pos.y as f32) {
+            if let Some(p) = self.level_pos_at(pos) {
+                self.editor.as_mut().unwrap().press(p);
+            }
+        }
+    }
+
+    // The level coordinates of the board point seen at pos in the window
+    fn level_pos_at(&self, pos: PhysicalPosition<f64>) -> Option<glm::Vec2> {
+        let p = self
+            .scene
+            .screen_to_plane(&self.ui.to_ndc(pos.x as f32, pos.y as f32), 0.0)?;
+        Some(glm::vec2(
+            p.x + self.level.size.w / 2.0,
+            p.z + self.level.size.h / 2.0,
+        ))
+    }
+}
+
+// The scene nodes GameLoop moves, hides or emits particles from
+struct SceneNodes {
+    board: graphics::NodeId,
+    ball: graphics::NodeId,
+    moving_walls: Vec<graphics::NodeId>,
+    rotating_bars: Vec<graphics::NodeId>,
+    trapdoor_lids: Vec<graphics::NodeId>,
+    collectibles: Vec<graphics::NodeId>,
+    dust_emitter: graphics::NodeId,
+    goal_emitter: graphics::NodeId,
+    hole_emitter: graphics::NodeId,
+}
+
+impl SceneNodes {
+    fn find(scene: &graphics::Scene, level: &game::Level) -> SceneNodes {
+        let find_node = |name| {
+            scene
+                .find_node(name)
+                .unwrap_or_else(|| panic!("Scene node {} missing", name))
+        };
+        SceneNodes {
+            board: find_node("board"),
+            ball: find_node("ball"),
+            moving_walls: find_nodes(scene, "moving wall", level.moving_walls.len()),
+            rotating_bars: find_nodes(scene, "rotating bar", level.rotating_bars.len()),
+            trapdoor_lids: find_nodes(scene, "trapdoor lid", level.trapdoors.len()),
+            collectibles: find_nodes(scene, "collectible", level.collectibles.len()),
+            dust_emitter: find_node("dust particles"),
+            goal_emitter: find_node("goal particles"),
+            hole_emitter: find_node("hole particles"),
+        }
+    }
+}
+
+// Scene position of a point on the level's board surface
+fn level_to_scene(p: &game::Point, level_size: game::Size) -> glm::Vec3 {
+    glm::vec3(p.x - level_size.w / 2.0, 0.0, p.y - level_size.h / 2.0)
+}
+
+// Find the nodes named "<prefix> 0" ... "<prefix> <count - 1>"
+fn find_nodes(scene: &graphics::Scene, prefix: &str, count: usize) -> Vec<graphics::NodeId> {
+    (0..count)
+        .map(|i| {
+            scene
+                .find_node(&format!("{} {}", prefix, i))
+                .unwrap_or_else(|| panic!("Scene node {} {} missing", prefix, i))
+        })
+        .collect()
 }
 
 // Calculates the ball position (x, y, z) when the game has been lost and the ball is falling in to hole.
@@ -462,12 +928,6 @@ fn animate_ball_falling_in_hole(
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-enum State {
-    GameInProgress,
-    GamePaused,
-}
-
 struct Stats {
     frame_count: u32,
     last_calculated_t: Instant,
@@ -479,12 +939,14 @@ struct Stopwatch {
 }
 
 impl Stopwatch {
-    fn start_new() -> Stopwatch {
+    // A stopped stopwatch at zero
+    fn new() -> Stopwatch {
         Stopwatch {
             elapsed: Duration::from_secs(0),
-            start_t: Some(Instant::now()),
+            start_t: None,
         }
     }
+
     fn start(&mut self) {
         if self.start_t.is_none() {
             self.start_t = Some(Instant::now());
@@ -512,18 +974,30 @@ struct Ui {
     width_points: f32,
     height_points: f32,
     scale: f32,
+    default_scale: f32, // scale without the large UI setting
     events: Vec<egui::Event>,
+    master_volume: f32,
+    sfx_volume: f32,
+    haptics: Option<bool>, // None if haptic feedback is not available
+    accessibility: Accessibility,
+    locale: locale::Locale,
 }
 
 impl Ui {
-    fn new(width_pixels: u32, height_pixels: u32, scale: f32) -> Ui {
+    fn new(width_pixels: u32, height_pixels: u32, scale: f32, haptics_available: bool) -> Ui {
         Ui {
             ctx: egui::CtxRef::default(),
             texture: None,
             width_points: width_pixels as f32 / scale,
             height_points: height_pixels as f32 / scale,
             scale,
+            default_scale: scale,
             events: Vec::new(),
+            master_volume: 1.0,
+            sfx_volume: 1.0,
+            haptics: if haptics_available { Some(true) } else { None },
+            accessibility: Accessibility::new(),
+            locale: locale::Locale::new(locale::detect_language()),
         }
     }
 
@@ -531,9 +1005,17 @@ impl Ui {
         &mut self,
         gfx: &graphics::Instance,
         elapsed: Duration,
-        pause_state: State,
-        game_state: game::State,
+        state: flow::State,
+        now: Instant,
+        level: &game::Level,
+        game: &game::Game,
+        split: Option<Split>,
+        editor: Option<&mut editor::Editor>,
+        level_to_ndc: &dyn Fn(&game::Point) -> glm::Vec2,
     ) -> UiOutput {
+        let game_state = game.state;
+        let score = game.score(elapsed.as_secs_f32());
+        let time = Duration::from_secs_f32(score.time);
         let mut actions = Vec::new();
         self.ctx.begin_frame(egui::RawInput {
             screen_rect: Some(egui::Rect::from_min_size(
@@ -544,74 +1026,249 @@ impl Ui {
             events: std::mem::take(&mut self.events),
             ..Default::default()
         });
-        egui::Window::new("Timer window")
-            .title_bar(false)
-            .collapsible(false)
-            .resizable(false)
-            .fixed_pos(egui::pos2(10.0, 10.0))
-            .show(&self.ctx, |ui| {
-                ui.label(format!(
-                    "{:02}:{:02}",
-                    elapsed.as_secs() / 60,
-                    elapsed.as_secs() % 60
-                ));
-            });
-        match pause_state {
-            State::GameInProgress => (),
-            State::GamePaused => {
-                const MENU_SIZE: egui::Vec2 = egui::vec2(200.0, 150.0);
-                egui::Window::new(match game_state {
-                    game::State::InProgress => "Game paused",
-                    game::State::Won => "You made it through!",
-                    game::State::Lost { .. } => "Oops...",
-                })
-                .collapsible(false)
-                .resizable(false)
-                .fixed_size(MENU_SIZE)
-                .fixed_pos(egui::pos2(
-                    (self.width_points - MENU_SIZE.x) / 2.0,
-                    (self.height_points - MENU_SIZE.y) / 2.0,
-                ))
-                .show(&self.ctx, |ui| {
-                    ui.vertical_centered_justified(|ui| {
-                        ui.spacing_mut().button_padding.y = 10.0;
-                        match game_state {
-                            game::State::InProgress => {
-                                if ui.button("Resume").clicked() {
-                                    println!("Resuming game");
-                                    actions.push(UiAction::ResumeGame);
+        let loc = &self.locale;
+        match state {
+            flow::State::Countdown { .. } | flow::State::Playing | flow::State::Paused => {
+                egui::Window::new("Timer window")
+                    .title_bar(false)
+                    .collapsible(false)
+                    .resizable(false)
+                    .fixed_pos(egui::pos2(10.0, 10.0))
+                    .show(&self.ctx, |ui| {
+                        ui.label(format!(
+                            "{:02}:{:02}",
+                            time.as_secs() / 60,
+                            time.as_secs() % 60
+                        ));
+                        ui.label(loc.format("lives", &[("lives", &game.lives)]));
+                        if score.total > 0 {
+                            ui.label(loc.format(
+                                "items",
+                                &[("collected", &score.collected), ("total", &score.total)],
+                            ));
+                        }
+                        if let Some(split) = split {
+                            ui.label(loc.format(
+                                "split",
+                                &[
+                                    ("waypoint", &split.waypoint),
+                                    ("waypoints", &(level.path.len() - 1)),
+                                    ("time", &format!("{:.1}", split.time)),
+                                ],
+                            ));
+                            if let Some(best) = split.best {
+                                let diff = split.time - best;
+                                ui.colored_label(
+                                    if diff <= 0.0 {
+                                        egui::Color32::from_rgb(100, 220, 100)
+                                    } else {
+                                        egui::Color32::from_rgb(240, 100, 100)
+                                    },
+                                    format!("{:+.1} s", diff),
+                                );
+                            }
+                        }
+                        tilt_indicator(ui, game);
+                    });
+                self.minimap(game);
+            }
+            flow::State::MainMenu
+            | flow::State::LevelIntro { .. }
+            | flow::State::Results
+            | flow::State::Editing => (),
+        }
+        let mut master_volume = self.master_volume;
+        let mut sfx_volume = self.sfx_volume;
+        let mut haptics = self.haptics;
+        let mut language = loc.lang;
+        let mut accessibility = self.accessibility;
+        match state {
+            flow::State::MainMenu => {
+                self.menu_window(&loc.get("game-title"))
+                    .show(&self.ctx, |ui| {
+                        ui.vertical_centered_justified(|ui| {
+                            ui.spacing_mut().button_padding.y = 10.0;
+                            ui.label(level.localized_name(loc.lang));
+                            ui.add_space(10.0);
+                            if ui.button(loc.get("play")).clicked() {
+                                actions.push(UiAction::StartGame);
+                            }
+                            if ui.button(loc.get("edit-level")).clicked() {
+                                actions.push(UiAction::EditLevel);
+                            }
+                            if ui.button(loc.get("quit")).clicked() {
+                                println!("Quitting");
+                                actions.push(UiAction::Quit);
+                            }
+                            settings(
+                                ui,
+                                loc,
+                                &mut master_volume,
+                                &mut sfx_volume,
+                                &mut haptics,
+                                &mut language,
+                                &mut accessibility,
+                            );
+                        });
+                    });
+            }
+            flow::State::LevelIntro { .. } => {
+                self.menu_window("Level intro")
+                    .title_bar(false)
+                    .show(&self.ctx, |ui| {
+                        ui.vertical_centered(|ui| {
+                            ui.heading(level.localized_name(loc.lang));
+                            if let Some(author) = &level.meta.author {
+                                ui.label(loc.format("level-author", &[("author", author)]));
+                            }
+                            ui.add_space(10.0);
+                            if let Some(description) = level.localized_description(loc.lang) {
+                                ui.label(description);
+                            }
+                            if let Some(difficulty) = level.meta.difficulty {
+                                let difficulty = loc.get(match difficulty {
+                                    game::Difficulty::Easy => "difficulty-easy",
+                                    game::Difficulty::Medium => "difficulty-medium",
+                                    game::Difficulty::Hard => "difficulty-hard",
+                                    game::Difficulty::Expert => "difficulty-expert",
+                                });
+                                ui.label(
+                                    loc.format("level-difficulty", &[("difficulty", &difficulty)]),
+                                );
+                            }
+                            if let Some(t) = level.par_time {
+                                let t = format!("{:02}:{:02}", t as u32 / 60, t as u32 % 60);
+                                ui.label(loc.format("par-time", &[("time", &t)]));
+                            }
+                            ui.add_space(10.0);
+                            ui.small(loc.get("click-to-start"));
+                        });
+                    });
+            }
+            flow::State::Countdown { .. } => {
+                const COUNTDOWN_SIZE: egui::Vec2 = egui::vec2(60.0, 60.0);
+                egui::Window::new("Countdown")
+                    .title_bar(false)
+                    .collapsible(false)
+                    .resizable(false)
+                    .fixed_size(COUNTDOWN_SIZE)
+                    .fixed_pos(egui::pos2(
+                        (self.width_points - COUNTDOWN_SIZE.x) / 2.0,
+                        (self.height_points - COUNTDOWN_SIZE.y) / 2.0,
+                    ))
+                    .show(&self.ctx, |ui| {
+                        ui.vertical_centered(|ui| {
+                            ui.heading(state.countdown_number(now).unwrap().to_string());
+                        });
+                    });
+            }
+            flow::State::Playing => (),
+            flow::State::Paused => {
+                self.menu_window(&loc.get("game-paused"))
+                    .show(&self.ctx, |ui| {
+                        ui.vertical_centered_justified(|ui| {
+                            ui.spacing_mut().button_padding.y = 10.0;
+                            if ui.button(loc.get("resume")).clicked() {
+                                println!("Resuming game");
+                                actions.push(UiAction::ResumeGame);
+                            }
+                            if ui.button(loc.get("restart")).clicked() {
+                                println!("Restarting level");
+                                actions.push(UiAction::RestartLevel);
+                            }
+                            if ui.button(loc.get("edit-level")).clicked() {
+                                actions.push(UiAction::EditLevel);
+                            }
+                            if ui.button(loc.get("main-menu")).clicked() {
+                                actions.push(UiAction::MainMenu);
+                            }
+                            if ui.button(loc.get("quit")).clicked() {
+                                println!("Quitting");
+                                actions.push(UiAction::Quit);
+                            }
+                            settings(
+                                ui,
+                                loc,
+                                &mut master_volume,
+                                &mut sfx_volume,
+                                &mut haptics,
+                                &mut language,
+                                &mut accessibility,
+                            );
+                        });
+                    });
+            }
+            flow::State::Results => {
+                let won = matches!(game_state, game::State::Won);
+                self.menu_window(&loc.get(if won { "game-won" } else { "game-lost" }))
+                    .show(&self.ctx, |ui| {
+                        ui.vertical_centered_justified(|ui| {
+                            ui.spacing_mut().button_padding.y = 10.0;
+                            ui.add_space(10.0);
+                            if won {
+                                let t = format!(
+                                    "{:02}:{:02}.{:03}",
+                                    time.as_secs() / 60,
+                                    time.as_secs() % 60,
+                                    time.as_millis() % 1000
+                                );
+                                ui.label(loc.format("your-time", &[("time", &t)]));
+                                if score.total > 0 {
+                                    ui.label(loc.format(
+                                        "collected",
+                                        &[("collected", &score.collected), ("total", &score.total)],
+                                    ));
                                 }
+                                ui.label(loc.format("score", &[("points", &score.points)]));
+                            } else {
+                                ui.label(loc.get("no-balls-left"));
                             }
-                            game::State::Won => {
-                                ui.add_space(10.0);
-                                ui.label(format!(
-                                    "Your time: {:02}:{:02}.{:03}",
-                                    elapsed.as_secs() / 60,
-                                    elapsed.as_secs() % 60,
-                                    elapsed.as_millis() % 1000
-                                ));
-                                ui.add_space(10.0);
+                            ui.add_space(10.0);
+                            if ui
+                                .button(loc.get(if won { "play-again" } else { "try-again" }))
+                                .clicked()
+                            {
+                                println!("Restarting level");
+                                actions.push(UiAction::RestartLevel);
                             }
-                            game::State::Lost { .. } => (),
-                        }
-                        if ui
-                            .button(match game_state {
-                                game::State::InProgress => "Restart",
-                                game::State::Won => "Play again",
-                                game::State::Lost { .. } => "Try again",
-                            })
-                            .clicked()
-                        {
-                            println!("Restarting level");
-                            actions.push(UiAction::RestartLevel);
-                        }
-                        if ui.button("Quit").clicked() {
-                            println!("Quitting");
-                            actions.push(UiAction::Quit);
-                        }
+                            if ui.button(loc.get("edit-level")).clicked() {
+                                actions.push(UiAction::EditLevel);
+                            }
+                            if ui.button(loc.get("main-menu")).clicked() {
+                                actions.push(UiAction::MainMenu);
+                            }
+                            if ui.button(loc.get("quit")).clicked() {
+                                println!("Quitting");
+                                actions.push(UiAction::Quit);
+                            }
+                        });
                     });
-                });
             }
+            flow::State::Editing => {
+                let editor = editor.unwrap();
+                self.editor_window(editor, &mut actions);
+                self.editor_overlay(editor, level_to_ndc);
+            }
+        }
+        if master_volume != self.master_volume || sfx_volume != self.sfx_volume {
+            self.master_volume = master_volume;
+            self.sfx_volume = sfx_volume;
+            actions.push(UiAction::SetVolumes {
+                master: master_volume,
+                sfx: sfx_volume,
+            });
+        }
+        if haptics != self.haptics {
+            self.haptics = haptics;
+            actions.push(UiAction::SetHaptics(haptics.unwrap()));
+        }
+        if language != self.locale.lang {
+            println!("Switching language to {}", language);
+            self.locale = locale::Locale::new(language);
+        }
+        if accessibility != self.accessibility {
+            self.accessibility = accessibility;
+            actions.push(UiAction::SetAccessibility(accessibility));
         }
         let (_output, shapes) = self.ctx.end_frame();
         let egui_texture = self.ctx.texture();
@@ -671,9 +1328,264 @@ impl Ui {
                 obj
             })
             .collect();
+        // The window size in points changes with the scale, so the new scale is taken into use
+        // only after this frame has been laid out and tessellated
+        self.set_scale(if self.accessibility.large_ui {
+            LARGE_UI_SCALE * self.default_scale
+        } else {
+            self.default_scale
+        });
         UiOutput { actions, objects }
     }
 
+    fn set_scale(&mut self, scale: f32) {
+        self.width_points *= self.scale / scale;
+        self.height_points *= self.scale / scale;
+        self.scale = scale;
+    }
+
+    // A menu window in the middle of the screen
+    fn menu_window(&self, title: &str) -> egui::Window<'static> {
+        const MENU_SIZE: egui::Vec2 = egui::vec2(200.0, 300.0);
+        egui::Window::new(title)
+            .collapsible(false)
+            .resizable(false)
+            .scroll(true)
+            .fixed_size(MENU_SIZE)
+            .fixed_pos(egui::pos2(
+                (self.width_points - MENU_SIZE.x) / 2.0,
+                (self.height_points - MENU_SIZE.y) / 2.0,
+            ))
+    }
+
+    // Overview of the whole board in the top right corner. The follow camera only shows the
+    // surroundings of the ball.
+    fn minimap(&self, game: &game::Game) {
+        const MAX_SIZE: f32 = 160.0;
+        const MARGIN: f32 = 10.0;
+        let level = &game.level;
+        let scale = MAX_SIZE / level.size.w.max(level.size.h);
+        let origin = egui::pos2(self.width_points - MARGIN - scale * level.size.w, MARGIN);
+        let to_map = |p: glm::Vec2| origin + scale * egui::vec2(p.x, p.y);
+        let rect = |r: &game::Rect| {
+            egui::Rect::from_min_size(
+                to_map(glm::Vec2::from(&r.pos)),
+                scale * egui::vec2(r.size.w, r.size.h),
+            )
+        };
+        let painter = self.ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Background,
+            egui::Id::new("minimap"),
+        ));
+        let wall_color = egui::Color32::from_rgb(90, 60, 35);
+        painter.rect_filled(
+            egui::Rect::from_min_size(origin, scale * egui::vec2(level.size.w, level.size.h)),
+            2.0,
+            egui::Color32::from_rgba_unmultiplied(210, 170, 120, 200),
+        );
+        painter.rect_filled(rect(&level.end), 0.0, egui::Color32::from_rgb(60, 160, 60));
+        for hole in level.holes.iter() {
+            painter.circle_filled(
+                to_map(glm::Vec2::from(hole)),
+                scale * game::HOLE_R,
+                egui::Color32::BLACK,
+            );
+        }
+        for trapdoor in level.trapdoors.iter() {
+            if trapdoor.is_open_at(game.time) {
+                painter.circle_filled(
+                    to_map(glm::Vec2::from(&trapdoor.pos)),
+                    scale * game::HOLE_R,
+                    egui::Color32::BLACK,
+                );
+            }
+        }
+        for wall in level.walls.iter() {
+            painter.rect_filled(rect(&wall.rect), 0.0, wall_color);
+        }
+        for wall in level.moving_walls.iter() {
+            painter.rect_filled(rect(&wall.rect_at(game.time)), 0.0, wall_color);
+        }
+        for post in level.posts.iter() {
+            painter.circle_filled(
+                to_map(glm::Vec2::from(&post.pos)),
+                scale * post.r,
+                wall_color,
+            );
+        }
+        for segment in level.segments.iter() {
+            painter.line_segment(
+                [
+                    to_map(glm::Vec2::from(&segment.a)),
+                    to_map(glm::Vec2::from(&segment.b)),
+                ],
+                (scale * segment.w, wall_color),
+            );
+        }
+        for arc in level.arcs.iter() {
+            const ARC_STEPS: usize = 16;
+            let point = |i: usize| {
+                let angle = arc.start_angle
+                    + (arc.end_angle - arc.start_angle) * i as f32 / ARC_STEPS as f32;
+                to_map(glm::Vec2::from(&arc.center) + arc.r * glm::vec2(angle.cos(), angle.sin()))
+            };
+            for i in 0..ARC_STEPS {
+                painter.line_segment([point(i), point(i + 1)], (scale * arc.w, wall_color));
+            }
+        }
+        for bar in level.rotating_bars.iter() {
+            let angle = bar.angle_at(game.time);
+            let half = bar.length / 2.0 * glm::vec2(angle.cos(), angle.sin());
+            let center = glm::Vec2::from(&bar.center);
+            painter.line_segment(
+                [to_map(center - half), to_map(center + half)],
+                (scale * bar.w, wall_color),
+            );
+        }
+        let ball = to_map(glm::Vec2::from(&game.ball_pos));
+        let ball_r = (scale * game::BALL_R).max(3.0);
+        painter.circle_filled(ball, ball_r, egui::Color32::WHITE);
+        painter.circle_stroke(ball, ball_r, (1.0, egui::Color32::BLACK));
+    }
+
+    fn editor_window(&self, editor: &mut editor::Editor, actions: &mut Vec<UiAction>) {
+        let problems = editor.validate();
+        let loc = &self.locale;
+        egui::Window::new(loc.get("level-editor"))
+            .collapsible(false)
+            .resizable(false)
+            .default_pos(egui::pos2(10.0, 10.0))
+            .show(&self.ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.radio_value(
+                        &mut editor.tool,
+                        editor::Tool::Select,
+                        loc.get("tool-select"),
+                    );
+                    ui.radio_value(&mut editor.tool, editor::Tool::Wall, loc.get("tool-wall"));
+                    ui.radio_value(&mut editor.tool, editor::Tool::Hole, loc.get("tool-hole"));
+                    ui.radio_value(&mut editor.tool, editor::Tool::Path, loc.get("tool-path"));
+                });
+                ui.checkbox(&mut editor.snap, loc.get("snap-to-grid"));
+                ui.horizontal(|ui| {
+                    if ui
+                        .add(egui::Button::new(loc.get("undo")).enabled(editor.can_undo()))
+                        .clicked()
+                    {
+                        editor.undo();
+                    }
+                    if ui
+                        .add(egui::Button::new(loc.get("redo")).enabled(editor.can_redo()))
+                        .clicked()
+                    {
+                        editor.redo();
+                    }
+                    let deletable = match editor.selection {
+                        Some(editor::Item::Start) | Some(editor::Item::End) | None => false,
+                        _ => true,
+                    };
+                    if ui
+                        .add(egui::Button::new(loc.get("delete")).enabled(deletable))
+                        .clicked()
+                    {
+                        editor.delete_selected();
+                    }
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button(loc.get("save")).clicked() {
+                        actions.push(UiAction::SaveLevel);
+                    }
+                    // Only playable levels can be tested
+                    if ui
+                        .add(egui::Button::new(loc.get("play")).enabled(problems.is_empty()))
+                        .clicked()
+                    {
+                        actions.push(UiAction::PlayLevel);
+                    }
+                });
+                if problems.is_empty() {
+                    ui.label(loc.get("level-playable"));
+                }
+                for problem in problems.iter() {
+                    ui.colored_label(egui::Color32::RED, problem.as_str());
+                }
+            });
+    }
+
+    // Draw the parts of the level that the scene doesn't show: the goal, the AI path and the
+    // selection. Drawn below the windows.
+    fn editor_overlay(
+        &self,
+        editor: &editor::Editor,
+        level_to_ndc: &dyn Fn(&game::Point) -> glm::Vec2,
+    ) {
+        let to_screen = |p: &game::Point| {
+            let ndc = level_to_ndc(p);
+            egui::pos2(
+                (ndc.x + 1.0) / 2.0 * self.width_points,
+                (1.0 - ndc.y) / 2.0 * self.height_points,
+            )
+        };
+        let rect = |min: glm::Vec2, max: glm::Vec2| {
+            egui::Rect::from_two_pos(
+                to_screen(&game::Point::from(&min)),
+                to_screen(&game::Point::from(&max)),
+            )
+        };
+        let painter = self.ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Background,
+            egui::Id::new("editor overlay"),
+        ));
+        let level = &editor.level;
+        let end = glm::Vec2::from(&level.end.pos);
+        painter.rect_stroke(
+            rect(end, end + glm::vec2(level.end.size.w, level.end.size.h)),
+            0.0,
+            (2.0, egui::Color32::GREEN),
+        );
+        let path_stroke = (1.5, egui::Color32::from_rgb(100, 180, 255));
+        for segment in level.path.windows(2) {
+            painter.line_segment(
+                [to_screen(&segment[0]), to_screen(&segment[1])],
+                path_stroke,
+            );
+        }
+        for p in level.path.iter() {
+            painter.circle_stroke(to_screen(p), 3.0, path_stroke);
+        }
+        if let Some((min, max, resizable)) = editor.selection_bounds() {
+            let stroke = (2.0, egui::Color32::YELLOW);
+            painter.rect_stroke(rect(min, max), 0.0, stroke);
+            if resizable {
+                painter.rect_filled(
+                    egui::Rect::from_center_size(
+                        to_screen(&game::Point::from(&max)),
+                        egui::vec2(6.0, 6.0),
+                    ),
+                    0.0,
+                    egui::Color32::YELLOW,
+                );
+            }
+        }
+    }
+
+    // Convert a window position in pixels to normalized device coordinates
+    fn to_ndc(&self, x: f32, y: f32) -> glm::Vec2 {
+        let (w, h) = (
+            self.width_points * self.scale,
+            self.height_points * self.scale,
+        );
+        glm::vec2(2.0 * x / w - 1.0, 1.0 - 2.0 * y / h)
+    }
+
+    // Return true if a window is at a position in pixels
+    fn is_over_window(&self, x: f32, y: f32) -> bool {
+        self.ctx
+            .layer_id_at(egui::pos2(x / self.scale, y / self.scale))
+            .is_some()
+    }
+
     fn cursor_moved(&mut self, x: f32, y: f32) {
         self.events.push(egui::Event::PointerMoved(egui::pos2(
             x / self.scale,
@@ -691,17 +1603,115 @@ impl Ui {
     }
 }
 
+// Bubble level showing the board tilt. The bubble moves to the raised side of the board, and
+// reaches the rim at game::MAX_ANGLE.
+fn tilt_indicator(ui: &mut egui::Ui, game: &game::Game) {
+    const LEVEL_R: f32 = 24.0;
+    const BUBBLE_R: f32 = 5.0;
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(2.0 * LEVEL_R, 2.0 * LEVEL_R),
+        egui::Sense::hover(),
+    );
+    let center = rect.center();
+    let painter = ui.painter();
+    painter.circle_stroke(center, LEVEL_R, (1.0, egui::Color32::GRAY));
+    painter.circle_stroke(center, BUBBLE_R + 1.0, (1.0, egui::Color32::GRAY));
+    let mut tilt = glm::vec2(game.angle_x, game.angle_y) / game::MAX_ANGLE;
+    if glm::length(&tilt) > 1.0 {
+        tilt = glm::normalize(&tilt);
+    }
+    let offset = -(LEVEL_R - BUBBLE_R) * tilt;
+    painter.circle_filled(
+        center + egui::vec2(offset.x, offset.y),
+        BUBBLE_R,
+        egui::Color32::from_rgb(120, 220, 120),
+    );
+}
+
+// Volume, vibration, language and accessibility settings, shown in the main menu and the pause
+// menu
+fn settings(
+    ui: &mut egui::Ui,
+    loc: &locale::Locale,
+    master_volume: &mut f32,
+    sfx_volume: &mut f32,
+    haptics: &mut Option<bool>,
+    language: &mut &'static str,
+    accessibility: &mut Accessibility,
+) {
+    ui.separator();
+    ui.add(egui::Slider::new(master_volume, 0.0..=1.0).text(loc.get("master-volume")));
+    ui.add(egui::Slider::new(sfx_volume, 0.0..=1.0).text(loc.get("effects-volume")));
+    if let Some(enabled) = haptics {
+        ui.checkbox(enabled, loc.get("vibration"));
+    }
+    ui.label(loc.get("language"));
+    ui.horizontal(|ui| {
+        for &(lang, name) in locale::LANGUAGES.iter() {
+            ui.radio_value(language, lang, name);
+        }
+    });
+    ui.collapsing(loc.get("accessibility"), |ui| {
+        ui.checkbox(&mut accessibility.high_contrast, loc.get("high-contrast"));
+        ui.checkbox(&mut accessibility.reduced_motion, loc.get("reduced-motion"));
+        ui.checkbox(&mut accessibility.large_ui, loc.get("large-ui"));
+        ui.add(
+            egui::Slider::new(&mut accessibility.game_speed, MIN_GAME_SPEED..=1.0)
+                .text(loc.get("game-speed")),
+        );
+    });
+}
+
+const LARGE_UI_SCALE: f32 = 1.5; // UI scale of the large UI setting relative to the default
+const MIN_GAME_SPEED: f32 = 0.5;
+
+// Settings for players who have trouble seeing the board or keeping up with the game
+#[derive(Copy, Clone, PartialEq)]
+struct Accessibility {
+    high_contrast: bool,  // flat, strongly distinguishable colors in the scene
+    reduced_motion: bool, // smoothed follow camera and no particle effects
+    large_ui: bool,
+    game_speed: f32, // simulation speed relative to real time
+}
+
+impl Accessibility {
+    fn new() -> Accessibility {
+        Accessibility {
+            high_contrast: false,
+            reduced_motion: false,
+            large_ui: false,
+            game_speed: 1.0,
+        }
+    }
+}
+
 struct EguiTexture {
     texture: Rc<graphics::Texture>,
     version: u64,
 }
 
 enum UiAction {
+    StartGame,
     ResumeGame,
     RestartLevel,
+    MainMenu,
+    SetVolumes { master: f32, sfx: f32 },
+    SetHaptics(bool),
+    SetAccessibility(Accessibility),
+    EditLevel,
+    SaveLevel,
+    PlayLevel,
     Quit,
 }
 
+// Time at the latest AI path point passed, and the personal best time at the same point
+#[derive(Copy, Clone)]
+struct Split {
+    waypoint: usize, // index of the path point
+    time: f32,
+    best: Option<f32>,
+}
+
 struct UiOutput {
     actions: Vec<UiAction>,
     objects: Vec<graphics::Object2d>,
@@ -1,6 +1,8 @@
 use nalgebra_glm as glm;
 use raw_window_handle::HasRawWindowHandle;
-use std::{iter, num::NonZeroU32, rc::Rc};
+use std::{iter, num::NonZeroU32, ops::Range, rc::Rc};
+
+use crate::mesh;
 
 const MAX_LIGHTS: usize = 4;
 
@@ -37,11 +39,15 @@ pub struct Instance {
     scene_uniform_bind_group_layout: wgpu::BindGroupLayout,
     object_uniform_bind_group_layout: wgpu::BindGroupLayout,
     object_texture_bind_group_layout: wgpu::BindGroupLayout,
+    material_bind_group_layout: wgpu::BindGroupLayout,
     render_pipeline: wgpu::RenderPipeline,
+    transparent_render_pipeline: wgpu::RenderPipeline,
     shadow_pass_uniform_buffer: wgpu::Buffer,
     shadow_pass_uniform_bind_group: wgpu::BindGroup,
     shadow_pass_pipeline: wgpu::RenderPipeline,
     render_2d_pipeline: wgpu::RenderPipeline,
+    particle_pipeline: wgpu::RenderPipeline,
+    additive_particle_pipeline: wgpu::RenderPipeline,
 }
 
 impl Instance {
@@ -254,6 +260,64 @@ impl Instance {
                 ],
             });
 
+        let material_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Material bind group layout"),
+                entries: &[
+                    // Material uniforms
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Base color texture
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler {
+                            filtering: true,
+                            comparison: false,
+                        },
+                        count: None,
+                    },
+                    // Normal map texture
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler {
+                            filtering: true,
+                            comparison: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
         let vs_module = device.create_shader_module(&wgpu::include_spirv!("default.vert.spv"));
         let fs_module = device.create_shader_module(&wgpu::include_spirv!("default.frag.spv"));
 
@@ -263,38 +327,26 @@ impl Instance {
                 bind_group_layouts: &[
                     &scene_uniform_bind_group_layout,
                     &shadow_maps_bind_group_layout,
-                    &object_uniform_bind_group_layout,
-                    &object_texture_bind_group_layout,
+                    &material_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
 
+        // Opaque and alpha masked objects are drawn without blending
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render pipeline"),
             layout: Some(&render_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &vs_module,
                 entry_point: "main",
-                buffers: &[Vertex::buffer_layout()],
+                buffers: &[Vertex::buffer_layout(), InstanceData::buffer_layout()],
             },
             fragment: Some(wgpu::FragmentState {
                 module: &fs_module,
                 entry_point: "main",
                 targets: &[wgpu::ColorTargetState {
                     format: swap_chain_descriptor.format,
-                    // Blending for straight alpha
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                    }),
+                    blend: None,
                     write_mask: wgpu::ColorWrite::ALL,
                 }],
             }),
@@ -325,6 +377,65 @@ impl Instance {
             },
         });
 
+        // Objects with alpha blending are drawn after the opaque objects
+        let transparent_render_pipeline =
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Transparent render pipeline"),
+                layout: Some(&render_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &vs_module,
+                    entry_point: "main",
+                    buffers: &[Vertex::buffer_layout(), InstanceData::buffer_layout()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &fs_module,
+                    entry_point: "main",
+                    targets: &[wgpu::ColorTargetState {
+                        format: swap_chain_descriptor.format,
+                        // Blending for straight alpha
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::SrcAlpha,
+                                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::SrcAlpha,
+                                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                        }),
+                        write_mask: wgpu::ColorWrite::ALL,
+                    }],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Back),
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: depth_buffer_desc.format,
+                    // Translucent objects are depth tested against the opaque objects, but don't occlude each other
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState {
+                        constant: 0,
+                        slope_scale: 0.0,
+                        clamp: 0.0,
+                    },
+                    clamp_depth: false,
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: config.msaa_samples,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+            });
+
         let shadow_pass_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Shadow pass uniform buffer"),
             size: std::mem::size_of::<RawMat4>() as wgpu::BufferAddress,
@@ -362,10 +473,7 @@ impl Instance {
         let shadow_pass_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Shadow pipeline layout"),
-                bind_group_layouts: &[
-                    &shadow_pass_uniform_bind_group_layout,
-                    &object_uniform_bind_group_layout,
-                ],
+                bind_group_layouts: &[&shadow_pass_uniform_bind_group_layout],
                 push_constant_ranges: &[],
             });
 
@@ -375,7 +483,7 @@ impl Instance {
             vertex: wgpu::VertexState {
                 module: &shadow_pass_vs_module,
                 entry_point: "main",
-                buffers: &[Vertex::buffer_layout()],
+                buffers: &[Vertex::buffer_layout(), InstanceData::buffer_layout()],
             },
             fragment: None,
             primitive: wgpu::PrimitiveState {
@@ -464,6 +572,47 @@ impl Instance {
             },
         });
 
+        let particle_vs_module =
+            device.create_shader_module(&wgpu::include_spirv!("particle.vert.spv"));
+        let particle_fs_module =
+            device.create_shader_module(&wgpu::include_spirv!("particle.frag.spv"));
+
+        let particle_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Particle pipeline layout"),
+                bind_group_layouts: &[
+                    &scene_uniform_bind_group_layout,
+                    &object_texture_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        // Blending for straight alpha
+        let particle_pipeline = create_particle_pipeline(
+            &device,
+            "Particle pipeline",
+            &particle_pipeline_layout,
+            &particle_vs_module,
+            &particle_fs_module,
+            swap_chain_descriptor.format,
+            depth_buffer_desc.format,
+            config.msaa_samples,
+            wgpu::BlendFactor::OneMinusSrcAlpha,
+        );
+
+        // Additive blending, the particles only brighten what is behind them
+        let additive_particle_pipeline = create_particle_pipeline(
+            &device,
+            "Additive particle pipeline",
+            &particle_pipeline_layout,
+            &particle_vs_module,
+            &particle_fs_module,
+            swap_chain_descriptor.format,
+            depth_buffer_desc.format,
+            config.msaa_samples,
+            wgpu::BlendFactor::One,
+        );
+
         Instance {
             config,
             width,
@@ -480,11 +629,15 @@ impl Instance {
             scene_uniform_bind_group_layout,
             object_uniform_bind_group_layout,
             object_texture_bind_group_layout,
+            material_bind_group_layout,
             render_pipeline,
+            transparent_render_pipeline,
             shadow_pass_uniform_buffer,
             shadow_pass_uniform_bind_group,
             shadow_pass_pipeline,
             render_2d_pipeline,
+            particle_pipeline,
+            additive_particle_pipeline,
         }
     }
 
@@ -503,8 +656,8 @@ impl Instance {
         Scene::new(self)
     }
 
-    pub fn create_shape(&self, name: &str, ply: &str) -> Shape {
-        Shape::from_ply(self, name, ply)
+    pub fn create_shape(&self, name: &str, mesh: &mesh::Mesh) -> Shape {
+        Shape::from_mesh(self, name, mesh)
     }
 
     pub fn create_shape_2d(&self, name: &str, vertices: &[Vertex2d], indices: &[u32]) -> Shape {
@@ -512,36 +665,89 @@ impl Instance {
     }
 
     pub fn create_texture(&self, name: &str, w: u32, h: u32, rgba_data: &[u8]) -> Texture {
-        Texture::from_image(self, name, w, h, rgba_data)
+        Texture::from_image(self, name, w, h, rgba_data, wgpu::AddressMode::ClampToEdge)
     }
 
-    pub fn create_object(&self, s: &Rc<Shape>, t: &Rc<Texture>) -> Node {
-        let buf = self.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Object uniform buffer"),
-            size: std::mem::size_of::<ObjectUniforms>() as wgpu::BufferAddress,
-            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-            mapped_at_creation: false,
-        });
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Object uniform bind group"),
-            layout: &self.object_uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: buf.as_entire_binding(),
-            }],
-        });
+    /// Create a texture that repeats when sampled outside the 0...1 texture coordinate range.
+    /// Every other repetition is mirrored to hide the seams of non-tileable images.
+    pub fn create_tiling_texture(&self, name: &str, w: u32, h: u32, rgba_data: &[u8]) -> Texture {
+        Texture::from_image(self, name, w, h, rgba_data, wgpu::AddressMode::MirrorRepeat)
+    }
+
+    pub fn create_material(&self, name: &str, cfg: &MaterialConfig) -> Material {
+        Material::new(self, name, cfg)
+    }
+
+    /// Create a node drawing shape s with material m. Objects sharing the same shape and
+    /// material are drawn together with a single instanced draw call, so reuse the same Rc's
+    /// instead of creating identical copies when drawing many similar objects.
+    pub fn create_object(&self, s: &Rc<Shape>, m: &Rc<Material>) -> Node {
         Node::new(NodeKind::Object(Object {
             shape: Rc::clone(s),
-            texture: Rc::clone(t),
-            uniform_buffer: buf,
-            uniform_bind_group: bind_group,
+            material: Rc::clone(m),
         }))
     }
 
+    /// Create objects for all the mesh primitives in glTF 2.0 data. The objects don't have a
+    /// common parent, and the glTF node transformations are already applied to their shapes.
+    pub fn create_gltf_objects(&self, name: &str, gltf: &[u8]) -> Result<Vec<Node>, mesh::Error> {
+        Ok(mesh::Mesh::from_gltf(gltf)?
+            .iter()
+            .enumerate()
+            .map(|(i, primitive)| {
+                let name = format!("{} {}", name, i);
+                let texture = Rc::new(match &primitive.texture {
+                    Some(img) => self.create_texture(&name, img.width(), img.height(), img),
+                    None => self.create_texture(&name, 1, 1, &[255, 255, 255, 255]),
+                });
+                let material = self.create_material(
+                    &name,
+                    &MaterialConfig {
+                        base_color: primitive.base_color,
+                        // Approximate the glTF metallic-roughness model
+                        specular: 0.04 + 0.96 * primitive.metallic,
+                        roughness: primitive.roughness,
+                        alpha_mode: match primitive.alpha_mode {
+                            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                            gltf::material::AlphaMode::Mask => {
+                                AlphaMode::Mask(primitive.alpha_cutoff)
+                            }
+                            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+                        },
+                        ..MaterialConfig::new(&texture)
+                    },
+                );
+                self.create_object(
+                    &Rc::new(self.create_shape(&name, &primitive.mesh)),
+                    &Rc::new(material),
+                )
+            })
+            .collect())
+    }
+
     pub fn create_transformation(&self) -> Node {
         Node::new(NodeKind::Transformation)
     }
 
+    /// Create a node emitting particles as configured in cfg. See Scene::emit_particles() and
+    /// Scene::update_particles().
+    pub fn create_emitter(&self, name: &str, cfg: &ParticleConfig) -> Node {
+        let instance_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("Particle instance buffer {}", name)),
+            size: (cfg.max_particles * std::mem::size_of::<ParticleInstance>())
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Node::new(NodeKind::Emitter(Emitter {
+            cfg: cfg.clone(),
+            particles: Vec::new(),
+            spawn_accumulator: 0.0,
+            rng: 0x9e3779b9,
+            instance_buffer,
+        }))
+    }
+
     pub fn create_object_2d(&self, s: &Rc<Shape>, t: &Rc<Texture>) -> Object2d {
         let buf = self.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("2D Object uniform buffer"),
@@ -557,12 +763,7 @@ impl Instance {
                 resource: buf.as_entire_binding(),
             }],
         });
-        Object2d::new(Object {
-            shape: Rc::clone(s),
-            texture: Rc::clone(t),
-            uniform_buffer: buf,
-            uniform_bind_group: bind_group,
-        })
+        Object2d::new(Rc::clone(s), Rc::clone(t), buf, bind_group)
     }
 
     pub fn add_light_to(
@@ -596,7 +797,7 @@ impl Instance {
         });
     }
 
-    pub fn render_scene(&self, scene: &Scene, objects2d: &[Object2d]) {
+    pub fn render_scene(&self, scene: &mut Scene, objects2d: &[Object2d]) {
         if self.swap_chain.is_none() {
             return;
         }
@@ -607,13 +808,15 @@ impl Instance {
                 label: Some("Render encoder"),
             });
 
-        // 1. Update uniforms
+        // 1. Update uniforms and instance data
         self.queue.write_buffer(
             &scene.scene_uniform_buffer,
             0,
             bytemuck::cast_slice(&[SceneUniforms::from(
-                &(scene.perspective_matrix * scene.view_matrix),
+                &scene.view_matrix,
+                &scene.perspective_matrix,
                 scene.lights.len() as u32,
+                scene.high_contrast,
             )]),
         );
         for (i, light) in scene.lights.iter().enumerate() {
@@ -623,32 +826,16 @@ impl Instance {
                 bytemuck::cast_slice(&[LightUniforms::from(&scene.light_projection_matrix, light)]),
             );
         }
-        for (id, n) in scene.nodes.iter().enumerate() {
-            match &n.node.kind {
-                NodeKind::Object(Object {
-                    shape: _,
-                    texture: _,
-                    uniform_buffer,
-                    uniform_bind_group: _,
-                }) => {
-                    let effective_model_matrix = SceneIterator::new(scene, id)
-                        .fold(glm::identity(), |acc, node| node.model_matrix * acc);
-                    self.queue.write_buffer(
-                        &uniform_buffer,
-                        0,
-                        bytemuck::cast_slice(&[ObjectUniforms::from(&effective_model_matrix)]),
-                    );
-                }
-                NodeKind::Transformation => (), // no uniforms to update
-            }
-        }
+        scene.update_instances(self);
+        scene.update_particle_buffers(self);
         for obj in objects2d.iter() {
             self.queue.write_buffer(
-                &obj.object.uniform_buffer,
+                &obj.uniform_buffer,
                 0,
                 bytemuck::cast_slice(&[Object2dUniforms::from(&obj.model_matrix)]),
             );
         }
+        let scene = &*scene;
 
         // 2. Create shadow maps
         for (i, light) in scene.lights.iter().enumerate() {
@@ -678,25 +865,11 @@ impl Instance {
 
                 render_pass.set_pipeline(&self.shadow_pass_pipeline);
                 render_pass.set_bind_group(0, &self.shadow_pass_uniform_bind_group, &[]);
+                render_pass.set_vertex_buffer(1, scene.instance_buffer.slice(..));
 
-                for n in scene.nodes.iter() {
-                    match &n.node.kind {
-                        NodeKind::Object(Object {
-                            shape,
-                            texture: _,
-                            uniform_buffer: _,
-                            uniform_bind_group,
-                        }) => {
-                            render_pass.set_bind_group(1, &uniform_bind_group, &[]);
-                            render_pass.set_vertex_buffer(0, shape.vertex_buffer.slice(..));
-                            render_pass.set_index_buffer(
-                                shape.index_buffer.slice(..),
-                                Vertex::index_format(),
-                            );
-                            render_pass.draw_indexed(0..shape.index_count as u32, 0, 0..1);
-                        }
-                        NodeKind::Transformation => (), // nothing to draw
-                    }
+                // Translucent objects don't cast shadows
+                for batch in scene.batches.iter() {
+                    draw_batch(&mut render_pass, batch);
                 }
             }
         }
@@ -733,24 +906,54 @@ impl Instance {
             render_pass.set_pipeline(&self.render_pipeline);
             render_pass.set_bind_group(0, &scene.uniform_bind_group, &[]);
             render_pass.set_bind_group(1, &self.shadow_maps_bind_group, &[]);
-
-            for n in scene.nodes.iter() {
-                match &n.node.kind {
-                    NodeKind::Object(Object {
-                        shape,
-                        texture,
-                        uniform_buffer: _,
-                        uniform_bind_group,
-                    }) => {
-                        render_pass.set_bind_group(2, &uniform_bind_group, &[]);
-                        render_pass.set_bind_group(3, &texture.bind_group, &[]);
-                        render_pass.set_vertex_buffer(0, shape.vertex_buffer.slice(..));
-                        render_pass
-                            .set_index_buffer(shape.index_buffer.slice(..), Vertex::index_format());
-                        render_pass.draw_indexed(0..shape.index_count as u32, 0, 0..1);
-                    }
-                    NodeKind::Transformation => (), // nothing to draw
+            render_pass.set_vertex_buffer(1, scene.instance_buffer.slice(..));
+
+            // The batches are sorted by material, so consecutive batches often share the
+            // material bind group
+            let mut current_material: Option<&Rc<Material>> = None;
+            for batch in scene.batches.iter() {
+                if current_material.map_or(true, |m| !Rc::ptr_eq(m, &batch.material)) {
+                    render_pass.set_bind_group(2, &batch.material.bind_group, &[]);
+                    current_material = Some(&batch.material);
                 }
+                draw_batch(&mut render_pass, batch);
+            }
+
+            // Translucent objects are drawn back to front so that blending works correctly
+            let camera_position = scene.camera_position();
+            let mut translucent_batches: Vec<(f32, &Batch)> = scene
+                .translucent_batches
+                .iter()
+                .map(|(id, batch)| {
+                    let position =
+                        (scene.scene_node(*id).world_matrix * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz();
+                    (glm::distance2(&position, &camera_position), batch)
+                })
+                .collect();
+            translucent_batches.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+            render_pass.set_pipeline(&self.transparent_render_pipeline);
+            for (_, batch) in translucent_batches {
+                render_pass.set_bind_group(2, &batch.material.bind_group, &[]);
+                draw_batch(&mut render_pass, batch);
+            }
+
+            // Particles are drawn last, as they are translucent too. The particles of one
+            // emitter aren't sorted, which is fine with additive blending and with small,
+            // short-lived particles in general.
+            for id in scene.emitters.iter() {
+                let emitter = match &scene.scene_node(*id).node.kind {
+                    NodeKind::Emitter(e) if !e.particles.is_empty() => e,
+                    _ => continue,
+                };
+                render_pass.set_pipeline(match emitter.cfg.blend_mode {
+                    BlendMode::Alpha => &self.particle_pipeline,
+                    BlendMode::Additive => &self.additive_particle_pipeline,
+                });
+                render_pass.set_bind_group(0, &scene.uniform_bind_group, &[]);
+                render_pass.set_bind_group(1, &emitter.cfg.texture.bind_group, &[]);
+                render_pass.set_vertex_buffer(0, emitter.instance_buffer.slice(..));
+                // The particle quads are generated in the vertex shader
+                render_pass.draw(0..6, 0..emitter.particles.len() as u32);
             }
         }
 
@@ -772,14 +975,12 @@ impl Instance {
             render_pass.set_pipeline(&self.render_2d_pipeline);
 
             for obj in objects2d.iter() {
-                render_pass.set_bind_group(0, &obj.object.uniform_bind_group, &[]);
-                render_pass.set_bind_group(1, &obj.object.texture.bind_group, &[]);
-                render_pass.set_vertex_buffer(0, obj.object.shape.vertex_buffer.slice(..));
-                render_pass.set_index_buffer(
-                    obj.object.shape.index_buffer.slice(..),
-                    Vertex2d::index_format(),
-                );
-                render_pass.draw_indexed(0..obj.object.shape.index_count as u32, 0, 0..1);
+                render_pass.set_bind_group(0, &obj.uniform_bind_group, &[]);
+                render_pass.set_bind_group(1, &obj.texture.bind_group, &[]);
+                render_pass.set_vertex_buffer(0, obj.shape.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(obj.shape.index_buffer.slice(..), Vertex2d::index_format());
+                render_pass.draw_indexed(0..obj.shape.index_count as u32, 0, 0..1);
             }
         }
 
@@ -787,8 +988,92 @@ impl Instance {
     }
 }
 
+// Draw all the instances of a batch. The material bind group and the instance buffer must
+// already be set.
+fn draw_batch<'a>(render_pass: &mut wgpu::RenderPass<'a>, batch: &'a Batch) {
+    render_pass.set_vertex_buffer(0, batch.shape.vertex_buffer.slice(..));
+    render_pass.set_index_buffer(batch.shape.index_buffer.slice(..), Vertex::index_format());
+    render_pass.draw_indexed(
+        0..batch.shape.index_count as u32,
+        0,
+        batch.instances.clone(),
+    );
+}
+
+// Create a pipeline for drawing particles as camera facing quads. dst_factor determines the
+// blending: OneMinusSrcAlpha for normal alpha blending, One for additive blending.
+fn create_particle_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    msaa_samples: u32,
+    dst_factor: wgpu::BlendFactor,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: vs_module,
+            entry_point: "main",
+            buffers: &[ParticleInstance::buffer_layout()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: fs_module,
+            entry_point: "main",
+            targets: &[wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None, // The quads always face the camera
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            // Particles are depth tested against the scene, but don't occlude each other
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState {
+                constant: 0,
+                slope_scale: 0.0,
+                clamp: 0.0,
+            },
+            clamp_depth: false,
+        }),
+        multisample: wgpu::MultisampleState {
+            count: msaa_samples,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+    })
+}
+
 pub struct Scene {
-    nodes: Vec<SceneNode>,
+    nodes: Vec<NodeSlot>,
+    free_slots: Vec<usize>, // Indices of the empty slots in nodes
+    root_nodes: Vec<NodeId>,
     lights: Vec<Light>,
     view_matrix: glm::Mat4x4,
     perspective_matrix: glm::Mat4x4,
@@ -796,6 +1081,14 @@ pub struct Scene {
     scene_uniform_buffer: wgpu::Buffer,
     light_uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
+    batches: Vec<Batch>,                       // Opaque and alpha masked objects
+    translucent_batches: Vec<(NodeId, Batch)>, // One batch per object, sorted when rendering
+    emitters: Vec<NodeId>,                     // Visible particle emitters
+    batches_dirty: bool, // Nodes have been added, removed or hidden since the batches were built
+    instances: Vec<InstanceData>,
+    instance_buffer: wgpu::Buffer,
+    instance_buffer_capacity: usize,
+    high_contrast: bool,
 }
 
 impl Scene {
@@ -828,8 +1121,11 @@ impl Scene {
                 },
             ],
         });
+        let instance_buffer_capacity = 64;
         Scene {
             nodes: Vec::new(),
+            free_slots: Vec::new(),
+            root_nodes: Vec::new(),
             lights: Vec::new(),
             view_matrix: glm::look_at(
                 &glm::vec3(0.0, 5.0, 5.0),
@@ -852,16 +1148,134 @@ impl Scene {
             scene_uniform_buffer,
             light_uniform_buffer,
             uniform_bind_group,
+            batches: Vec::new(),
+            translucent_batches: Vec::new(),
+            emitters: Vec::new(),
+            batches_dirty: false,
+            instances: Vec::new(),
+            instance_buffer: create_instance_buffer(inst, instance_buffer_capacity),
+            instance_buffer_capacity,
+            high_contrast: false,
         }
     }
 
     pub fn add_node(&mut self, node: Node, parent: Option<NodeId>) -> NodeId {
-        self.nodes.push(SceneNode { node, parent });
-        self.nodes.len() - 1
+        let scene_node = SceneNode {
+            node,
+            parent,
+            children: Vec::new(),
+            visible: true,
+            world_matrix: glm::identity(),
+            instance: None,
+        };
+        let id = match self.free_slots.pop() {
+            Some(index) => {
+                let slot = &mut self.nodes[index];
+                slot.node = Some(scene_node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.nodes.push(NodeSlot {
+                    generation: 0,
+                    node: Some(scene_node),
+                });
+                NodeId {
+                    index: self.nodes.len() - 1,
+                    generation: 0,
+                }
+            }
+        };
+        self.attach(id, parent);
+        self.batches_dirty = true;
+        id
+    }
+
+    /// Remove a node and all its descendants from the scene. The ids of the removed nodes become
+    /// invalid, and are never handed out again by add_node().
+    pub fn remove_node(&mut self, id: NodeId) {
+        self.detach(id);
+        for removed in self.descendants(id).collect::<Vec<_>>() {
+            let slot = &mut self.nodes[removed.index];
+            slot.node = None;
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free_slots.push(removed.index);
+        }
+        self.batches_dirty = true;
+    }
+
+    /// Move a node (with its descendants) under a new parent, or make it a root node if parent
+    /// is None. The local transformation of the node is kept.
+    pub fn reparent(&mut self, id: NodeId, parent: Option<NodeId>) {
+        if let Some(p) = parent {
+            assert!(
+                !self.descendants(id).any(|d| d == p),
+                "Node can't be moved under its own descendant"
+            );
+        }
+        self.detach(id);
+        self.attach(id, parent);
+        self.scene_node_mut(id).node.model_matrix_changed = true;
+        self.batches_dirty = true;
+    }
+
+    pub fn contains_node(&self, id: NodeId) -> bool {
+        self.nodes.get(id.index).map_or(false, |slot| {
+            slot.generation == id.generation && slot.node.is_some()
+        })
     }
 
     pub fn get_node(&mut self, id: NodeId) -> &mut Node {
-        &mut self.nodes[id].node
+        &mut self.scene_node_mut(id).node
+    }
+
+    /// Hiding a node hides also all its descendants. Hidden objects aren't drawn and don't cast
+    /// shadows.
+    pub fn set_visible(&mut self, id: NodeId, visible: bool) {
+        let n = self.scene_node_mut(id);
+        if n.visible != visible {
+            n.visible = visible;
+            self.batches_dirty = true;
+        }
+    }
+
+    pub fn is_visible(&self, id: NodeId) -> bool {
+        self.scene_node(id).visible
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.scene_node(id).parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.scene_node(id).children
+    }
+
+    pub fn root_nodes(&self) -> &[NodeId] {
+        &self.root_nodes
+    }
+
+    /// Iterate a node and all its descendants, depth first with parents before their children
+    pub fn descendants(&self, id: NodeId) -> SceneIterator {
+        SceneIterator {
+            scene: self,
+            stack: vec![id],
+        }
+    }
+
+    /// Find the first node with the given name, see Node::set_name()
+    pub fn find_node(&self, name: &str) -> Option<NodeId> {
+        self.node_ids()
+            .find(|id| self.scene_node(*id).node.name.as_deref() == Some(name))
+    }
+
+    /// Find all nodes with the given tag, see Node::add_tag()
+    pub fn find_nodes_with_tag(&self, tag: &str) -> Vec<NodeId> {
+        self.node_ids()
+            .filter(|id| self.scene_node(*id).node.has_tag(tag))
+            .collect()
     }
 
     pub fn look_at(
@@ -879,53 +1293,336 @@ impl Scene {
             &glm::vec3(0.0, 1.0, 0.0),
         );
     }
-}
 
-pub struct Shape {
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    index_count: usize,
-}
+    /// In the high contrast mode, materials with a high contrast color are drawn in that flat
+    /// color instead of their texture
+    pub fn set_high_contrast(&mut self, enabled: bool) {
+        self.high_contrast = enabled;
+    }
 
-impl Shape {
-    fn from_ply(inst: &Instance, name: &str, ply: &str) -> Shape {
-        // Helper functions for reading PLY properties
-        fn as_float(property: &ply_rs::ply::Property) -> f32 {
-            match property {
-                ply_rs::ply::Property::Float(value) => *value,
-                _ => panic!("Property was not Float"),
-            }
+    /// Place the camera at height above the point (x, 0, z), looking straight down with the
+    /// negative z-axis pointing up on the screen
+    pub fn look_down_at(&mut self, x: f32, z: f32, height: f32) {
+        self.view_matrix = glm::look_at(
+            &glm::vec3(x, height, z),
+            &glm::vec3(x, 0.0, z),
+            &glm::vec3(0.0, 0.0, -1.0),
+        );
+    }
+
+    /// Project a world space point to normalized device coordinates, x and y in -1...1 when
+    /// the point is visible
+    pub fn world_to_screen(&self, p: &glm::Vec3) -> glm::Vec2 {
+        let clip = self.perspective_matrix * self.view_matrix * glm::vec4(p.x, p.y, p.z, 1.0);
+        clip.xy() / clip.w
+    }
+
+    /// Find the world space point on the horizontal plane at height y that is seen at the
+    /// normalized device coordinates (x, y). Returns None if the plane is not visible there.
+    pub fn screen_to_plane(&self, ndc: &glm::Vec2, y: f32) -> Option<glm::Vec3> {
+        let inv = glm::inverse(&(self.perspective_matrix * self.view_matrix));
+        let unproject = |depth: f32| {
+            let p = inv * glm::vec4(ndc.x, ndc.y, depth, 1.0);
+            p.xyz() / p.w
+        };
+        let near = unproject(0.0);
+        let dir = unproject(1.0) - near;
+        if dir.y == 0.0 {
+            return None;
         }
-        fn as_list_uint(property: &ply_rs::ply::Property) -> Vec<u32> {
-            match property {
-                ply_rs::ply::Property::ListUInt(value) => value.clone(),
-                _ => panic!("Property was not ListUInt"),
+        let t = (y - near.y) / dir.y;
+        if t < 0.0 {
+            return None;
+        }
+        Some(near + t * dir)
+    }
+
+    /// Spawn count particles at once from an emitter node, e.g. for an explosion. The particles
+    /// start from the current position of the node, even if it was moved after the latest
+    /// render.
+    pub fn emit_particles(&mut self, id: NodeId, count: usize) {
+        let origin = self.current_position(id);
+        match &mut self.scene_node_mut(id).node.kind {
+            NodeKind::Emitter(e) => e.spawn(&origin, count),
+            _ => panic!("Node {:?} is not an emitter", id),
+        }
+    }
+
+    /// Advance the particle simulation of all the emitters by dt seconds. Visible emitters
+    /// with a non-zero rate also spawn new particles.
+    pub fn update_particles(&mut self, dt: f32) {
+        let emitter_ids: Vec<NodeId> = self
+            .node_ids()
+            .filter(|id| matches!(self.scene_node(*id).node.kind, NodeKind::Emitter(_)))
+            .collect();
+        for id in emitter_ids {
+            let origin = self.current_position(id);
+            let shown = self.is_shown(id);
+            if let NodeKind::Emitter(e) = &mut self.scene_node_mut(id).node.kind {
+                e.simulate(dt);
+                if shown {
+                    e.spawn_accumulator += e.cfg.rate * dt;
+                    let count = e.spawn_accumulator.floor();
+                    e.spawn_accumulator -= count;
+                    e.spawn(&origin, count as usize);
+                }
             }
         }
+    }
+
+    /// Returns true if any emitter has live particles, i.e. the scene is animating
+    pub fn has_particles(&self) -> bool {
+        self.node_ids()
+            .any(|id| match &self.scene_node(id).node.kind {
+                NodeKind::Emitter(e) => !e.particles.is_empty(),
+                _ => false,
+            })
+    }
+
+    fn camera_position(&self) -> glm::Vec3 {
+        (glm::inverse(&self.view_matrix) * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz()
+    }
+
+    // World space position of a node, calculated from the current model matrices instead of the
+    // world matrices updated when rendering
+    fn current_position(&self, id: NodeId) -> glm::Vec3 {
+        let mut m = glm::identity();
+        let mut node = Some(id);
+        while let Some(id) = node {
+            let n = self.scene_node(id);
+            m = n.node.model_matrix * m;
+            node = n.parent;
+        }
+        (m * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz()
+    }
 
-        let mut bytes = ply.as_bytes();
-        let ply = ply_rs::parser::Parser::<ply_rs::ply::DefaultElement>::new()
-            .read_ply(&mut bytes)
-            .unwrap();
+    // True if the node and all its ancestors are visible
+    fn is_shown(&self, id: NodeId) -> bool {
+        let n = self.scene_node(id);
+        n.visible && n.parent.map_or(true, |p| self.is_shown(p))
+    }
+
+    fn scene_node(&self, id: NodeId) -> &SceneNode {
+        match self.nodes.get(id.index) {
+            Some(NodeSlot {
+                generation,
+                node: Some(n),
+            }) if *generation == id.generation => n,
+            _ => panic!("Invalid node id {:?}", id),
+        }
+    }
 
-        let vertices: Vec<Vertex> = ply.payload["vertex"]
+    fn scene_node_mut(&mut self, id: NodeId) -> &mut SceneNode {
+        match self.nodes.get_mut(id.index) {
+            Some(NodeSlot {
+                generation,
+                node: Some(n),
+            }) if *generation == id.generation => n,
+            _ => panic!("Invalid node id {:?}", id),
+        }
+    }
+
+    // Ids of all the nodes in the scene, in no particular order
+    fn node_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes
             .iter()
-            .map(|v| Vertex {
-                position: [as_float(&v["x"]), as_float(&v["y"]), as_float(&v["z"])],
-                normal: [as_float(&v["nx"]), as_float(&v["ny"]), as_float(&v["nz"])],
-                tex_coords: [as_float(&v["s"]), as_float(&v["t"])],
+            .enumerate()
+            .filter(|(_, slot)| slot.node.is_some())
+            .map(|(index, slot)| NodeId {
+                index,
+                generation: slot.generation,
             })
+    }
+
+    fn attach(&mut self, id: NodeId, parent: Option<NodeId>) {
+        match parent {
+            Some(p) => self.scene_node_mut(p).children.push(id),
+            None => self.root_nodes.push(id),
+        }
+        self.scene_node_mut(id).parent = parent;
+    }
+
+    fn detach(&mut self, id: NodeId) {
+        let parent = self.scene_node(id).parent;
+        let siblings = match parent {
+            Some(p) => &mut self.scene_node_mut(p).children,
+            None => &mut self.root_nodes,
+        };
+        siblings.retain(|c| *c != id);
+    }
+
+    // Group the objects to batches that can be drawn with one instanced draw call, and assign
+    // each object a slot in the instance buffer. Batches are sorted by material and shape to
+    // minimize state changes between the draw calls.
+    fn build_batches(&mut self) {
+        let mut objects: Vec<(NodeId, &Object)> = Vec::new();
+        let mut emitters = Vec::new();
+        let mut stack: Vec<NodeId> = self.root_nodes.iter().rev().copied().collect();
+        while let Some(id) = stack.pop() {
+            let n = self.scene_node(id);
+            if !n.visible {
+                continue; // skip the whole subtree
+            }
+            match &n.node.kind {
+                NodeKind::Object(obj) => objects.push((id, obj)),
+                NodeKind::Emitter(_) => emitters.push(id),
+                NodeKind::Transformation => (),
+            }
+            stack.extend(n.children.iter().rev());
+        }
+        objects.sort_by_key(|(_, obj)| {
+            (
+                obj.material.alpha_mode == AlphaMode::Blend,
+                Rc::as_ptr(&obj.material) as usize,
+                Rc::as_ptr(&obj.shape) as usize,
+            )
+        });
+
+        let mut batches: Vec<Batch> = Vec::new();
+        let mut translucent_batches = Vec::new();
+        let mut instance_of_node = Vec::new();
+        for (i, (id, obj)) in objects.iter().enumerate() {
+            let i = i as u32;
+            instance_of_node.push((*id, i));
+            if obj.material.alpha_mode == AlphaMode::Blend {
+                translucent_batches.push((*id, Batch::new(obj, i)));
+                continue;
+            }
+            match batches.last_mut() {
+                Some(batch)
+                    if Rc::ptr_eq(&batch.shape, &obj.shape)
+                        && Rc::ptr_eq(&batch.material, &obj.material) =>
+                {
+                    batch.instances.end += 1
+                }
+                _ => batches.push(Batch::new(obj, i)),
+            }
+        }
+
+        let instance_count = objects.len();
+        self.instances = vec![InstanceData::from(&glm::identity()); instance_count];
+        for n in self.nodes.iter_mut().filter_map(|slot| slot.node.as_mut()) {
+            n.instance = None;
+        }
+        for (id, i) in instance_of_node {
+            self.scene_node_mut(id).instance = Some(i as usize);
+        }
+        self.batches = batches;
+        self.translucent_batches = translucent_batches;
+        self.emitters = emitters;
+    }
+
+    // Upload the particles of the visible emitters to their instance buffers
+    fn update_particle_buffers(&self, inst: &Instance) {
+        for id in self.emitters.iter() {
+            if let NodeKind::Emitter(e) = &self.scene_node(*id).node.kind {
+                if !e.particles.is_empty() {
+                    inst.queue.write_buffer(
+                        &e.instance_buffer,
+                        0,
+                        bytemuck::cast_slice(&e.instance_data()),
+                    );
+                }
+            }
+        }
+    }
+
+    // Update the world matrices of the nodes that have moved since the last frame, and upload
+    // the changed instance data to the GPU.
+    fn update_instances(&mut self, inst: &Instance) {
+        let rebuild = self.batches_dirty;
+        if rebuild {
+            self.build_batches();
+            self.batches_dirty = false;
+        }
+
+        let mut instances_changed = rebuild;
+        // Walk the hierarchy from the roots: (node, parent world matrix, parent changed)
+        let mut stack: Vec<(NodeId, glm::Mat4x4, bool)> = self
+            .root_nodes
+            .iter()
+            .map(|id| (*id, glm::identity(), false))
             .collect();
+        while let Some((id, parent_matrix, parent_changed)) = stack.pop() {
+            let n = match self.nodes[id.index].node.as_mut() {
+                Some(n) => n,
+                None => continue,
+            };
+            let changed = rebuild || parent_changed || n.node.model_matrix_changed;
+            if changed {
+                n.world_matrix = parent_matrix * n.node.model_matrix;
+                n.node.model_matrix_changed = false;
+                if let Some(i) = n.instance {
+                    self.instances[i] = InstanceData::from(&n.world_matrix);
+                    instances_changed = true;
+                }
+            }
+            for c in n.children.iter() {
+                stack.push((*c, n.world_matrix, changed));
+            }
+        }
+
+        if !instances_changed {
+            return;
+        }
+        if self.instances.len() > self.instance_buffer_capacity {
+            self.instance_buffer_capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = create_instance_buffer(inst, self.instance_buffer_capacity);
+        }
+        inst.queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.instances),
+        );
+    }
+}
+
+fn create_instance_buffer(inst: &Instance, capacity: usize) -> wgpu::Buffer {
+    inst.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Instance buffer"),
+        size: (capacity * std::mem::size_of::<InstanceData>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+// Objects with the same shape and material, drawn with one instanced draw call
+struct Batch {
+    shape: Rc<Shape>,
+    material: Rc<Material>,
+    instances: Range<u32>, // Indices to the scene instance buffer
+}
 
-        let indices: Vec<u32> = ply.payload["face"]
+impl Batch {
+    fn new(obj: &Object, first_instance: u32) -> Batch {
+        Batch {
+            shape: Rc::clone(&obj.shape),
+            material: Rc::clone(&obj.material),
+            instances: first_instance..first_instance + 1,
+        }
+    }
+}
+
+pub struct Shape {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: usize,
+}
+
+impl Shape {
+    fn from_mesh(inst: &Instance, name: &str, mesh: &mesh::Mesh) -> Shape {
+        let vertices: Vec<Vertex> = mesh
+            .positions
             .iter()
-            .map(|f| {
-                let vis = as_list_uint(&f["vertex_indices"]);
-                assert!(vis.len() == 3, "{} contains non-triangle faces", name);
-                vis
+            .zip(mesh.normals.iter())
+            .zip(mesh.tex_coords.iter())
+            .map(|((position, normal), tex_coords)| Vertex {
+                position: *position,
+                normal: *normal,
+                tex_coords: *tex_coords,
             })
-            .flatten()
             .collect();
+        let indices = &mesh.indices;
 
         let vertex_buffer = inst.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some(&format!("Vertex buffer {}", name)),
@@ -997,11 +1694,23 @@ impl Shape {
 }
 
 pub struct Texture {
-    bind_group: wgpu::BindGroup,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    bind_group: wgpu::BindGroup, // For 2D objects and particles
 }
 
 impl Texture {
-    fn from_image(inst: &Instance, name: &str, w: u32, h: u32, rgba_data: &[u8]) -> Texture {
+    fn from_image(
+        inst: &Instance,
+        name: &str,
+        w: u32,
+        h: u32,
+        rgba_data: &[u8],
+        address_mode: wgpu::AddressMode,
+    ) -> Texture {
+        // A mip level can't be smaller than 1x1, so small textures get fewer levels
+        let mipmap_levels = inst.config.mipmap_levels.min(32 - w.max(h).leading_zeros());
+
         // Create the texture, view, sampler, bind group...
         let tex = inst.device.create_texture(&wgpu::TextureDescriptor {
             label: Some(&format!("Texture {}", name)),
@@ -1010,7 +1719,7 @@ impl Texture {
                 height: h,
                 depth_or_array_layers: 1,
             },
-            mip_level_count: inst.config.mipmap_levels,
+            mip_level_count: mipmap_levels,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8Unorm,
@@ -1019,9 +1728,9 @@ impl Texture {
         let view = tex.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = inst.device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some(&format!("Sampler {}", name)),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
@@ -1065,11 +1774,11 @@ impl Texture {
         // Create mipmaps and upload their data
         let original: image::ImageBuffer<image::Rgba<u8>, _> =
             image::ImageBuffer::from_raw(w, h, rgba_data).unwrap();
-        for level in 1..inst.config.mipmap_levels {
+        for level in 1..mipmap_levels {
             let mipmap = image::imageops::resize(
                 &original,
-                w / 2u32.pow(level),
-                h / 2u32.pow(level),
+                (w >> level).max(1),
+                (h >> level).max(1),
                 image::imageops::FilterType::Triangle,
             );
             inst.queue.write_texture(
@@ -1092,20 +1801,240 @@ impl Texture {
             );
         }
 
-        Texture { bind_group }
+        Texture {
+            view,
+            sampler,
+            bind_group,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask(f32), // Fragments with alpha below the given cutoff value are discarded
+    Blend,
+}
+
+/// Parameters for creating a Material. The final surface color is the texture color multiplied
+/// by `base_color`. `specular` (0...1) scales the intensity of the specular highlights, and
+/// `roughness` (0...1) controls their size: polished surfaces have small roughness.
+/// `high_contrast_color` replaces the texture and the base color when the scene is in the high
+/// contrast mode.
+pub struct MaterialConfig {
+    pub base_color: [f32; 4],
+    pub texture: Rc<Texture>,
+    pub normal_map: Option<Rc<Texture>>,
+    pub specular: f32,
+    pub roughness: f32,
+    pub alpha_mode: AlphaMode,
+    pub high_contrast_color: Option<[f32; 4]>,
+}
+
+impl MaterialConfig {
+    pub fn new(texture: &Rc<Texture>) -> MaterialConfig {
+        MaterialConfig {
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            texture: Rc::clone(texture),
+            normal_map: None,
+            specular: 0.0,
+            roughness: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            high_contrast_color: None,
+        }
+    }
+}
+
+pub struct Material {
+    alpha_mode: AlphaMode,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Material {
+    fn new(inst: &Instance, name: &str, cfg: &MaterialConfig) -> Material {
+        let uniform_buffer = inst.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("Material uniform buffer {}", name)),
+            size: std::mem::size_of::<MaterialUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        inst.queue.write_buffer(
+            &uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[MaterialUniforms::from(cfg)]),
+        );
+        // Without a normal map, the base texture is bound in its place to satisfy the layout.
+        // The shader doesn't sample it in that case.
+        let normal_map = cfg.normal_map.as_ref().unwrap_or(&cfg.texture);
+        let bind_group = inst.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("Material bind group {}", name)),
+            layout: &inst.material_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cfg.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&cfg.texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&normal_map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&normal_map.sampler),
+                },
+            ],
+        });
+        Material {
+            alpha_mode: cfg.alpha_mode,
+            bind_group,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlendMode {
+    Alpha,
+    Additive, // For glowing effects: sparks, fire...
+}
+
+/// Parameters for creating a particle emitter. Particles are spawned within `spawn_radius` from
+/// the emitter node, and live in world space after that, i.e. they don't follow the node. The
+/// initial velocity is `velocity` plus a random vector up to `velocity_spread` long, and
+/// `acceleration` (e.g. gravity) applies to all the particles. Color and size change linearly
+/// from the start values to the end values over the lifetime of a particle.
+#[derive(Clone)]
+pub struct ParticleConfig {
+    pub texture: Rc<Texture>,
+    pub blend_mode: BlendMode,
+    pub rate: f32,     // Particles per second spawned continuously, 0 for bursts only
+    pub lifetime: f32, // in seconds
+    pub spawn_radius: f32,
+    pub velocity: [f32; 3],
+    pub velocity_spread: f32,
+    pub acceleration: [f32; 3],
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    pub start_size: f32,
+    pub end_size: f32,
+    pub max_particles: usize, // New particles aren't spawned while the emitter has this many
+}
+
+impl ParticleConfig {
+    pub fn new(texture: &Rc<Texture>) -> ParticleConfig {
+        ParticleConfig {
+            texture: Rc::clone(texture),
+            blend_mode: BlendMode::Alpha,
+            rate: 0.0,
+            lifetime: 1.0,
+            spawn_radius: 0.0,
+            velocity: [0.0, 0.0, 0.0],
+            velocity_spread: 0.0,
+            acceleration: [0.0, 0.0, 0.0],
+            start_color: [1.0, 1.0, 1.0, 1.0],
+            end_color: [1.0, 1.0, 1.0, 0.0],
+            start_size: 1.0,
+            end_size: 1.0,
+            max_particles: 100,
+        }
+    }
+}
+
+// CPU simulated particles, drawn as camera facing quads with one instanced draw call
+struct Emitter {
+    cfg: ParticleConfig,
+    particles: Vec<Particle>,
+    spawn_accumulator: f32, // Fraction of a particle left over from the previous continuous spawn
+    rng: u32,
+    instance_buffer: wgpu::Buffer,
+}
+
+struct Particle {
+    position: glm::Vec3,
+    velocity: glm::Vec3,
+    age: f32,
+}
+
+impl Emitter {
+    fn spawn(&mut self, origin: &glm::Vec3, count: usize) {
+        let count = count.min(self.cfg.max_particles - self.particles.len());
+        for _ in 0..count {
+            let position = origin + self.cfg.spawn_radius * self.random_in_unit_sphere();
+            let velocity = glm::make_vec3(&self.cfg.velocity)
+                + self.cfg.velocity_spread * self.random_in_unit_sphere();
+            self.particles.push(Particle {
+                position,
+                velocity,
+                age: 0.0,
+            });
+        }
+    }
+
+    fn simulate(&mut self, dt: f32) {
+        let lifetime = self.cfg.lifetime;
+        self.particles.retain(|p| p.age + dt < lifetime);
+        let acceleration = glm::make_vec3(&self.cfg.acceleration);
+        for p in self.particles.iter_mut() {
+            p.age += dt;
+            p.velocity += dt * acceleration;
+            p.position += dt * p.velocity;
+        }
+    }
+
+    fn instance_data(&self) -> Vec<ParticleInstance> {
+        let start_color = glm::make_vec4(&self.cfg.start_color);
+        let end_color = glm::make_vec4(&self.cfg.end_color);
+        self.particles
+            .iter()
+            .map(|p| {
+                let t = p.age / self.cfg.lifetime;
+                ParticleInstance {
+                    position: p.position.into(),
+                    size: self.cfg.start_size + t * (self.cfg.end_size - self.cfg.start_size),
+                    color: glm::lerp(&start_color, &end_color, t).into(),
+                }
+            })
+            .collect()
+    }
+
+    // xorshift, uniform in 0...1
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32
+    }
+
+    fn random_in_unit_sphere(&mut self) -> glm::Vec3 {
+        loop {
+            let v = glm::vec3(
+                2.0 * self.random() - 1.0,
+                2.0 * self.random() - 1.0,
+                2.0 * self.random() - 1.0,
+            );
+            if glm::length2(&v) <= 1.0 {
+                return v;
+            }
+        }
     }
 }
 
 struct Object {
     shape: Rc<Shape>,
-    texture: Rc<Texture>,
-    uniform_buffer: wgpu::Buffer,
-    uniform_bind_group: wgpu::BindGroup,
+    material: Rc<Material>,
 }
 
 enum NodeKind {
     Object(Object),
     Transformation,
+    Emitter(Emitter),
 }
 
 pub struct Node {
@@ -1114,6 +2043,9 @@ pub struct Node {
     rotation: glm::Mat4x4,
     translation: glm::Vec3,
     model_matrix: glm::Mat4x4,
+    model_matrix_changed: bool, // The scene world matrices need to be updated
+    name: Option<String>,
+    tags: Vec<String>,
 }
 
 impl Node {
@@ -1124,9 +2056,33 @@ impl Node {
             rotation: glm::identity(),
             translation: glm::vec3(0.0, 0.0, 0.0),
             model_matrix: glm::identity(),
+            model_matrix_changed: true,
+            name: None,
+            tags: Vec::new(),
         }
     }
 
+    /// Set a name for finding the node with Scene::find_node()
+    pub fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Add a tag for finding the node with Scene::find_nodes_with_tag(). A node can have any
+    /// number of tags, and the same tag can be used on many nodes.
+    pub fn add_tag(&mut self, tag: &str) {
+        if !self.has_tag(tag) {
+            self.tags.push(tag.to_string());
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
     pub fn set_scaling(&mut self, x: f32, y: f32, z: f32) {
         self.scaling = glm::vec3(x, y, z);
         self.update_model_matrix();
@@ -1153,56 +2109,70 @@ impl Node {
     fn update_model_matrix(&mut self) {
         self.model_matrix =
             glm::translation(&self.translation) * self.rotation * glm::scaling(&self.scaling);
+        self.model_matrix_changed = true;
     }
 }
 
-pub type NodeId = usize;
+/// Handle to a node in a Scene. The generation makes the ids of removed nodes invalid even if
+/// their slot in the scene gets reused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+struct NodeSlot {
+    generation: u32,
+    node: Option<SceneNode>,
+}
 
 struct SceneNode {
     node: Node,
     parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    visible: bool,
+    world_matrix: glm::Mat4x4, // Model matrix combined with the model matrices of all ancestors
+    instance: Option<usize>,   // Index to the scene instance data, for visible object nodes
 }
 
-// Iterate scene nodes towards the root
-struct SceneIterator<'a> {
-    nodes: &'a Vec<SceneNode>,
-    next: Option<NodeId>,
-}
-
-impl<'a> SceneIterator<'a> {
-    fn new(scene: &'a Scene, id: NodeId) -> Self {
-        SceneIterator {
-            nodes: &scene.nodes,
-            next: Some(id),
-        }
-    }
+// Iterate a subtree of the scene depth first
+pub struct SceneIterator<'a> {
+    scene: &'a Scene,
+    stack: Vec<NodeId>,
 }
 
 impl<'a> Iterator for SceneIterator<'a> {
-    type Item = &'a Node;
+    type Item = NodeId;
     fn next(&mut self) -> Option<Self::Item> {
-        match self.next {
-            None => None,
-            Some(id) => {
-                let n = &self.nodes[id];
-                self.next = n.parent;
-                Some(&n.node)
-            }
-        }
+        let id = self.stack.pop()?;
+        self.stack
+            .extend(self.scene.scene_node(id).children.iter().rev());
+        Some(id)
     }
 }
 
 pub struct Object2d {
-    object: Object,
+    shape: Rc<Shape>,
+    texture: Rc<Texture>,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
     scaling: glm::Vec2,
     translation: glm::Vec2,
     model_matrix: glm::Mat3x3,
 }
 
 impl Object2d {
-    fn new(object: Object) -> Object2d {
+    fn new(
+        shape: Rc<Shape>,
+        texture: Rc<Texture>,
+        uniform_buffer: wgpu::Buffer,
+        uniform_bind_group: wgpu::BindGroup,
+    ) -> Object2d {
         Object2d {
-            object,
+            shape,
+            texture,
+            uniform_buffer,
+            uniform_bind_group,
             scaling: glm::vec2(1.0, 1.0),
             translation: glm::vec2(0.0, 0.0),
             model_matrix: glm::identity(),
@@ -1340,33 +2310,143 @@ impl LightUniforms {
 #[derive(Debug, Copy, Clone, bytemuck_derive::Pod, bytemuck_derive::Zeroable)]
 struct SceneUniforms {
     view_projection: RawMat4,
-    num_lights: [u32; 4], // Only x component used
+    num_lights: [u32; 4],               // Only x component used
+    camera_pos_world_space: [f32; 4],   // Only xyz components used
+    camera_right_world_space: [f32; 4], // Only xyz components used. For billboarding particles.
+    camera_up_world_space: [f32; 4],    // Only xyz components used. For billboarding particles.
+    render_mode: [u32; 4],              // high contrast mode enabled (0 or 1), rest unused
 }
 
 impl SceneUniforms {
-    fn from(view_projection: &glm::Mat4, num_lights: u32) -> SceneUniforms {
+    fn from(
+        view: &glm::Mat4,
+        projection: &glm::Mat4,
+        num_lights: u32,
+        high_contrast: bool,
+    ) -> SceneUniforms {
+        let camera_pos = (glm::inverse(view) * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz();
+        // The first two rows of the view matrix are the camera x and y axes in world space
+        let camera_axis = |row: usize| [view[(row, 0)], view[(row, 1)], view[(row, 2)], 0.0];
         SceneUniforms {
-            view_projection: view_projection.clone().into(),
+            view_projection: (projection * view).into(),
             num_lights: [num_lights, 0, 0, 0],
+            camera_pos_world_space: glm::vec3_to_vec4(&camera_pos).into(),
+            camera_right_world_space: camera_axis(0),
+            camera_up_world_space: camera_axis(1),
+            render_mode: [high_contrast as u32, 0, 0, 0],
         }
     }
 }
 
-// Uniforms related to one scene object
+// Uniforms related to one material
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck_derive::Pod, bytemuck_derive::Zeroable)]
-struct ObjectUniforms {
+struct MaterialUniforms {
+    base_color: [f32; 4],
+    params: [f32; 4], // specular, roughness, alpha cutoff, normal map enabled (0 or 1)
+    high_contrast_color: [f32; 4], // alpha is negative if the material has no high contrast color
+}
+
+impl MaterialUniforms {
+    fn from(cfg: &MaterialConfig) -> MaterialUniforms {
+        MaterialUniforms {
+            base_color: cfg.base_color,
+            params: [
+                cfg.specular,
+                cfg.roughness,
+                match cfg.alpha_mode {
+                    AlphaMode::Mask(cutoff) => cutoff,
+                    AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+                },
+                if cfg.normal_map.is_some() { 1.0 } else { 0.0 },
+            ],
+            high_contrast_color: cfg.high_contrast_color.unwrap_or([0.0, 0.0, 0.0, -1.0]),
+        }
+    }
+}
+
+// Per-instance vertex data for one scene object
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck_derive::Pod, bytemuck_derive::Zeroable)]
+struct InstanceData {
     model: RawMat4,
     model_normal: RawMat4,
 }
 
-impl ObjectUniforms {
-    fn from(m: &glm::Mat4) -> ObjectUniforms {
-        ObjectUniforms {
+// The matrices are passed as 4 vec4 attributes each, following the Vertex attributes
+const fn instance_attribute(column: u32) -> wgpu::VertexAttribute {
+    wgpu::VertexAttribute {
+        offset: column as wgpu::BufferAddress
+            * std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+        shader_location: 3 + column,
+        format: wgpu::VertexFormat::Float32x4,
+    }
+}
+
+const INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 8] = [
+    instance_attribute(0), // model
+    instance_attribute(1),
+    instance_attribute(2),
+    instance_attribute(3),
+    instance_attribute(4), // model_normal
+    instance_attribute(5),
+    instance_attribute(6),
+    instance_attribute(7),
+];
+
+impl InstanceData {
+    fn from(m: &glm::Mat4) -> InstanceData {
+        InstanceData {
             model: m.clone().into(),
             model_normal: glm::transpose(&glm::inverse(m)).into(),
         }
     }
+
+    fn buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceData>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &INSTANCE_ATTRIBUTES,
+        }
+    }
+}
+
+// Per-instance vertex data for one particle
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck_derive::Pod, bytemuck_derive::Zeroable)]
+struct ParticleInstance {
+    position: [f32; 3],
+    size: f32,
+    color: [f32; 4],
+}
+
+impl ParticleInstance {
+    fn buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ParticleInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    // position
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    // size
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    // color
+                    offset: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
 }
 
 // Uniforms related to one 2D object
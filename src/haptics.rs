@@ -0,0 +1,252 @@
+use std::time::Duration;
+
+use crate::game;
+
+// Haptic feedback for the ball hitting walls. The pulses are derived from the game events, and
+// played by a platform specific Haptics implementation: the Android Vibrator service on
+// Android, and a logger recording the pulses elsewhere.
+
+const FULL_IMPULSE: f32 = 20.0 * game::BALL_R; // impulse giving the strongest pulse
+const MIN_PULSE_MS: f32 = 10.0;
+const MAX_PULSE_MS: f32 = 40.0;
+const MIN_AMPLITUDE: f32 = 0.2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pulse {
+    pub duration: Duration,
+    pub amplitude: f32, // 0...1
+}
+
+pub trait Haptics {
+    fn vibrate(&mut self, pulse: Pulse);
+}
+
+/// Calculate the pulse for the strongest impact in events, if any
+pub fn pulse_for_events(events: &[game::Event]) -> Option<Pulse> {
+    // Impacts happening in the same update are felt as one, so only the strongest one counts
+    let impulse = events
+        .iter()
+        .filter_map(|event| match event.kind {
+            game::EventKind::WallImpact { impulse, .. } => Some(impulse),
+            game::EventKind::EdgeHit { impulse, .. } => Some(impulse),
+            _ => None,
+        })
+        .filter(|impulse| impulse.is_finite())
+        .fold(None, |max: Option<f32>, impulse| {
+            Some(max.map_or(impulse, |max| max.max(impulse)))
+        })?;
+    let strength = (impulse / FULL_IMPULSE).min(1.0);
+    Some(Pulse {
+        duration: Duration::from_secs_f32(
+            (MIN_PULSE_MS + strength * (MAX_PULSE_MS - MIN_PULSE_MS)) / 1000.0,
+        ),
+        amplitude: MIN_AMPLITUDE + strength * (1.0 - MIN_AMPLITUDE),
+    })
+}
+
+/// Play the pulse for the events of the latest game update
+pub fn handle_events(haptics: &mut dyn Haptics, events: &[game::Event]) {
+    if let Some(pulse) = pulse_for_events(events) {
+        haptics.vibrate(pulse);
+    }
+}
+
+/// Create the Haptics implementation for the current platform
+pub fn create() -> Box<dyn Haptics> {
+    #[cfg(target_os = "android")]
+    {
+        if let Some(haptics) = AndroidHaptics::new() {
+            return Box::new(haptics);
+        }
+        println!("Vibrator not available, logging haptic feedback instead");
+    }
+    Box::new(LoggingHaptics::new())
+}
+
+/// Logs the pulses and records them in pulses instead of vibrating anything
+pub struct LoggingHaptics {
+    pub pulses: Vec<Pulse>,
+}
+
+impl LoggingHaptics {
+    pub fn new() -> LoggingHaptics {
+        LoggingHaptics { pulses: Vec::new() }
+    }
+}
+
+impl Haptics for LoggingHaptics {
+    fn vibrate(&mut self, pulse: Pulse) {
+        println!(
+            "Haptic pulse {} ms, amplitude {:.2}",
+            pulse.duration.as_millis(),
+            pulse.amplitude
+        );
+        self.pulses.push(pulse);
+    }
+}
+
+/// Vibrates the device using the Android Vibrator system service through JNI. Requires the
+/// android.permission.VIBRATE permission in the app manifest.
+#[cfg(target_os = "android")]
+pub struct AndroidHaptics {
+    vm: jni::JavaVM,
+    vibrator: jni::objects::GlobalRef,
+    sdk_version: i32,
+}
+
+#[cfg(target_os = "android")]
+impl AndroidHaptics {
+    // VibrationEffect with amplitude is available starting from API level 26
+    const VIBRATION_EFFECT_SDK_VERSION: i32 = 26;
+
+    pub fn new() -> Option<AndroidHaptics> {
+        let activity = ndk_glue::native_activity();
+        let vm = unsafe { jni::JavaVM::from_raw(activity.vm() as *mut jni::sys::JavaVM) }.ok()?;
+        let env = vm.attach_current_thread_permanently().ok()?;
+        match Self::get_vibrator(&env, jni::objects::JObject::from(activity.activity())) {
+            Ok(Some((vibrator, sdk_version))) => Some(AndroidHaptics {
+                vm,
+                vibrator,
+                sdk_version,
+            }),
+            Ok(None) => None,
+            Err(e) => {
+                println!("Failed to get the vibrator service: {}", e);
+                let _ = env.exception_clear();
+                None
+            }
+        }
+    }
+
+    // Returns the Vibrator service and the SDK version, or None if the device can't vibrate
+    fn get_vibrator(
+        env: &jni::JNIEnv,
+        activity: jni::objects::JObject,
+    ) -> jni::errors::Result<Option<(jni::objects::GlobalRef, i32)>> {
+        let sdk_version = env
+            .get_static_field("android/os/Build$VERSION", "SDK_INT", "I")?
+            .i()?;
+        let vibrator = env
+            .call_method(
+                activity,
+                "getSystemService",
+                "(Ljava/lang/String;)Ljava/lang/Object;",
+                &[env.new_string("vibrator")?.into()],
+            )?
+            .l()?;
+        if vibrator.is_null() || !env.call_method(vibrator, "hasVibrator", "()Z", &[])?.z()? {
+            return Ok(None);
+        }
+        Ok(Some((env.new_global_ref(vibrator)?, sdk_version)))
+    }
+
+    fn try_vibrate(&self, pulse: Pulse) -> jni::errors::Result<()> {
+        let env = self.vm.attach_current_thread_permanently()?;
+        let ms = jni::objects::JValue::Long(pulse.duration.as_millis() as i64);
+        if self.sdk_version >= Self::VIBRATION_EFFECT_SDK_VERSION {
+            let amplitude = (pulse.amplitude * 255.0).round().max(1.0).min(255.0) as i32;
+            let effect = env
+                .call_static_method(
+                    "android/os/VibrationEffect",
+                    "createOneShot",
+                    "(JI)Landroid/os/VibrationEffect;",
+                    &[ms, jni::objects::JValue::Int(amplitude)],
+                )?
+                .l()?;
+            env.call_method(
+                self.vibrator.as_obj(),
+                "vibrate",
+                "(Landroid/os/VibrationEffect;)V",
+                &[effect.into()],
+            )?;
+        } else {
+            // No amplitude control, so only the duration varies
+            env.call_method(self.vibrator.as_obj(), "vibrate", "(J)V", &[ms])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "android")]
+impl Haptics for AndroidHaptics {
+    fn vibrate(&mut self, pulse: Pulse) {
+        if let Err(e) = self.try_vibrate(pulse) {
+            println!("Failed to vibrate: {}", e);
+            if let Ok(env) = self.vm.get_env() {
+                let _ = env.exception_clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn impact(impulse: f32) -> game::Event {
+        game::Event {
+            time: 0.0,
+            kind: game::EventKind::WallImpact {
+                pos: game::Point { x: 0.0, y: 0.0 },
+                impulse,
+            },
+        }
+    }
+
+    fn edge_hit(impulse: f32) -> game::Event {
+        game::Event {
+            time: 0.0,
+            kind: game::EventKind::EdgeHit {
+                pos: game::Point { x: 0.0, y: 0.0 },
+                impulse,
+            },
+        }
+    }
+
+    fn pulses(events: &[game::Event]) -> Vec<Pulse> {
+        let mut haptics = LoggingHaptics::new();
+        handle_events(&mut haptics, events);
+        haptics.pulses
+    }
+
+    #[test]
+    fn no_pulse_without_impacts() {
+        assert!(pulses(&[]).is_empty());
+        assert!(pulses(&[game::Event {
+            time: 0.0,
+            kind: game::EventKind::Goal,
+        }])
+        .is_empty());
+    }
+
+    #[test]
+    fn strongest_impact_gives_the_pulse() {
+        let strongest = pulses(&[
+            impact(0.25 * FULL_IMPULSE),
+            edge_hit(0.5 * FULL_IMPULSE),
+            impact(0.1 * FULL_IMPULSE),
+        ]);
+        assert_eq!(strongest, pulses(&[edge_hit(0.5 * FULL_IMPULSE)]));
+        assert_eq!(strongest.len(), 1);
+        assert!(
+            (strongest[0].amplitude - (MIN_AMPLITUDE + 0.5 * (1.0 - MIN_AMPLITUDE))).abs() < 1e-6
+        );
+    }
+
+    #[test]
+    fn pulse_is_clamped_at_full_impulse() {
+        let full = pulses(&[impact(FULL_IMPULSE)]);
+        assert_eq!(full, pulses(&[impact(10.0 * FULL_IMPULSE)]));
+        assert_eq!(full[0].amplitude, 1.0);
+        assert!((1000.0 * full[0].duration.as_secs_f32() - MAX_PULSE_MS).abs() < 0.01);
+    }
+
+    #[test]
+    fn non_finite_impulses_are_ignored() {
+        assert!(pulses(&[impact(f32::NAN), impact(f32::INFINITY)]).is_empty());
+        assert_eq!(
+            pulses(&[impact(f32::NAN), impact(FULL_IMPULSE)]),
+            pulses(&[impact(FULL_IMPULSE)])
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use instant::Instant;
+use nalgebra_glm as glm;
+use std::fmt;
+use std::time::Duration;
+
+use crate::ai::{self, GameAi};
+use crate::game;
+use crate::level_generator;
+
+// Level difficulty analysis. The geometry of the AI path is measured against the obstacles and
+// the holes, and the level is played many times by the path tracer AI with noisy board control.
+// The suggested difficulty rating is based mainly on how often the noisy AI succeeds.
+
+const SAMPLE_STEP: f32 = 0.5 * game::BALL_R; // distance between the corridor width samples
+const TIME_STEP: f32 = 1.0 / 60.0; // simulation step of the AI runs, one frame at 60 FPS
+const TIGHTEST_TURNS: usize = 3; // number of turns listed in the report
+
+/// Analyzer parameters
+pub struct Config {
+    pub hole_distance: f32, // holes closer than this to the route are counted, in units of BALL_R
+    pub runs: u32,          // number of AI runs
+    pub noise: f32,         // AI board control noise relative to MAX_ANGLE
+    pub time_limit: f32,    // seconds after which an AI run is considered failed
+    pub seed: u64,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            hole_distance: 2.0,
+            runs: 50,
+            noise: 0.4,
+            time_limit: 300.0,
+            seed: 0,
+        }
+    }
+}
+
+/// A turn of the AI path at a path point. The angle is the change of direction in radians,
+/// 0 for going straight on and PI for a U-turn.
+#[derive(Copy, Clone, Debug)]
+pub struct Turn {
+    pub point: usize, // index of the path point
+    pub angle: f32,
+}
+
+/// Difficulty metrics of a level. Lengths are in level units.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub route_length: f32,
+    pub min_corridor_width: f32, // narrowest free width across the route
+    pub min_corridor_at: game::Point,
+    pub holes_near_route: usize, // holes and trapdoors closer than Config::hole_distance
+    pub tightest_turns: Vec<Turn>, // sharpest first
+    pub runs: u32,
+    pub success_rate: f32, // share of the AI runs that reached the goal without falling
+    pub mean_time: Option<f32>, // mean time of the successful runs
+    pub difficulty: game::Difficulty,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let radii = |v: f32| v / game::BALL_R;
+        writeln!(
+            f,
+            "Route length:        {:.0} ({:.0} ball radii)",
+            self.route_length,
+            radii(self.route_length)
+        )?;
+        writeln!(
+            f,
+            "Narrowest corridor:  {:.0} ({:.1} ball radii) at ({:.0}, {:.0})",
+            self.min_corridor_width,
+            radii(self.min_corridor_width),
+            self.min_corridor_at.x,
+            self.min_corridor_at.y
+        )?;
+        writeln!(f, "Holes near route:    {}", self.holes_near_route)?;
+        write!(f, "Tightest turns:     ")?;
+        for turn in self.tightest_turns.iter() {
+            write!(
+                f,
+                " {:.0}° at path point {}",
+                turn.angle.to_degrees(),
+                turn.point
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "AI success rate:     {:.0} % of {} runs",
+            100.0 * self.success_rate,
+            self.runs
+        )?;
+        if let Some(t) = self.mean_time {
+            writeln!(f, "Mean AI time:        {:.1} s", t)?;
+        }
+        write!(f, "Suggested difficulty: {:?}", self.difficulty)
+    }
+}
+
+/// Analyze level. The level must have an AI path.
+pub fn analyze(level: &game::Level, cfg: &Config) -> Report {
+    assert!(
+        level.path.len() >= 2,
+        "The level needs an AI path for the analysis"
+    );
+    let route_length = level
+        .path
+        .windows(2)
+        .map(|s| glm::distance(&glm::Vec2::from(&s[0]), &glm::Vec2::from(&s[1])))
+        .sum();
+    let (min_corridor_width, min_corridor_at) = narrowest_corridor(level);
+    let max_distance = game::HOLE_R + cfg.hole_distance * game::BALL_R;
+    let holes_near_route = level
+        .holes
+        .iter()
+        .chain(level.trapdoors.iter().map(|t| &t.pos))
+        .filter(|h| level_generator::distance_to_polyline(h, &level.path) < max_distance)
+        .count();
+    let mut tightest_turns = turns(&level.path);
+    tightest_turns.sort_by(|a, b| b.angle.partial_cmp(&a.angle).unwrap());
+    tightest_turns.truncate(TIGHTEST_TURNS);
+
+    let times: Vec<f32> = (0..cfg.runs)
+        .filter_map(|i| run_ai(level, cfg, cfg.seed.wrapping_add(i as u64)))
+        .collect();
+    let success_rate = if cfg.runs > 0 {
+        times.len() as f32 / cfg.runs as f32
+    } else {
+        0.0
+    };
+    let mean_time = if times.is_empty() {
+        None
+    } else {
+        Some(times.iter().sum::<f32>() / times.len() as f32)
+    };
+
+    Report {
+        route_length,
+        min_corridor_width,
+        min_corridor_at,
+        holes_near_route,
+        tightest_turns,
+        runs: cfg.runs,
+        success_rate,
+        mean_time,
+        difficulty: rate(success_rate, min_corridor_width),
+    }
+}
+
+// The failure rate of the noisy AI gives the rating. Corridors barely wider than the ball make
+// a level harder for a human than for the AI, so they raise the rating by one step.
+fn rate(success_rate: f32, min_corridor_width: f32) -> game::Difficulty {
+    const NARROW_CORRIDOR: f32 = 3.0 * game::BALL_R;
+    let ratings = [
+        game::Difficulty::Easy,
+        game::Difficulty::Medium,
+        game::Difficulty::Hard,
+        game::Difficulty::Expert,
+    ];
+    let mut i = match success_rate {
+        r if r >= 0.9 => 0,
+        r if r >= 0.6 => 1,
+        r if r >= 0.3 => 2,
+        _ => 3,
+    };
+    if min_corridor_width < NARROW_CORRIDOR {
+        i += 1;
+    }
+    ratings[i.min(ratings.len() - 1)]
+}
+
+// The narrowest free width across the route, and where it is. The width is measured
+// perpendicular to the route at sample points by marching to the closest obstacle on both sides.
+fn narrowest_corridor(level: &game::Level) -> (f32, game::Point) {
+    let max_width = level.size.w + level.size.h;
+    // Sphere tracing: the distance to the closest obstacle is always a safe step
+    let free_distance = |p: glm::Vec2, dir: glm::Vec2| {
+        let mut d = 0.0;
+        loop {
+            let step = level.obstacle_distance(p + d * dir);
+            if step < 0.5 || d > max_width {
+                return d;
+            }
+            d += step;
+        }
+    };
+    let mut narrowest = (f32::MAX, level.path[0]);
+    for segment in level.path.windows(2) {
+        let (a, b) = (glm::Vec2::from(&segment[0]), glm::Vec2::from(&segment[1]));
+        let len = glm::distance(&a, &b);
+        if len == 0.0 {
+            continue;
+        }
+        let along = (b - a) / len;
+        let across = glm::vec2(-along.y, along.x);
+        let samples = (len / SAMPLE_STEP).ceil() as usize;
+        for i in 0..=samples {
+            let p = a + (i as f32 / samples as f32) * (b - a);
+            let width = free_distance(p, across) + free_distance(p, -across);
+            if width < narrowest.0 {
+                narrowest = (width, game::Point::from(&p));
+            }
+        }
+    }
+    narrowest
+}
+
+// The turns at the path points between the first and the last
+fn turns(path: &[game::Point]) -> Vec<Turn> {
+    path.windows(3)
+        .enumerate()
+        .filter_map(|(i, p)| {
+            let v0 = glm::Vec2::from(&p[1]) - glm::Vec2::from(&p[0]);
+            let v1 = glm::Vec2::from(&p[2]) - glm::Vec2::from(&p[1]);
+            if glm::length(&v0) == 0.0 || glm::length(&v1) == 0.0 {
+                return None;
+            }
+            let cos = glm::dot(&glm::normalize(&v0), &glm::normalize(&v1));
+            Some(Turn {
+                point: i + 1,
+                angle: glm::clamp_scalar(cos, -1.0, 1.0).acos(),
+            })
+        })
+        .collect()
+}
+
+// Play the level once with the noisy path tracer AI and a single ball. Returns the completion
+// time if the AI reaches the goal.
+fn run_ai(level: &game::Level, cfg: &Config, seed: u64) -> Option<f32> {
+    let mut game_cfg = game::Config::new();
+    game_cfg.lives = 1;
+    let mut game = game::Game::new(level, game_cfg);
+    let mut ai = ai::NoisyAi::new(ai::PathTracerAi::quiet(), cfg.noise, seed);
+    ai.init(level);
+    let t0 = Instant::now();
+    game.update(t0);
+    let steps = (cfg.time_limit / TIME_STEP) as u32;
+    for i in 1..=steps {
+        let t = t0 + Duration::from_secs_f32(i as f32 * TIME_STEP);
+        let rotation = ai.next_move(&game, t);
+        game.rotate_x(rotation.x);
+        game.rotate_y(rotation.y);
+        game.update(t);
+        for event in game.drain_events() {
+            ai.handle_event(level, &event);
+        }
+        match game.state {
+            game::State::Won => return Some(game.time),
+            game::State::Lost { .. } => return None,
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn point(x: f32, y: f32) -> game::Point {
+        game::Point { x, y }
+    }
+
+    // A 400x200 board with a post above the middle of a straight route across it
+    fn corridor_level() -> game::Level {
+        game::Level::from_json(
+            r#"{
+                "name": "Corridor",
+                "size": { "w": 400, "h": 200 },
+                "start": { "x": 50, "y": 100 },
+                "end": { "pos": { "x": 340, "y": 80 }, "size": { "w": 40, "h": 40 } },
+                "walls": [],
+                "posts": [{ "pos": { "x": 200, "y": 60 }, "r": 20 }],
+                "path": [{ "x": 50, "y": 100 }, { "x": 350, "y": 100 }]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn narrowest_corridor_is_next_to_the_post() {
+        let (width, at) = narrowest_corridor(&corridor_level());
+        // 20 to the post and 100 to the board edge
+        assert!((width - 120.0).abs() < 1.0, "{}", width);
+        assert_eq!(at, point(200.0, 100.0));
+    }
+
+    #[test]
+    fn turns_are_measured_at_inner_path_points() {
+        let path = [
+            point(0.0, 0.0),
+            point(100.0, 0.0),
+            point(100.0, 100.0),
+            point(100.0, 100.0), // repeated points are not turns
+            point(100.0, 200.0),
+            point(0.0, 100.0),
+        ];
+        let turns = turns(&path);
+        assert_eq!(turns.len(), 2);
+        assert_eq!(turns[0].point, 1);
+        assert!((turns[0].angle - PI / 2.0).abs() < 1e-5);
+        assert_eq!(turns[1].point, 4);
+        assert!((turns[1].angle - 3.0 * PI / 4.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn rating_follows_success_rate_and_narrow_corridors() {
+        let wide = 10.0 * game::BALL_R;
+        let narrow = 2.0 * game::BALL_R;
+        assert_eq!(rate(1.0, wide), game::Difficulty::Easy);
+        assert_eq!(rate(0.7, wide), game::Difficulty::Medium);
+        assert_eq!(rate(0.3, wide), game::Difficulty::Hard);
+        assert_eq!(rate(0.0, wide), game::Difficulty::Expert);
+        assert_eq!(rate(0.95, narrow), game::Difficulty::Medium);
+        assert_eq!(rate(0.0, narrow), game::Difficulty::Expert);
+    }
+}
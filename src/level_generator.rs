@@ -0,0 +1,500 @@
+use nalgebra_glm as glm;
+use std::collections::BTreeMap;
+
+use crate::game;
+
+// Procedural level generation. A perfect maze is carved into a grid of cells, the route is the
+// unique path between two of its cells, and the holes are placed in the corners of the route
+// cells so that the route always stays clear of them. The same seed and config always generate
+// the same level.
+
+const WALL_W: f32 = 16.0;
+const GOAL_SIZE: f32 = 2.5 * game::BALL_R;
+const HOLE_CLEARANCE: f32 = game::HOLE_R + 0.5 * game::BALL_R; // minimum distance from the route to a hole center
+const HOLE_INSET: f32 = game::HOLE_R + 2.0; // distance from the cell sides to a hole center
+
+/// Generator parameters. Holes fit beside the route only in corridors at least about 5 BALL_R
+/// wide, so narrower corridors give levels without holes.
+pub struct Config {
+    pub size: game::Size,
+    pub corridor_width: f32, // in units of BALL_R
+    pub hole_density: f32,   // probability of a hole in each cell along the route, 0...1
+    pub route_length: f32,   // length of the route relative to the longest route in the maze, 0...1
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            size: game::Size { w: 924.0, h: 769.0 },
+            corridor_width: 6.0,
+            hole_density: 0.4,
+            route_length: 0.8,
+        }
+    }
+}
+
+/// Generate a level from seed. The level includes the AI path along the route. Fails if the
+/// corridors are too narrow for the ball or the board is too small for a maze.
+pub fn generate(seed: u64, cfg: &Config) -> Result<game::Level, String> {
+    if cfg.corridor_width < 2.5 {
+        return Err(String::from("Corridors must be wider than the ball"));
+    }
+    let corridor = cfg.corridor_width * game::BALL_R;
+    let grid = Grid::new(cfg);
+    if cfg.size.w < corridor || cfg.size.h < corridor || grid.cols * grid.rows < 2 {
+        return Err(String::from(
+            "The board is too small for a maze with the given corridor width",
+        ));
+    }
+    let mut rng = Rng::new(seed);
+    let maze = Maze::carve(grid.cols, grid.rows, &mut rng);
+
+    // The route is a part of the longest route in the maze, found by searching the cell
+    // farthest from a random cell, and then the cell farthest from that
+    let farthest = |from: usize| {
+        let dist = maze.distances(from);
+        let max = *dist.iter().max().unwrap();
+        (dist.iter().position(|d| *d == max).unwrap(), max)
+    };
+    let (start_cell, _) = farthest(rng.below(maze.cells()));
+    let (far_cell, max_dist) = farthest(start_cell);
+    let longest = maze.route(start_cell, far_cell);
+    let len = ((cfg.route_length.max(0.0).min(1.0) * max_dist as f32).round() as usize)
+        .max(1)
+        .min(max_dist);
+    let route = &longest[..=len];
+
+    let path: Vec<game::Point> = route.iter().map(|c| grid.center(*c)).collect();
+    let holes = route
+        .iter()
+        .enumerate()
+        // No holes right at the start or at the goal
+        .filter(|(i, _)| *i > 0 && *i < route.len() - 1)
+        .filter_map(|(i, c)| {
+            if !rng.chance(cfg.hole_density) {
+                return None;
+            }
+            let route_points = [
+                grid.center(route[i - 1]),
+                grid.center(*c),
+                grid.center(route[i + 1]),
+            ];
+            let candidates: Vec<game::Point> = grid
+                .corners(*c)
+                .iter()
+                .copied()
+                .filter(|h| distance_to_polyline(h, &route_points) >= HOLE_CLEARANCE)
+                .collect();
+            if candidates.is_empty() {
+                None
+            } else {
+                Some(candidates[rng.below(candidates.len())])
+            }
+        })
+        .collect();
+
+    let goal = path.last().unwrap();
+    let goal_size = GOAL_SIZE.min(grid.cell_w).min(grid.cell_h);
+    Ok(game::Level {
+        name: format!("Generated level {}", seed),
+        meta: game::Metadata {
+            description: Some(format!(
+                "Generated with size {}x{}, corridor width {}, hole density {} and route length {}",
+                cfg.size.w, cfg.size.h, cfg.corridor_width, cfg.hole_density, cfg.route_length
+            )),
+            ..game::Metadata::default()
+        },
+        translations: BTreeMap::new(),
+        assets: game::Assets::default(),
+        size: cfg.size,
+        start: path[0],
+        end: game::Rect {
+            pos: game::Point {
+                x: goal.x - goal_size / 2.0,
+                y: goal.y - goal_size / 2.0,
+            },
+            size: game::Size {
+                w: goal_size,
+                h: goal_size,
+            },
+        },
+        walls: grid.walls(&maze),
+        posts: Vec::new(),
+        segments: Vec::new(),
+        arcs: Vec::new(),
+        moving_walls: Vec::new(),
+        rotating_bars: Vec::new(),
+        holes,
+        trapdoors: Vec::new(),
+        checkpoints: Vec::new(),
+        zones: Vec::new(),
+        collectibles: Vec::new(),
+        par_time: None,
+        path,
+    })
+}
+
+// The layout of the maze cells on the board. The corridors are widened to fill the board.
+struct Grid {
+    size: game::Size,
+    cols: usize,
+    rows: usize,
+    cell_w: f32,
+    cell_h: f32,
+}
+
+impl Grid {
+    fn new(cfg: &Config) -> Grid {
+        let pitch = cfg.corridor_width * game::BALL_R + WALL_W;
+        let cols = (((cfg.size.w + WALL_W) / pitch).floor() as usize).max(1);
+        let rows = (((cfg.size.h + WALL_W) / pitch).floor() as usize).max(1);
+        Grid {
+            size: cfg.size,
+            cols,
+            rows,
+            cell_w: (cfg.size.w - (cols - 1) as f32 * WALL_W) / cols as f32,
+            cell_h: (cfg.size.h - (rows - 1) as f32 * WALL_W) / rows as f32,
+        }
+    }
+
+    // The coordinates are rounded to whole units like in hand made levels, which also keeps
+    // them exact in the level JSON
+
+    fn x(&self, col: usize) -> f32 {
+        (col as f32 * (self.cell_w + WALL_W)).round()
+    }
+
+    fn y(&self, row: usize) -> f32 {
+        (row as f32 * (self.cell_h + WALL_W)).round()
+    }
+
+    // The right side of a column, exactly at the board edge for the last column
+    fn right(&self, col: usize) -> f32 {
+        if col + 1 < self.cols {
+            (col as f32 * (self.cell_w + WALL_W) + self.cell_w).round()
+        } else {
+            self.size.w
+        }
+    }
+
+    fn bottom(&self, row: usize) -> f32 {
+        if row + 1 < self.rows {
+            (row as f32 * (self.cell_h + WALL_W) + self.cell_h).round()
+        } else {
+            self.size.h
+        }
+    }
+
+    fn center(&self, cell: usize) -> game::Point {
+        let (col, row) = (cell % self.cols, cell / self.cols);
+        game::Point {
+            x: ((self.x(col) + self.right(col)) / 2.0).round(),
+            y: ((self.y(row) + self.bottom(row)) / 2.0).round(),
+        }
+    }
+
+    // The hole positions in the corners of a cell
+    fn corners(&self, cell: usize) -> [game::Point; 4] {
+        let c = self.center(cell);
+        let dx = self.cell_w / 2.0 - HOLE_INSET;
+        let dy = self.cell_h / 2.0 - HOLE_INSET;
+        let corner = |sx: f32, sy: f32| game::Point {
+            x: (c.x + sx * dx).round(),
+            y: (c.y + sy * dy).round(),
+        };
+        [
+            corner(-1.0, -1.0),
+            corner(1.0, -1.0),
+            corner(-1.0, 1.0),
+            corner(1.0, 1.0),
+        ]
+    }
+
+    // The walls between the cells that have no passage between them. Walls along the same line
+    // are merged, and the horizontal walls also fill the wall junctions at their ends.
+    fn walls(&self, maze: &Maze) -> Vec<game::Wall> {
+        let mut walls = Vec::new();
+        for row in 0..self.rows - 1 {
+            for_each_run(
+                self.cols,
+                |col| !maze.open_south[row * self.cols + col],
+                |first, last| {
+                    let x0 = if first > 0 {
+                        self.x(first) - WALL_W
+                    } else {
+                        0.0
+                    };
+                    let x1 = if last + 1 < self.cols {
+                        self.right(last) + WALL_W
+                    } else {
+                        self.size.w
+                    };
+                    walls.push(game::Wall::new(game::Rect {
+                        pos: game::Point {
+                            x: x0,
+                            y: self.bottom(row),
+                        },
+                        size: game::Size {
+                            w: x1 - x0,
+                            h: WALL_W,
+                        },
+                    }));
+                },
+            );
+        }
+        for col in 0..self.cols - 1 {
+            for_each_run(
+                self.rows,
+                |row| !maze.open_east[row * self.cols + col],
+                |first, last| {
+                    walls.push(game::Wall::new(game::Rect {
+                        pos: game::Point {
+                            x: self.right(col),
+                            y: self.y(first),
+                        },
+                        size: game::Size {
+                            w: WALL_W,
+                            h: self.bottom(last) - self.y(first),
+                        },
+                    }));
+                },
+            );
+        }
+        walls
+    }
+}
+
+// A perfect maze, i.e. one with exactly one route between any two cells. Cells are indexed row
+// by row.
+struct Maze {
+    cols: usize,
+    rows: usize,
+    open_east: Vec<bool>,  // passage from the cell to the cell on its right
+    open_south: Vec<bool>, // passage from the cell to the cell below it
+}
+
+impl Maze {
+    // Carve the passages with a randomized depth-first search
+    fn carve(cols: usize, rows: usize, rng: &mut Rng) -> Maze {
+        let mut maze = Maze {
+            cols,
+            rows,
+            open_east: vec![false; cols * rows],
+            open_south: vec![false; cols * rows],
+        };
+        let mut visited = vec![false; cols * rows];
+        let first = rng.below(cols * rows);
+        let mut stack = vec![first];
+        visited[first] = true;
+        while let Some(&cell) = stack.last() {
+            let unvisited: Vec<usize> = maze
+                .neighbors(cell)
+                .into_iter()
+                .filter(|n| !visited[*n])
+                .collect();
+            if unvisited.is_empty() {
+                stack.pop();
+                continue;
+            }
+            let next = unvisited[rng.below(unvisited.len())];
+            maze.set_open(cell, next);
+            visited[next] = true;
+            stack.push(next);
+        }
+        maze
+    }
+
+    fn cells(&self) -> usize {
+        self.cols * self.rows
+    }
+
+    fn neighbors(&self, cell: usize) -> Vec<usize> {
+        let (col, row) = (cell % self.cols, cell / self.cols);
+        let mut n = Vec::with_capacity(4);
+        if col > 0 {
+            n.push(cell - 1);
+        }
+        if col + 1 < self.cols {
+            n.push(cell + 1);
+        }
+        if row > 0 {
+            n.push(cell - self.cols);
+        }
+        if row + 1 < self.rows {
+            n.push(cell + self.cols);
+        }
+        n
+    }
+
+    fn set_open(&mut self, a: usize, b: usize) {
+        let (a, b) = (a.min(b), a.max(b));
+        if b == a + 1 {
+            self.open_east[a] = true;
+        } else {
+            self.open_south[a] = true;
+        }
+    }
+
+    fn is_open(&self, a: usize, b: usize) -> bool {
+        let (a, b) = (a.min(b), a.max(b));
+        if b == a + 1 {
+            self.open_east[a]
+        } else {
+            self.open_south[a]
+        }
+    }
+
+    // The passage neighbors of a cell
+    fn passages(&self, cell: usize) -> Vec<usize> {
+        self.neighbors(cell)
+            .into_iter()
+            .filter(|n| self.is_open(cell, *n))
+            .collect()
+    }
+
+    // Route lengths in cells from one cell to all the cells
+    fn distances(&self, from: usize) -> Vec<usize> {
+        let mut dist = vec![usize::MAX; self.cells()];
+        let mut queue = std::collections::VecDeque::new();
+        dist[from] = 0;
+        queue.push_back(from);
+        while let Some(cell) = queue.pop_front() {
+            for n in self.passages(cell) {
+                if dist[n] == usize::MAX {
+                    dist[n] = dist[cell] + 1;
+                    queue.push_back(n);
+                }
+            }
+        }
+        dist
+    }
+
+    // The cells along the route from one cell to another, both included
+    fn route(&self, from: usize, to: usize) -> Vec<usize> {
+        // Walk back from the destination along decreasing distances
+        let dist = self.distances(from);
+        let mut route = vec![to];
+        let mut cell = to;
+        while cell != from {
+            cell = self
+                .passages(cell)
+                .into_iter()
+                .find(|n| dist[*n] + 1 == dist[cell])
+                .unwrap();
+            route.push(cell);
+        }
+        route.reverse();
+        route
+    }
+}
+
+// Call add(first, last) for each run of consecutive indices in 0...count - 1 that are blocked
+fn for_each_run(count: usize, blocked: impl Fn(usize) -> bool, mut add: impl FnMut(usize, usize)) {
+    let mut i = 0;
+    while i < count {
+        if blocked(i) {
+            let first = i;
+            while i + 1 < count && blocked(i + 1) {
+                i += 1;
+            }
+            add(first, i);
+        }
+        i += 1;
+    }
+}
+
+// Shortest distance from p to the line through points
+pub(crate) fn distance_to_polyline(p: &game::Point, points: &[game::Point]) -> f32 {
+    let p = glm::Vec2::from(p);
+    points
+        .windows(2)
+        .map(|s| {
+            let (a, b) = (glm::Vec2::from(&s[0]), glm::Vec2::from(&s[1]));
+            let t = glm::clamp_scalar(
+                glm::dot(&(p - a), &(b - a)) / glm::length2(&(b - a)),
+                0.0,
+                1.0,
+            );
+            glm::distance(&p, &(a + t * (b - a)))
+        })
+        .fold(f32::MAX, f32::min)
+}
+
+// Small deterministic random number generator (xorshift64*), so that a seed generates the same
+// level on every platform
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        // Scramble the seed with splitmix64 so that similar seeds give unrelated sequences.
+        // xorshift doesn't work with zero state.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng((z ^ (z >> 31)).max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Random integer in 0...n - 1
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Random number in 0...1
+    pub(crate) fn uniform(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn chance(&mut self, p: f32) -> bool {
+        self.uniform() < p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_valid_and_deterministic() {
+        let cfg = Config::new();
+        for seed in 0..10 {
+            let level = generate(seed, &cfg).unwrap();
+            assert_eq!(generate(seed, &cfg).unwrap(), level);
+            assert_eq!(game::Level::from_json(&level.to_json()).unwrap(), level);
+            assert!(level.path.len() >= 2);
+            for hole in level.holes.iter() {
+                assert!(
+                    distance_to_polyline(hole, &level.path) >= HOLE_CLEARANCE,
+                    "Hole {:?} too close to the route of level {}",
+                    hole,
+                    seed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_levels() {
+        let cfg = Config::new();
+        assert_ne!(generate(1, &cfg).unwrap(), generate(2, &cfg).unwrap());
+    }
+
+    #[test]
+    fn invalid_configs_are_errors() {
+        let mut narrow = Config::new();
+        narrow.corridor_width = 2.0;
+        assert!(generate(0, &narrow).is_err());
+        let mut small = Config::new();
+        small.size = game::Size { w: 10.0, h: 10.0 };
+        assert!(generate(0, &small).is_err());
+        let mut flat = Config::new();
+        flat.size = game::Size { w: 924.0, h: 10.0 };
+        assert!(generate(0, &flat).is_err());
+    }
+}
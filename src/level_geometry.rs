@@ -0,0 +1,537 @@
+use nalgebra_glm as glm;
+use std::f32::consts::PI;
+
+use crate::game;
+use crate::mesh::Mesh;
+
+// All the meshes are built in scene coordinates relative to the board center: x-axis is the
+// level x-axis, z-axis the level y-axis and y-axis points up from the board surface.
+
+const HOLE_SEGMENTS: usize = 32; // Must be a multiple of 8, see add_hole_tile()
+const CIRCLE_SEGMENTS: usize = 32; // Segments in a full circle for posts and curved walls
+
+/// Build a single mesh containing all the walls of the level, including the walls on the board
+/// edges, posts, straight segments and arcs. Bumpers are left out, see `bumpers_mesh`. Texture
+/// coordinates are in world units divided by `texture_size`, so a tiling texture keeps the same
+/// scale on all wall faces regardless of the wall dimensions.
+pub fn walls_mesh(level: &game::Level, wall_h: f32, edge_wall_w: f32, texture_size: f32) -> Mesh {
+    let mut mesh = frame_mesh(level.size, wall_h, edge_wall_w, texture_size);
+    add_static_obstacles(&mut mesh, level, false, wall_h, texture_size);
+    mesh
+}
+
+/// Build a single mesh containing the static obstacles of the level that are bumpers, so that
+/// they can be drawn with a different material than the ordinary walls.
+pub fn bumpers_mesh(level: &game::Level, wall_h: f32, texture_size: f32) -> Mesh {
+    let mut mesh = Mesh::default();
+    add_static_obstacles(&mut mesh, level, true, wall_h, texture_size);
+    mesh
+}
+
+/// Build the mesh for the surface zones of the given type, lifted `lift` units above the board
+/// surface. Texture coordinates are in world units divided by `texture_size`.
+pub fn zones_mesh(
+    level: &game::Level,
+    surface: game::Surface,
+    lift: f32,
+    texture_size: f32,
+) -> Mesh {
+    let mut mesh = Mesh::default();
+    for zone in level.zones.iter().filter(|z| z.surface == surface) {
+        let min = board_to_scene(level, glm::Vec2::from(&zone.rect.pos));
+        let max = min + glm::vec2(zone.rect.size.w, zone.rect.size.h);
+        add_top_triangle(
+            &mut mesh,
+            [min, glm::vec2(min.x, max.y), max],
+            lift,
+            texture_size,
+        );
+        add_top_triangle(
+            &mut mesh,
+            [min, max, glm::vec2(max.x, min.y)],
+            lift,
+            texture_size,
+        );
+    }
+    mesh
+}
+
+// Add the static obstacles of the level that either are or aren't bumpers
+fn add_static_obstacles(
+    mesh: &mut Mesh,
+    level: &game::Level,
+    bumpers: bool,
+    wall_h: f32,
+    texture_size: f32,
+) {
+    let included = |restitution: f32| game::is_bumper(restitution) == bumpers;
+    for wall in level.walls.iter().filter(|w| included(w.restitution)) {
+        let x0 = wall.rect.pos.x - level.size.w / 2.0;
+        let z0 = wall.rect.pos.y - level.size.h / 2.0;
+        add_box(
+            mesh,
+            glm::vec2(x0, z0),
+            glm::vec2(x0 + wall.rect.size.w, z0 + wall.rect.size.h),
+            wall_h,
+            texture_size,
+        );
+    }
+    for post in level.posts.iter().filter(|p| included(p.restitution)) {
+        let center = board_to_scene(level, glm::Vec2::from(&post.pos));
+        add_post(mesh, center, post.r, wall_h, texture_size);
+    }
+    for segment in level.segments.iter().filter(|s| included(s.restitution)) {
+        let a = board_to_scene(level, glm::Vec2::from(&segment.a));
+        let b = board_to_scene(level, glm::Vec2::from(&segment.b));
+        let along = glm::normalize(&(b - a));
+        let across = segment.w / 2.0 * glm::vec2(-along.y, along.x);
+        add_prism(
+            mesh,
+            &[a - across, a + across, b + across, b - across],
+            wall_h,
+            texture_size,
+        );
+    }
+    for arc in level.arcs.iter().filter(|a| included(a.restitution)) {
+        add_arc(mesh, level, arc, wall_h, texture_size);
+    }
+}
+
+/// Build a mesh for four walls of width `wall_w` surrounding an area of the given size.
+pub fn frame_mesh(size: game::Size, wall_h: f32, wall_w: f32, texture_size: f32) -> Mesh {
+    let (w, h) = (size.w / 2.0, size.h / 2.0);
+    let mut mesh = Mesh::default();
+    for (min, max) in [
+        (glm::vec2(-w - wall_w, -h), glm::vec2(-w, h)), // left
+        (glm::vec2(w, -h), glm::vec2(w + wall_w, h)),   // right
+        (
+            glm::vec2(-w - wall_w, -h - wall_w),
+            glm::vec2(w + wall_w, -h),
+        ), // top
+        (glm::vec2(-w - wall_w, h), glm::vec2(w + wall_w, h + wall_w)), // bottom
+    ]
+    .iter()
+    {
+        add_box(&mut mesh, *min, *max, wall_h, texture_size);
+    }
+    mesh
+}
+
+/// Build a box with the given footprint centered at the origin, standing on the xz-plane. Used
+/// for the walls that move, so that they can be animated with their node transformation.
+pub fn box_mesh(size: game::Size, height: f32, texture_size: f32) -> Mesh {
+    let mut mesh = Mesh::default();
+    let half = glm::vec2(size.w / 2.0, size.h / 2.0);
+    add_box(&mut mesh, -half, half, height, texture_size);
+    mesh
+}
+
+/// Build the board surface mesh with round openings for the level holes and trapdoors. Texture
+/// coordinates map the whole board surface to the whole texture.
+pub fn board_surface_mesh(level: &game::Level) -> Mesh {
+    let mut mesh = Mesh::default();
+    let holes = hole_positions(level);
+
+    // Each hole is surrounded by a square tile that is triangulated separately. The tiles must
+    // not overlap each other or extend outside the board.
+    let tiles: Vec<(glm::Vec2, f32)> = holes
+        .iter()
+        .enumerate()
+        .map(|(i, hole)| {
+            let edge_distance = hole
+                .x
+                .min(hole.y)
+                .min(level.size.w - hole.x)
+                .min(level.size.h - hole.y);
+            let neighbor_distance = holes
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, h)| (h.x - hole.x).abs().max((h.y - hole.y).abs()) / 2.0)
+                .fold(f32::MAX, f32::min);
+            let half_size = (1.5 * game::HOLE_R)
+                .min(edge_distance)
+                .min(neighbor_distance)
+                .max(1.01 * game::HOLE_R);
+            (glm::Vec2::from(hole), half_size)
+        })
+        .collect();
+    for (center, half_size) in tiles.iter() {
+        add_hole_tile(&mut mesh, level, *center, *half_size);
+    }
+
+    // Cover the rest of the board with rectangles on a grid formed by the tile edges.
+    // Consecutive grid cells on a row are merged to a single rectangle.
+    let grid_lines = |size: f32, coord: fn(&glm::Vec2) -> f32| {
+        let mut lines = vec![0.0, size];
+        for (center, half_size) in tiles.iter() {
+            lines.push(coord(center) - half_size);
+            lines.push(coord(center) + half_size);
+        }
+        lines.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        lines.dedup();
+        lines.retain(|&l| l >= 0.0 && l <= size);
+        lines
+    };
+    let xs = grid_lines(level.size.w, |v| v.x);
+    let ys = grid_lines(level.size.h, |v| v.y);
+    let in_tile = |p: glm::Vec2| {
+        tiles.iter().any(|(center, half_size)| {
+            (p.x - center.x).abs() < *half_size && (p.y - center.y).abs() < *half_size
+        })
+    };
+    for row in ys.windows(2) {
+        let mut run_start: Option<f32> = None;
+        for col in xs.windows(2) {
+            let covered = in_tile(glm::vec2((col[0] + col[1]) / 2.0, (row[0] + row[1]) / 2.0));
+            match (covered, run_start) {
+                (false, None) => run_start = Some(col[0]),
+                (true, Some(x0)) => {
+                    add_board_rect(&mut mesh, level, x0, row[0], col[0], row[1]);
+                    run_start = None;
+                }
+                _ => (),
+            }
+        }
+        if let Some(x0) = run_start {
+            add_board_rect(&mut mesh, level, x0, row[0], level.size.w, row[1]);
+        }
+    }
+
+    mesh
+}
+
+/// Build a mesh for the insides of the level holes and trapdoors: cylindrical walls of the given
+/// depth facing inwards, and a bottom for each hole.
+pub fn holes_mesh(level: &game::Level, depth: f32) -> Mesh {
+    let mut mesh = Mesh::default();
+    for hole in hole_positions(level).iter() {
+        let center = board_to_scene(level, glm::Vec2::from(hole));
+        let first_vertex = mesh.positions.len() as u32;
+        for i in 0..=HOLE_SEGMENTS {
+            let angle = 2.0 * PI * i as f32 / HOLE_SEGMENTS as f32;
+            let dir = glm::vec2(angle.cos(), angle.sin());
+            let p = center + game::HOLE_R * dir;
+            let u = game::HOLE_R * angle / depth;
+            for (y, v) in [(0.0, 1.0), (-depth, 0.0)].iter() {
+                mesh.positions.push([p.x, *y, p.y]);
+                mesh.normals.push([-dir.x, 0.0, -dir.y]);
+                mesh.tex_coords.push([u, *v]);
+            }
+        }
+        for i in 0..HOLE_SEGMENTS as u32 {
+            let top0 = first_vertex + 2 * i;
+            let (bottom0, top1, bottom1) = (top0 + 1, top0 + 2, top0 + 3);
+            // The walls are seen from the inside of the cylinder
+            mesh.indices
+                .extend_from_slice(&[top0, bottom0, top1, bottom0, bottom1, top1]);
+        }
+
+        let center_vertex = mesh.positions.len() as u32;
+        mesh.positions.push([center.x, -depth, center.y]);
+        mesh.normals.push([0.0, 1.0, 0.0]);
+        mesh.tex_coords.push([0.5, 0.5]);
+        for i in 0..HOLE_SEGMENTS {
+            let angle = 2.0 * PI * i as f32 / HOLE_SEGMENTS as f32;
+            let dir = glm::vec2(angle.cos(), angle.sin());
+            let p = center + game::HOLE_R * dir;
+            mesh.positions.push([p.x, -depth, p.y]);
+            mesh.normals.push([0.0, 1.0, 0.0]);
+            mesh.tex_coords.push([0.5 + 0.5 * dir.x, 0.5 + 0.5 * dir.y]);
+        }
+        for i in 0..HOLE_SEGMENTS as u32 {
+            let next = (i + 1) % HOLE_SEGMENTS as u32;
+            mesh.indices.extend_from_slice(&[
+                center_vertex,
+                center_vertex + 1 + next,
+                center_vertex + 1 + i,
+            ]);
+        }
+    }
+    mesh
+}
+
+/// Build a lid covering the opening of a trapdoor at the board surface level. Texture
+/// coordinates are the same as on the board surface, so that a closed trapdoor blends in.
+pub fn trapdoor_lid_mesh(level: &game::Level, trapdoor: &game::Trapdoor) -> Mesh {
+    let mut mesh = Mesh::default();
+    let center = glm::Vec2::from(&trapdoor.pos);
+    let center_vertex = add_board_vertex(&mut mesh, level, center);
+    for i in 0..HOLE_SEGMENTS {
+        let angle = 2.0 * PI * i as f32 / HOLE_SEGMENTS as f32;
+        add_board_vertex(
+            &mut mesh,
+            level,
+            center + game::HOLE_R * glm::vec2(angle.cos(), angle.sin()),
+        );
+    }
+    for i in 0..HOLE_SEGMENTS as u32 {
+        let next = (i + 1) % HOLE_SEGMENTS as u32;
+        mesh.indices.extend_from_slice(&[
+            center_vertex,
+            center_vertex + 1 + next,
+            center_vertex + 1 + i,
+        ]);
+    }
+    mesh
+}
+
+// Positions of all the openings on the board surface
+fn hole_positions(level: &game::Level) -> Vec<game::Point> {
+    level
+        .holes
+        .iter()
+        .copied()
+        .chain(level.trapdoors.iter().map(|d| d.pos))
+        .collect()
+}
+
+fn board_to_scene(level: &game::Level, p: glm::Vec2) -> glm::Vec2 {
+    p - glm::vec2(level.size.w / 2.0, level.size.h / 2.0)
+}
+
+// Add a vertex on the board surface at level coordinates p
+fn add_board_vertex(mesh: &mut Mesh, level: &game::Level, p: glm::Vec2) -> u32 {
+    let scene_p = board_to_scene(level, p);
+    mesh.positions.push([scene_p.x, 0.0, scene_p.y]);
+    mesh.normals.push([0.0, 1.0, 0.0]);
+    // Texture v-direction is opposite to the level y-direction
+    mesh.tex_coords
+        .push([p.x / level.size.w, 1.0 - p.y / level.size.h]);
+    mesh.positions.len() as u32 - 1
+}
+
+fn add_board_rect(mesh: &mut Mesh, level: &game::Level, x0: f32, y0: f32, x1: f32, y1: f32) {
+    let a = add_board_vertex(mesh, level, glm::vec2(x0, y0));
+    let b = add_board_vertex(mesh, level, glm::vec2(x1, y0));
+    let c = add_board_vertex(mesh, level, glm::vec2(x0, y1));
+    let d = add_board_vertex(mesh, level, glm::vec2(x1, y1));
+    mesh.indices.extend_from_slice(&[a, c, b, b, c, d]);
+}
+
+// Add a square board surface tile with a hole in the middle. The tile is triangulated by casting
+// rays from the hole center: each ray connects a point on the hole edge to a point on the tile
+// edge. With the number of rays divisible by 8, there is a ray to each corner of the tile, and
+// consecutive rays always hit the same side of the tile.
+fn add_hole_tile(mesh: &mut Mesh, level: &game::Level, center: glm::Vec2, half_size: f32) {
+    let first_vertex = mesh.positions.len() as u32;
+    for i in 0..HOLE_SEGMENTS {
+        let angle = 2.0 * PI * i as f32 / HOLE_SEGMENTS as f32;
+        let dir = glm::vec2(angle.cos(), angle.sin());
+        add_board_vertex(mesh, level, center + game::HOLE_R * dir);
+        add_board_vertex(
+            mesh,
+            level,
+            center + half_size * dir / dir.x.abs().max(dir.y.abs()),
+        );
+    }
+    for i in 0..HOLE_SEGMENTS as u32 {
+        let next = (i + 1) % HOLE_SEGMENTS as u32;
+        let (inner0, outer0) = (first_vertex + 2 * i, first_vertex + 2 * i + 1);
+        let (inner1, outer1) = (first_vertex + 2 * next, first_vertex + 2 * next + 1);
+        mesh.indices
+            .extend_from_slice(&[inner0, inner1, outer0, outer0, inner1, outer1]);
+    }
+}
+
+// Add a box standing on the board with the given footprint
+fn add_box(mesh: &mut Mesh, min: glm::Vec2, max: glm::Vec2, height: f32, texture_size: f32) {
+    add_prism(
+        mesh,
+        &[min, glm::vec2(min.x, max.y), max, glm::vec2(max.x, min.y)],
+        height,
+        texture_size,
+    );
+}
+
+// Add a prism standing on the board with a convex footprint given as its corners in either
+// winding order. The bottom face is left out as it's never visible.
+fn add_prism(mesh: &mut Mesh, outline: &[glm::Vec2], height: f32, texture_size: f32) {
+    let signed_area: f32 = (0..outline.len())
+        .map(|i| {
+            let (a, b) = (outline[i], outline[(i + 1) % outline.len()]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum();
+    let mut outline = outline.to_vec();
+    if signed_area > 0.0 {
+        outline.reverse();
+    }
+    for i in 1..outline.len() - 1 {
+        add_top_triangle(
+            mesh,
+            [outline[0], outline[i], outline[i + 1]],
+            height,
+            texture_size,
+        );
+    }
+    for i in 0..outline.len() {
+        add_side(
+            mesh,
+            outline[i],
+            outline[(i + 1) % outline.len()],
+            height,
+            texture_size,
+        );
+    }
+}
+
+// Add a round post standing on the board. The side normals are smooth.
+fn add_post(mesh: &mut Mesh, center: glm::Vec2, r: f32, height: f32, texture_size: f32) {
+    let dir = |i: usize| {
+        let angle = 2.0 * PI * i as f32 / CIRCLE_SEGMENTS as f32;
+        glm::vec2(angle.cos(), angle.sin())
+    };
+    let first_vertex = mesh.positions.len() as u32;
+    for i in 0..=CIRCLE_SEGMENTS {
+        let d = dir(i);
+        let p = center + r * d;
+        // u decreases with the angle to keep the texture unmirrored when seen from outside
+        let u = -r * 2.0 * PI * i as f32 / CIRCLE_SEGMENTS as f32 / texture_size;
+        for y in [height, 0.0].iter() {
+            mesh.positions.push([p.x, *y, p.y]);
+            mesh.normals.push([d.x, 0.0, d.y]);
+            mesh.tex_coords.push([u, y / texture_size]);
+        }
+    }
+    for i in 0..CIRCLE_SEGMENTS as u32 {
+        let top0 = first_vertex + 2 * i;
+        let (bottom0, top1, bottom1) = (top0 + 1, top0 + 2, top0 + 3);
+        mesh.indices
+            .extend_from_slice(&[top1, bottom1, top0, top0, bottom1, bottom0]);
+    }
+    for i in 0..CIRCLE_SEGMENTS {
+        add_top_triangle(
+            mesh,
+            [center, center + r * dir(i), center + r * dir(i + 1)],
+            height,
+            texture_size,
+        );
+    }
+}
+
+// Add a curved wall following an arc. The inner and outer sides have smooth normals and the
+// ends are flat.
+fn add_arc(mesh: &mut Mesh, level: &game::Level, arc: &game::Arc, height: f32, texture_size: f32) {
+    let center = board_to_scene(level, glm::Vec2::from(&arc.center));
+    let span = arc.end_angle - arc.start_angle;
+    let segments = ((span / (2.0 * PI) * CIRCLE_SEGMENTS as f32).ceil() as usize).max(1);
+    let angle = |i: usize| arc.start_angle + span * i as f32 / segments as f32;
+    let dir = |i: usize| glm::vec2(angle(i).cos(), angle(i).sin());
+    let (r_inner, r_outer) = (arc.r - arc.w / 2.0, arc.r + arc.w / 2.0);
+
+    // Outer side faces away from the center, inner side towards it
+    for &(r, normal_sign) in [(r_outer, 1.0), (r_inner, -1.0)].iter() {
+        let first_vertex = mesh.positions.len() as u32;
+        for i in 0..=segments {
+            let d = dir(i);
+            let p = center + r * d;
+            let u = -normal_sign * r * angle(i) / texture_size;
+            for y in [height, 0.0].iter() {
+                mesh.positions.push([p.x, *y, p.y]);
+                mesh.normals
+                    .push([normal_sign * d.x, 0.0, normal_sign * d.y]);
+                mesh.tex_coords.push([u, y / texture_size]);
+            }
+        }
+        for i in 0..segments as u32 {
+            let top0 = first_vertex + 2 * i;
+            let (bottom0, top1, bottom1) = (top0 + 1, top0 + 2, top0 + 3);
+            if normal_sign > 0.0 {
+                mesh.indices
+                    .extend_from_slice(&[top1, bottom1, top0, top0, bottom1, bottom0]);
+            } else {
+                mesh.indices
+                    .extend_from_slice(&[top0, bottom0, top1, top1, bottom0, bottom1]);
+            }
+        }
+    }
+
+    for i in 0..segments {
+        let (inner0, outer0) = (center + r_inner * dir(i), center + r_outer * dir(i));
+        let (inner1, outer1) = (center + r_inner * dir(i + 1), center + r_outer * dir(i + 1));
+        add_top_triangle(mesh, [inner0, outer0, inner1], height, texture_size);
+        add_top_triangle(mesh, [inner1, outer0, outer1], height, texture_size);
+    }
+    add_side(
+        mesh,
+        center + r_outer * dir(0),
+        center + r_inner * dir(0),
+        height,
+        texture_size,
+    );
+    add_side(
+        mesh,
+        center + r_inner * dir(segments),
+        center + r_outer * dir(segments),
+        height,
+        texture_size,
+    );
+}
+
+// Add a vertical wall side from a to b. a is the left edge when seen from the front side.
+fn add_side(mesh: &mut Mesh, a: glm::Vec2, b: glm::Vec2, height: f32, texture_size: f32) {
+    let d = glm::normalize(&(b - a));
+    add_quad(
+        mesh,
+        [
+            glm::vec3(a.x, height, a.y),
+            glm::vec3(b.x, height, b.y),
+            glm::vec3(a.x, 0.0, a.y),
+            glm::vec3(b.x, 0.0, b.y),
+        ],
+        glm::vec3(-d.y, 0.0, d.x),
+        texture_size,
+    );
+}
+
+// Add an upwards facing triangle at the given height. The corners can be in either winding
+// order. Texture coordinates are anchored to world coordinates like in add_quad().
+fn add_top_triangle(mesh: &mut Mesh, corners: [glm::Vec2; 3], height: f32, texture_size: f32) {
+    let (a, b, c) = (corners[0], corners[1], corners[2]);
+    // Facing up requires the y-component of (b - a) x (c - a) to be positive
+    let corners = if (b - a).y * (c - a).x - (b - a).x * (c - a).y > 0.0 {
+        [a, b, c]
+    } else {
+        [a, c, b]
+    };
+    for p in corners.iter() {
+        mesh.indices.push(mesh.positions.len() as u32);
+        mesh.positions.push([p.x, height, p.y]);
+        mesh.normals.push([0.0, 1.0, 0.0]);
+        mesh.tex_coords
+            .push([p.x / texture_size, -p.y / texture_size]);
+    }
+}
+
+// Add a planar quad given as top-left, top-right, bottom-left and bottom-right corners when seen
+// from the front side. Texture coordinates are the corner coordinates along the quad edges in
+// world units divided by texture_size.
+fn add_quad(mesh: &mut Mesh, corners: [glm::Vec3; 4], normal: glm::Vec3, texture_size: f32) {
+    let u_dir = corners[1] - corners[0];
+    let v_dir = corners[0] - corners[2];
+    let u_len = glm::length(&u_dir);
+    let v_len = glm::length(&v_dir);
+    // Anchor the texture to world coordinates so that adjacent faces continue seamlessly
+    let u0 = glm::dot(&corners[2], &(u_dir / u_len)) / texture_size;
+    let v0 = glm::dot(&corners[2], &(v_dir / v_len)) / texture_size;
+    let tex_coords = [
+        [u0, v0 + v_len / texture_size],
+        [u0 + u_len / texture_size, v0 + v_len / texture_size],
+        [u0, v0],
+        [u0 + u_len / texture_size, v0],
+    ];
+    let first_vertex = mesh.positions.len() as u32;
+    for (p, tc) in corners.iter().zip(tex_coords.iter()) {
+        mesh.positions.push([p.x, p.y, p.z]);
+        mesh.normals.push([normal.x, normal.y, normal.z]);
+        mesh.tex_coords.push(*tc);
+    }
+    let (a, b, c, d) = (
+        first_vertex,
+        first_vertex + 1,
+        first_vertex + 2,
+        first_vertex + 3,
+    );
+    mesh.indices.extend_from_slice(&[a, c, b, b, c, d]);
+}
@@ -0,0 +1,502 @@
+use std::collections::BTreeMap;
+
+use crate::game;
+
+// Level import from top-down images of boards, e.g. scanned layouts or photos. The pixels are
+// classified with a colour legend: walls are decomposed into axis aligned rectangles, round blobs
+// of the hole colour become holes, and the start and the goal are taken from their own marker
+// colours. The image must be axis aligned with the board, rotation or perspective isn't
+// corrected.
+
+const MIN_HOLE_PIXELS: usize = 20; // smaller blobs of the hole colour are noise
+const MIN_WALL_AREA: u32 = 4; // in squares of the wall edge tolerance, smaller walls are noise
+const DEFAULT_WIDTH: f32 = 924.0; // board width if the scale can't be determined, same as level 1
+const GOAL_SIZE: f32 = 2.5 * game::BALL_R; // size of the goal if there's no goal marker
+
+/// Importer parameters. The colours are RGB, and a pixel has a colour of the legend if its
+/// distance to the colour in the RGB space is at most the tolerance.
+pub struct Config {
+    pub wall_color: [u8; 3],
+    pub hole_color: [u8; 3],
+    pub start_color: [u8; 3],
+    pub end_color: [u8; 3],
+    pub tolerance: f32,
+    pub ball_r: Option<f32>, // ball radius in image pixels, estimated from the holes if None
+    pub crop: Option<game::Rect>, // board area in image pixels, the area of the legend colours if None
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            wall_color: [0, 0, 0],
+            hole_color: [255, 0, 0],
+            start_color: [0, 255, 0],
+            end_color: [0, 0, 255],
+            tolerance: 100.0,
+            ball_r: None,
+            crop: None,
+        }
+    }
+}
+
+/// Import a level from img. Returns the level, and the image cropped to the board to be used as
+/// its markings. The level has no AI path. Fails if the image has none of the legend colours or
+/// the crop area isn't inside the image.
+pub fn import(
+    img: &image::RgbaImage,
+    cfg: &Config,
+) -> Result<(game::Level, image::RgbaImage), String> {
+    let mask = |color: [u8; 3]| Mask::new(img, color, cfg.tolerance);
+    let (walls, holes, start, end) = (
+        mask(cfg.wall_color),
+        mask(cfg.hole_color),
+        mask(cfg.start_color),
+        mask(cfg.end_color),
+    );
+
+    let (x0, y0, w, h) = match &cfg.crop {
+        Some(crop) => (
+            crop.pos.x as u32,
+            crop.pos.y as u32,
+            crop.size.w as u32,
+            crop.size.h as u32,
+        ),
+        None => {
+            let bounds = [&walls, &holes, &start, &end]
+                .iter()
+                .filter_map(|m| m.bounds())
+                .fold(None, |acc: Option<(u32, u32, u32, u32)>, b| match acc {
+                    Some(a) => Some((a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3))),
+                    None => Some(b),
+                })
+                .ok_or("No legend colours found in the image")?;
+            (bounds.0, bounds.1, bounds.2 - bounds.0, bounds.3 - bounds.1)
+        }
+    };
+    let inside = |start: u32, len: u32, max: u32| {
+        len > 0 && matches!(start.checked_add(len), Some(end) if end <= max)
+    };
+    if !inside(x0, w, img.width()) || !inside(y0, h, img.height()) {
+        return Err(String::from("The crop area must be inside the image"));
+    }
+    let (walls, holes, start, end) = (
+        walls.crop(x0, y0, w, h),
+        holes.crop(x0, y0, w, h),
+        start.crop(x0, y0, w, h),
+        end.crop(x0, y0, w, h),
+    );
+
+    // Holes are the round blobs of the hole colour. Level units per pixel come from the ball
+    // size, which is known from the size of the holes if it isn't given.
+    let circles: Vec<(f32, f32, f32)> = holes
+        .components()
+        .iter()
+        .filter(|c| c.len() >= MIN_HOLE_PIXELS)
+        .filter_map(|c| circle(c))
+        .collect();
+    let scale = match cfg.ball_r {
+        Some(r) => game::BALL_R / r,
+        None if !circles.is_empty() => {
+            let mut radii: Vec<f32> = circles.iter().map(|c| c.2).collect();
+            radii.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            game::HOLE_R / radii[radii.len() / 2]
+        }
+        None => {
+            println!("No holes to determine the scale from, using the default board width");
+            DEFAULT_WIDTH / w as f32
+        }
+    };
+    // Coordinates are rounded to whole units like in hand made levels
+    let to_level = |v: f32| (v * scale).round();
+    let size = game::Size {
+        w: to_level(w as f32),
+        h: to_level(h as f32),
+    };
+
+    let start = match start.centroid() {
+        Some((x, y)) => game::Point {
+            x: to_level(x),
+            y: to_level(y),
+        },
+        None => {
+            println!("No start marker found, the start is in the middle of the board");
+            game::Point {
+                x: (size.w / 2.0).round(),
+                y: (size.h / 2.0).round(),
+            }
+        }
+    };
+    let end = match end.bounds() {
+        Some((x0, y0, x1, y1)) => game::Rect {
+            pos: game::Point {
+                x: to_level(x0 as f32),
+                y: to_level(y0 as f32),
+            },
+            size: game::Size {
+                w: to_level((x1 - x0) as f32),
+                h: to_level((y1 - y0) as f32),
+            },
+        },
+        None => {
+            println!("No goal marker found, the goal is in the bottom right corner");
+            game::Rect {
+                pos: game::Point {
+                    x: size.w - GOAL_SIZE,
+                    y: size.h - GOAL_SIZE,
+                },
+                size: game::Size {
+                    w: GOAL_SIZE,
+                    h: GOAL_SIZE,
+                },
+            }
+        }
+    };
+
+    // Wall edges within a quarter of the ball radius are considered straight
+    let tolerance = ((game::BALL_R / scale / 4.0).round() as u32).max(1);
+    let walls = walls
+        .rects(tolerance)
+        .iter()
+        .map(|(x0, y0, x1, y1)| {
+            let pos = game::Point {
+                x: to_level(*x0 as f32),
+                y: to_level(*y0 as f32),
+            };
+            game::Wall::new(game::Rect {
+                pos,
+                size: game::Size {
+                    w: to_level(*x1 as f32) - pos.x,
+                    h: to_level(*y1 as f32) - pos.y,
+                },
+            })
+        })
+        .collect();
+
+    let level = game::Level {
+        name: String::from("Imported level"),
+        meta: game::Metadata::default(),
+        translations: BTreeMap::new(),
+        assets: game::Assets::default(),
+        size,
+        start,
+        end,
+        walls,
+        posts: Vec::new(),
+        segments: Vec::new(),
+        arcs: Vec::new(),
+        moving_walls: Vec::new(),
+        rotating_bars: Vec::new(),
+        holes: circles
+            .iter()
+            .map(|(x, y, _)| game::Point {
+                x: to_level(*x),
+                y: to_level(*y),
+            })
+            .collect(),
+        trapdoors: Vec::new(),
+        checkpoints: Vec::new(),
+        zones: Vec::new(),
+        collectibles: Vec::new(),
+        par_time: None,
+        path: Vec::new(),
+    };
+    let markings = image::imageops::crop_imm(img, x0, y0, w, h).to_image();
+    Ok((level, markings))
+}
+
+// The center and the radius of a blob of pixels, if it is round. A disc fills about PI/4 of
+// its bounding box, which is square.
+fn circle(pixels: &[(u32, u32)]) -> Option<(f32, f32, f32)> {
+    let min_x = pixels.iter().map(|p| p.0).min().unwrap();
+    let max_x = pixels.iter().map(|p| p.0).max().unwrap();
+    let min_y = pixels.iter().map(|p| p.1).min().unwrap();
+    let max_y = pixels.iter().map(|p| p.1).max().unwrap();
+    let (w, h) = ((max_x - min_x + 1) as f32, (max_y - min_y + 1) as f32);
+    let fill = pixels.len() as f32 / (w * h);
+    if w.min(h) / w.max(h) < 0.8 || (fill - std::f32::consts::FRAC_PI_4).abs() > 0.1 {
+        println!(
+            "Ignoring a blob of the hole colour at ({}, {}), it isn't round",
+            min_x, min_y
+        );
+        return None;
+    }
+    let n = pixels.len() as f32;
+    Some((
+        // Pixel centers are at +0.5
+        pixels.iter().map(|p| p.0 as f32).sum::<f32>() / n + 0.5,
+        pixels.iter().map(|p| p.1 as f32).sum::<f32>() / n + 0.5,
+        (n / std::f32::consts::PI).sqrt(),
+    ))
+}
+
+// Pixels having a legend colour
+struct Mask {
+    width: u32,
+    height: u32,
+    bits: Vec<bool>,
+}
+
+impl Mask {
+    fn new(img: &image::RgbaImage, color: [u8; 3], tolerance: f32) -> Mask {
+        Mask {
+            width: img.width(),
+            height: img.height(),
+            bits: img
+                .pixels()
+                .map(|p| {
+                    let d2: f32 = (0..3)
+                        .map(|i| (p[i] as f32 - color[i] as f32).powi(2))
+                        .sum();
+                    d2 <= tolerance * tolerance
+                })
+                .collect(),
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> bool {
+        self.bits[(y * self.width + x) as usize]
+    }
+
+    fn crop(&self, x0: u32, y0: u32, w: u32, h: u32) -> Mask {
+        Mask {
+            width: w,
+            height: h,
+            bits: (0..w * h)
+                .map(|i| self.get(x0 + i % w, y0 + i / w))
+                .collect(),
+        }
+    }
+
+    // Bounding box of the set pixels, as (min x, min y, max x + 1, max y + 1)
+    fn bounds(&self) -> Option<(u32, u32, u32, u32)> {
+        (0..self.width * self.height)
+            .filter(|i| self.bits[*i as usize])
+            .map(|i| (i % self.width, i / self.width))
+            .fold(None, |acc, (x, y)| match acc {
+                Some((x0, y0, x1, y1)) => Some((
+                    u32::min(x0, x),
+                    u32::min(y0, y),
+                    u32::max(x1, x + 1),
+                    u32::max(y1, y + 1),
+                )),
+                None => Some((x, y, x + 1, y + 1)),
+            })
+    }
+
+    fn centroid(&self) -> Option<(f32, f32)> {
+        let (mut sx, mut sy, mut n) = (0.0, 0.0, 0);
+        for i in (0..self.width * self.height).filter(|i| self.bits[*i as usize]) {
+            sx += (i % self.width) as f32 + 0.5;
+            sy += (i / self.width) as f32 + 0.5;
+            n += 1;
+        }
+        if n > 0 {
+            Some((sx / n as f32, sy / n as f32))
+        } else {
+            None
+        }
+    }
+
+    // The 4-connected groups of set pixels
+    fn components(&self) -> Vec<Vec<(u32, u32)>> {
+        let mut visited = vec![false; self.bits.len()];
+        let mut components = Vec::new();
+        for i in 0..self.bits.len() {
+            if !self.bits[i] || visited[i] {
+                continue;
+            }
+            visited[i] = true;
+            let mut component = Vec::new();
+            let mut stack = vec![(i as u32 % self.width, i as u32 / self.width)];
+            while let Some((x, y)) = stack.pop() {
+                component.push((x, y));
+                let neighbors = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+                for (nx, ny) in neighbors.iter().copied() {
+                    if nx < self.width && ny < self.height {
+                        let j = (ny * self.width + nx) as usize;
+                        if self.bits[j] && !visited[j] {
+                            visited[j] = true;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+            }
+            components.push(component);
+        }
+        components
+    }
+
+    // Decompose the mask into rectangles. Runs of set pixels on each row are merged with the
+    // runs on the rows above that have their ends within tolerance pixels. Gaps up to the
+    // tolerance within a run are filled. The rectangles are as (x0, y0, x1, y1), with the
+    // ends of the merged runs averaged.
+    fn rects(&self, tolerance: u32) -> Vec<(u32, u32, u32, u32)> {
+        struct Rect {
+            x0_sum: u32,
+            x1_sum: u32,
+            y0: u32,
+            y1: u32,
+            x0: u32, // ends of the latest run
+            x1: u32,
+        }
+        let mut open: Vec<Rect> = Vec::new();
+        let mut done = Vec::new();
+        for y in 0..=self.height {
+            let mut runs: Vec<(u32, u32)> = Vec::new();
+            let mut x = 0;
+            while y < self.height && x < self.width {
+                if self.get(x, y) {
+                    let mut end = x + 1;
+                    while end < self.width && self.get(end, y) {
+                        end += 1;
+                    }
+                    match runs.last_mut() {
+                        Some(last) if x - last.1 <= tolerance => last.1 = end,
+                        _ => runs.push((x, end)),
+                    }
+                    x = end;
+                } else {
+                    x += 1;
+                }
+            }
+            let near = |a: u32, b: u32| (a as i64 - b as i64).abs() <= tolerance as i64;
+            let mut continued = Vec::new();
+            for (x0, x1) in runs {
+                match open.iter().position(|r| near(r.x0, x0) && near(r.x1, x1)) {
+                    Some(i) => {
+                        let mut r = open.swap_remove(i);
+                        r.x0_sum += x0;
+                        r.x1_sum += x1;
+                        r.y1 = y + 1;
+                        r.x0 = x0;
+                        r.x1 = x1;
+                        continued.push(r);
+                    }
+                    None => continued.push(Rect {
+                        x0_sum: x0,
+                        x1_sum: x1,
+                        y0: y,
+                        y1: y + 1,
+                        x0,
+                        x1,
+                    }),
+                }
+            }
+            done.extend(open);
+            open = continued;
+        }
+        let min_area = MIN_WALL_AREA * tolerance * tolerance;
+        done.iter()
+            .map(|r| {
+                let rows = r.y1 - r.y0;
+                let x0 = (r.x0_sum as f32 / rows as f32).round() as u32;
+                let x1 = (r.x1_sum as f32 / rows as f32).round() as u32;
+                (x0, r.y0, x1, r.y1)
+            })
+            .filter(|r| (r.2 - r.0) * (r.3 - r.1) >= min_area)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: image::Rgba<u8> = image::Rgba([255, 255, 255, 255]);
+
+    fn fill(img: &mut image::RgbaImage, x0: u32, y0: u32, x1: u32, y1: u32, color: [u8; 3]) {
+        for y in y0..y1 {
+            for x in x0..x1 {
+                img.put_pixel(x, y, image::Rgba([color[0], color[1], color[2], 255]));
+            }
+        }
+    }
+
+    // A 200x100 board with a wall along the top edge, a hole of radius 6 in the middle, a start
+    // marker on the left and a goal marker in the bottom right corner
+    fn board(cfg: &Config) -> image::RgbaImage {
+        let mut img = image::RgbaImage::from_pixel(200, 100, WHITE);
+        fill(&mut img, 0, 0, 200, 8, cfg.wall_color);
+        for y in 40..60 {
+            for x in 90..110 {
+                let (dx, dy) = (x as f32 + 0.5 - 100.0, y as f32 + 0.5 - 50.0);
+                if dx * dx + dy * dy <= 36.0 {
+                    fill(&mut img, x, y, x + 1, y + 1, cfg.hole_color);
+                }
+            }
+        }
+        fill(&mut img, 20, 40, 30, 50, cfg.start_color);
+        fill(&mut img, 180, 80, 200, 100, cfg.end_color);
+        img
+    }
+
+    #[test]
+    fn imports_synthetic_board() {
+        let mut cfg = Config::new();
+        cfg.ball_r = Some(5.0); // 4 level units per pixel
+        let (level, markings) = import(&board(&cfg), &cfg).unwrap();
+        assert_eq!(level.size, game::Size { w: 800.0, h: 400.0 });
+        assert_eq!(level.walls.len(), 1);
+        assert_eq!(
+            level.walls[0].rect,
+            game::Rect {
+                pos: game::Point { x: 0.0, y: 0.0 },
+                size: game::Size { w: 800.0, h: 32.0 },
+            }
+        );
+        assert_eq!(level.holes, vec![game::Point { x: 400.0, y: 200.0 }]);
+        assert_eq!(level.start, game::Point { x: 100.0, y: 180.0 });
+        assert_eq!(
+            level.end,
+            game::Rect {
+                pos: game::Point { x: 720.0, y: 320.0 },
+                size: game::Size { w: 80.0, h: 80.0 },
+            }
+        );
+        assert_eq!(markings.dimensions(), (200, 100));
+    }
+
+    #[test]
+    fn blank_image_is_an_error() {
+        let img = image::RgbaImage::from_pixel(100, 100, WHITE);
+        assert!(import(&img, &Config::new()).is_err());
+    }
+
+    #[test]
+    fn crop_outside_the_image_is_an_error() {
+        let mut cfg = Config::new();
+        let img = board(&cfg);
+        for &(x, y, w, h) in [
+            (150.0, 0.0, 100.0, 100.0),
+            (0.0, 0.0, 0.0, 100.0),
+            (4.0e9, 0.0, 4.0e9, 100.0),
+        ]
+        .iter()
+        {
+            cfg.crop = Some(game::Rect {
+                pos: game::Point { x, y },
+                size: game::Size { w, h },
+            });
+            assert!(import(&img, &cfg).is_err(), "{:?}", cfg.crop);
+        }
+    }
+
+    #[test]
+    fn mask_components_and_rects() {
+        let mut img = image::RgbaImage::from_pixel(20, 10, WHITE);
+        fill(&mut img, 0, 0, 8, 10, [0, 0, 0]);
+        fill(&mut img, 12, 2, 20, 6, [0, 0, 0]);
+        let mask = Mask::new(&img, [0, 0, 0], 10.0);
+        let mut sizes: Vec<usize> = mask.components().iter().map(|c| c.len()).collect();
+        sizes.sort_unstable();
+        assert_eq!(sizes, vec![32, 80]);
+        let mut rects = mask.rects(1);
+        rects.sort_unstable();
+        assert_eq!(rects, vec![(0, 0, 8, 10), (12, 2, 20, 6)]);
+    }
+}
@@ -1,11 +1,23 @@
 use mobile_entry_point::mobile_entry_point;
+use nalgebra_glm as glm;
 use std::rc::Rc;
 use std::str::FromStr;
 
 mod ai;
+mod audio;
+mod editor;
+mod flow;
 mod game;
 mod game_loop;
 mod graphics;
+mod haptics;
+mod level_analyzer;
+mod level_generator;
+mod level_geometry;
+mod level_import;
+mod locale;
+mod markings;
+mod mesh;
 
 #[mobile_entry_point]
 pub fn init() {
@@ -22,15 +34,73 @@ pub fn init() {
             -f                    'Sets fullscreen mode'
             -s                    'Sets static camera'
             -t                    'Enables statistics output'
+            -l, --lives=[LIVES]   'Sets the number of balls available'
+            -p, --physics=[MODEL] 'Sets the physics model: arcade (default) or realistic'
             -m, --mipmap=[LEVELS] 'Sets the number of texture mipmap levels to use'
-            -n, --no-vsync        'Disables VSync for unlimited FPS'",
+            --level=[FILE]        'Loads the level from a JSON file, also used for saving in the level editor'
+            -n, --no-vsync        'Disables VSync for unlimited FPS'
+            --no-haptics          'Disables haptic feedback'",
+        )
+        .subcommand(
+            clap::SubCommand::with_name("generate")
+                .about("Prints a procedurally generated level as JSON")
+                .args_from_usage(
+                    "--seed=[SEED]        'Sets the seed, defaults to the daily challenge of the current day'
+                    --size=[WxH]         'Sets the board size, e.g. 924x769'
+                    --corridor=[WIDTH]   'Sets the corridor width in ball radii'
+                    --holes=[DENSITY]    'Sets the probability of a hole in each cell along the route, 0...1'
+                    --route=[LENGTH]     'Sets the route length relative to the longest route in the maze, 0...1'
+                    --markings=[FILE]    'Writes the board markings of the level to a PNG file'",
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("analyze")
+                .about("Prints difficulty metrics and a suggested difficulty rating for a level")
+                .args_from_usage(
+                    "<LEVEL>                 'The level JSON file to analyze'
+                    --runs=[N]              'Sets the number of noisy AI runs, default 50'
+                    --noise=[NOISE]         'Sets the AI board control noise relative to the maximum board angle, default 0.4'
+                    --hole-distance=[RADII] 'Sets the distance from the route within which holes are counted, in ball radii, default 2'
+                    --seed=[SEED]           'Sets the seed of the AI noise'
+                    -w, --write             'Stores the suggested difficulty in the level file'",
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("import")
+                .about("Imports a level from a top-down image of a board")
+                .args_from_usage(
+                    "<IMAGE>                 'The image to import'
+                    [LEVEL]                 'The level JSON file to write, defaults to the image name with .json extension'
+                    --wall=[RRGGBB]         'Sets the wall colour, default 000000'
+                    --hole=[RRGGBB]         'Sets the hole colour, default ff0000'
+                    --start=[RRGGBB]        'Sets the start marker colour, default 00ff00'
+                    --end=[RRGGBB]          'Sets the goal marker colour, default 0000ff'
+                    --tolerance=[DISTANCE]  'Sets the maximum RGB distance from a legend colour, default 100'
+                    --ball-radius=[PIXELS]  'Sets the ball radius in image pixels, estimated from the holes by default'
+                    --crop=[X,Y,WxH]        'Sets the board area in image pixels, the area of the legend colours by default'",
+                ),
         )
         .get_matches();
 
+    if let Some(args) = args.subcommand_matches("generate") {
+        generate_level(args);
+        return;
+    }
+    if let Some(args) = args.subcommand_matches("analyze") {
+        analyze_level(args);
+        return;
+    }
+    if let Some(args) = args.subcommand_matches("import") {
+        import_level(args);
+        return;
+    }
+
     let ai = args.is_present("a");
     let fullscreen = args.is_present("f");
     let static_camera = args.is_present("s");
     let stats = args.is_present("t");
+    let haptics = !args.is_present("no-haptics");
+    let level_file = args.value_of("level").map(String::from);
 
     let mut gfx_cfg = graphics::Config::new();
     if let Some(val) = args.value_of("mipmap") {
@@ -41,6 +111,19 @@ pub fn init() {
         gfx_cfg.vsync = false;
     }
 
+    let mut game_cfg = game::Config::new();
+    if let Some(val) = args.value_of("lives") {
+        game_cfg.lives = u32::from_str(val).expect("Invalid lives option");
+        assert!(game_cfg.lives >= 1, "At least one life is needed");
+    }
+    if let Some(val) = args.value_of("physics") {
+        game_cfg.physics = match val {
+            "arcade" => game::PhysicsModel::Arcade,
+            "realistic" => game::PhysicsModel::Realistic,
+            _ => panic!("Invalid physics option"),
+        };
+    }
+
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::Window::new(&event_loop).expect("Failed to create window");
 
@@ -78,15 +161,11 @@ pub fn init() {
             .expect("Failed to append canvas to document body");
     }
 
-    window.set_cursor_visible(false);
-
+    // The cursor is hidden and grabbed when the game starts, the main menu is shown first
     #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
-    {
-        window
-            .set_cursor_position(winit::dpi::PhysicalPosition::new(w / 2, h / 2))
-            .expect("Failed center cursor");
-        window.set_cursor_grab(true).expect("Failed to grab cursor");
-    }
+    window
+        .set_cursor_position(winit::dpi::PhysicalPosition::new(w / 2, h / 2))
+        .expect("Failed center cursor");
 
     #[cfg(not(target_arch = "wasm32"))]
     {
@@ -94,16 +173,199 @@ pub fn init() {
         // on Android, the first Resumed event will set the window
         #[cfg(not(target_os = "android"))]
         gfx.set_window(Some(&window));
-        run(gfx, event_loop, window, w, h, static_camera, stats, ai);
+        run(
+            gfx,
+            event_loop,
+            window,
+            w,
+            h,
+            game_cfg,
+            static_camera,
+            stats,
+            ai,
+            haptics,
+            level_file,
+        );
     }
     #[cfg(target_arch = "wasm32")]
     {
         wasm_bindgen_futures::spawn_local(async move {
             let mut gfx = graphics::Instance::new(gfx_cfg, w, h).await;
             gfx.set_window(Some(&window));
-            run(gfx, event_loop, window, w, h, static_camera, stats, ai);
+            run(
+                gfx,
+                event_loop,
+                window,
+                w,
+                h,
+                game_cfg,
+                static_camera,
+                stats,
+                ai,
+                haptics,
+                level_file,
+            );
+        });
+    }
+}
+
+// Print a generated level, and optionally write its markings image
+fn generate_level(args: &clap::ArgMatches) {
+    let mut cfg = level_generator::Config::new();
+    let seed = match args.value_of("seed") {
+        Some(val) => u64::from_str(val).expect("Invalid seed option"),
+        // The daily challenge uses the number of days since 1970-01-01 as the seed
+        None => {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                / (24 * 60 * 60)
+        }
+    };
+    if let Some(val) = args.value_of("size") {
+        let mut parts = val
+            .split('x')
+            .map(|v| f32::from_str(v).expect("Invalid size option"));
+        cfg.size = game::Size {
+            w: parts.next().unwrap(),
+            h: parts.next().expect("Invalid size option"),
+        };
+    }
+    if let Some(val) = args.value_of("corridor") {
+        cfg.corridor_width = f32::from_str(val).expect("Invalid corridor option");
+    }
+    if let Some(val) = args.value_of("holes") {
+        cfg.hole_density = f32::from_str(val).expect("Invalid holes option");
+    }
+    if let Some(val) = args.value_of("route") {
+        cfg.route_length = f32::from_str(val).expect("Invalid route option");
+    }
+    let level = match level_generator::generate(seed, &cfg) {
+        Ok(level) => level,
+        Err(e) => {
+            println!("Invalid generator options: {}", e);
+            return;
+        }
+    };
+    println!("{}", level.to_json());
+    if let Some(path) = args.value_of("markings") {
+        markings::markings_image(&level, level.size.w as u32, level.size.h as u32)
+            .save(path)
+            .unwrap_or_else(|e| panic!("Failed to write markings to {}: {}", path, e));
+    }
+}
+
+// Print the difficulty report of a level, and optionally store the suggested difficulty
+fn analyze_level(args: &clap::ArgMatches) {
+    let mut cfg = level_analyzer::Config::new();
+    if let Some(val) = args.value_of("runs") {
+        cfg.runs = u32::from_str(val).expect("Invalid runs option");
+    }
+    if let Some(val) = args.value_of("noise") {
+        cfg.noise = f32::from_str(val).expect("Invalid noise option");
+    }
+    if let Some(val) = args.value_of("hole-distance") {
+        cfg.hole_distance = f32::from_str(val).expect("Invalid hole distance option");
+    }
+    if let Some(val) = args.value_of("seed") {
+        cfg.seed = u64::from_str(val).expect("Invalid seed option");
+    }
+    let path = args.value_of("LEVEL").unwrap();
+    let mut level = match game::Level::from_json(
+        &std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read level {}: {}", path, e)),
+    ) {
+        Ok(level) => level,
+        Err(e) => {
+            println!("Invalid level {}: {}", path, e);
+            return;
+        }
+    };
+    if level.path.len() < 2 {
+        println!("Level {} has no AI path to analyze", path);
+        return;
+    }
+    let report = level_analyzer::analyze(&level, &cfg);
+    println!("{}", report);
+    if args.is_present("write") {
+        level.meta.difficulty = Some(report.difficulty);
+        std::fs::write(path, level.to_json())
+            .unwrap_or_else(|e| panic!("Failed to write level {}: {}", path, e));
+    }
+}
+
+// Import a level from an image, and write it with the cropped image as its markings
+fn import_level(args: &clap::ArgMatches) {
+    let mut cfg = level_import::Config::new();
+    let color = |name: &str, default: [u8; 3]| match args.value_of(name) {
+        Some(val) => {
+            let rgb = u32::from_str_radix(val.trim_start_matches('#'), 16)
+                .unwrap_or_else(|_| panic!("Invalid {} option", name));
+            [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]
+        }
+        None => default,
+    };
+    cfg.wall_color = color("wall", cfg.wall_color);
+    cfg.hole_color = color("hole", cfg.hole_color);
+    cfg.start_color = color("start", cfg.start_color);
+    cfg.end_color = color("end", cfg.end_color);
+    if let Some(val) = args.value_of("tolerance") {
+        cfg.tolerance = f32::from_str(val).expect("Invalid tolerance option");
+    }
+    if let Some(val) = args.value_of("ball-radius") {
+        cfg.ball_r = Some(f32::from_str(val).expect("Invalid ball radius option"));
+    }
+    if let Some(val) = args.value_of("crop") {
+        let nums: Vec<f32> = val
+            .split([',', 'x'])
+            .map(|v| f32::from_str(v).expect("Invalid crop option"))
+            .collect();
+        assert!(nums.len() == 4, "Invalid crop option");
+        cfg.crop = Some(game::Rect {
+            pos: game::Point {
+                x: nums[0],
+                y: nums[1],
+            },
+            size: game::Size {
+                w: nums[2],
+                h: nums[3],
+            },
         });
     }
+
+    let image_path = std::path::Path::new(args.value_of("IMAGE").unwrap());
+    let level_path = match args.value_of("LEVEL") {
+        Some(path) => std::path::PathBuf::from(path),
+        None => image_path.with_extension("json"),
+    };
+    let img = image::open(image_path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", image_path.display(), e))
+        .into_rgba8();
+    let (mut level, markings_img) = match level_import::import(&img, &cfg) {
+        Ok(imported) => imported,
+        Err(e) => {
+            println!("Failed to import {}: {}", image_path.display(), e);
+            return;
+        }
+    };
+    let stem = level_path.file_stem().unwrap().to_string_lossy();
+    level.name = String::from(image_path.file_stem().unwrap().to_string_lossy());
+    // The markings file is next to the level file, so the reference is just the file name
+    let markings_file = format!("{}_markings.png", stem);
+    level.assets.markings = Some(markings_file.clone());
+    let markings_path = level_path.with_file_name(&markings_file);
+    markings_img
+        .save(&markings_path)
+        .unwrap_or_else(|e| panic!("Failed to write {}: {}", markings_path.display(), e));
+    std::fs::write(&level_path, level.to_json())
+        .unwrap_or_else(|e| panic!("Failed to write {}: {}", level_path.display(), e));
+    println!(
+        "Imported {} walls and {} holes to {}. Add the AI path in the level editor.",
+        level.walls.len(),
+        level.holes.len(),
+        level_path.display()
+    );
 }
 
 fn run(
@@ -112,54 +374,141 @@ fn run(
     window: winit::window::Window,
     width_pixels: u32,
     height_pixels: u32,
+    game_cfg: game::Config,
     static_camera: bool,
     stats: bool,
     ai: bool,
+    haptics: bool,
+    mut level_file: Option<String>,
 ) {
+    // Create level. The images in the level assets replace the built-in textures. An invalid
+    // level file is reported and the built-in level played instead, without saving over the
+    // file in the editor.
+    let level1 = game::Level::from_json(include_str!("level1.json")).unwrap();
+    let level = match &level_file {
+        Some(path) => match game::Level::from_json(
+            &std::fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("Failed to read level {}: {}", path, e)),
+        ) {
+            Ok(level) => level,
+            Err(e) => {
+                println!("Invalid level {}: {}\nPlaying the built-in level", path, e);
+                level_file = None;
+                level1.clone()
+            }
+        },
+        None => level1.clone(),
+    };
+
+    // Missing or unreadable asset images are reported and the built-in images used instead
+    let asset_img = |path: &Option<String>| {
+        path.as_ref()
+            .and_then(|path| match load_asset_image(level_file.as_deref(), path) {
+                Ok(img) => Some(img),
+                Err(e) => {
+                    println!("{}\nUsing the built-in image", e);
+                    None
+                }
+            })
+    };
+    let board_asset_img = asset_img(&level.assets.board_texture);
+    let markings_asset_img = asset_img(&level.assets.markings);
+    let wall_asset_img = asset_img(&level.assets.wall_texture);
+
     // Create common assets
-    let quad = Rc::new(gfx.create_shape("quad", include_str!("quad.ply")));
-    let cube = Rc::new(gfx.create_shape("cube", include_str!("cube.ply")));
-    let sphere = Rc::new(gfx.create_shape("sphere", include_str!("sphere.ply")));
-    let wall_tex = texture_from_image(
+    let quad = Rc::new(gfx.create_shape("quad", &load_ply(include_bytes!("quad.ply"))));
+    let sphere = Rc::new(gfx.create_shape("sphere", &load_ply(include_bytes!("sphere.ply"))));
+    let wall_img = wall_asset_img
+        .unwrap_or_else(|| create_image(include_bytes!("wall.jpg"), image::ImageFormat::Jpeg));
+    let wall_tex = tiling_texture_from_image(&gfx, "wall", &wall_img);
+    let wall_normals = tiling_texture_from_image(
         &gfx,
-        "wall",
-        &create_image(include_bytes!("wall.jpg"), image::ImageFormat::Jpeg),
+        "wall normals",
+        &normal_map_from_height(&wall_img, 2.0),
     );
-    let ball_tex = texture_from_image(
-        &gfx,
+    let wall_mat = Rc::new(gfx.create_material(
+        "wall",
+        &graphics::MaterialConfig {
+            normal_map: Some(Rc::clone(&wall_normals)),
+            specular: 0.1,
+            roughness: 0.6,
+            high_contrast_color: Some(HIGH_CONTRAST_WALL),
+            ..graphics::MaterialConfig::new(&wall_tex)
+        },
+    ));
+    // Bumpers are painted, glossy walls
+    let bumper_mat = Rc::new(gfx.create_material(
+        "bumper",
+        &graphics::MaterialConfig {
+            base_color: [1.0, 0.35, 0.2, 1.0],
+            normal_map: Some(wall_normals),
+            specular: 0.6,
+            roughness: 0.3,
+            high_contrast_color: Some(HIGH_CONTRAST_BUMPER),
+            ..graphics::MaterialConfig::new(&wall_tex)
+        },
+    ));
+    // Polished steel
+    let ball_mat = Rc::new(gfx.create_material(
         "ball",
-        &create_image(include_bytes!("ball.jpg"), image::ImageFormat::Jpeg),
-    );
-
-    // Create level
-    let level1 = game::Level::from_json(include_str!("level1.json"));
+        &graphics::MaterialConfig {
+            specular: 1.0,
+            roughness: 0.2,
+            high_contrast_color: Some(HIGH_CONTRAST_BALL),
+            ..graphics::MaterialConfig::new(&texture_from_image(
+                &gfx,
+                "ball",
+                &create_image(include_bytes!("ball.jpg"), image::ImageFormat::Jpeg),
+            ))
+        },
+    ));
 
-    // The scene
-    let scene_data = create_scene(
-        &gfx,
-        &level1,
-        &quad,
-        &cube,
-        &sphere,
-        &wall_tex,
-        &ball_tex,
-        &create_image(include_bytes!("board.jpg"), image::ImageFormat::Jpeg),
-        &create_image(
-            include_bytes!("level1_markings.png"),
-            image::ImageFormat::Png,
-        ),
+    // The scene is recreated by the level editor whenever the level changes
+    let board_img = board_asset_img
+        .unwrap_or_else(|| create_image(include_bytes!("board.jpg"), image::ImageFormat::Jpeg));
+    let level1_markings_img = create_image(
+        include_bytes!("level1_markings.png"),
+        image::ImageFormat::Png,
     );
+    let scene_factory = move |gfx: &graphics::Instance, level: &game::Level| {
+        // The hand-drawn markings only match the original level 1, other levels without a
+        // markings image get generated markings
+        let generated_markings_img;
+        let markings_img = if let Some(img) = &markings_asset_img {
+            img
+        } else if *level == level1 {
+            &level1_markings_img
+        } else {
+            generated_markings_img = image::imageops::flip_vertical(&markings::markings_image(
+                level,
+                level.size.w as u32,
+                level.size.h as u32,
+            ));
+            &generated_markings_img
+        };
+        create_scene(
+            gfx,
+            level,
+            &quad,
+            &sphere,
+            &wall_mat,
+            &bumper_mat,
+            &ball_mat,
+            &board_img,
+            markings_img,
+        )
+    };
 
     // Enter the main loop
     let mut gl = game_loop::GameLoop::new(
         window,
-        level1,
+        level,
+        game_cfg,
         gfx,
         width_pixels,
         height_pixels,
-        scene_data.scene,
-        scene_data.board_id,
-        scene_data.ball_id,
+        Box::new(scene_factory),
+        level_file.unwrap_or_else(|| "level.json".to_string()),
         static_camera,
         stats,
         if ai {
@@ -167,34 +516,35 @@ fn run(
         } else {
             None
         },
+        if haptics {
+            Some(haptics::create())
+        } else {
+            None
+        },
     );
     event_loop.run(move |ev, _, cf| *cf = gl.handle_event(&ev));
 }
 
-struct LabyrinthScene {
-    scene: graphics::Scene,
-    board_id: graphics::NodeId,
-    ball_id: graphics::NodeId,
-}
-
-// Creata a scene and add objects to it based on level
+// Creata a scene and add objects to it based on level. GameLoop finds the nodes it animates by
+// name.
 fn create_scene(
     gfx: &graphics::Instance,
     level: &game::Level,
     quad_shape: &Rc<graphics::Shape>,
-    cube_shape: &Rc<graphics::Shape>,
     sphere_shape: &Rc<graphics::Shape>,
-    wall_tex: &Rc<graphics::Texture>,
-    ball_tex: &Rc<graphics::Texture>,
+    wall_mat: &Rc<graphics::Material>,
+    bumper_mat: &Rc<graphics::Material>,
+    ball_mat: &Rc<graphics::Material>,
     board_img: &image::RgbaImage,
     board_markings_img: &image::RgbaImage,
-) -> LabyrinthScene {
+) -> graphics::Scene {
     let mut scene = gfx.create_scene();
-    add_outer_walls(&mut scene, gfx, level, cube_shape, wall_tex);
+    add_outer_walls(&mut scene, gfx, level, wall_mat);
     // Parent node for board moving parts
-    let board_id = scene.add_node(gfx.create_transformation(), None);
-    // Ball has to be added to the scene before the board surface to draw ball falling in to hole correctly
-    let ball_id = add_ball(&mut scene, board_id, gfx, level, sphere_shape, ball_tex);
+    let mut board = gfx.create_transformation();
+    board.set_name("board");
+    let board_id = scene.add_node(board, None);
+    add_ball(&mut scene, board_id, gfx, level, sphere_shape, ball_mat);
     add_board_surface(
         &mut scene,
         board_id,
@@ -204,111 +554,331 @@ fn create_scene(
         board_img,
         board_markings_img,
     );
-    add_edge_walls(&mut scene, board_id, gfx, level, cube_shape, wall_tex);
-    add_walls(&mut scene, board_id, gfx, level, cube_shape, wall_tex);
+    add_surface_zones(&mut scene, board_id, gfx, level);
+    add_walls(&mut scene, board_id, gfx, level, wall_mat, bumper_mat);
+    add_moving_walls(&mut scene, board_id, gfx, level, wall_mat, bumper_mat);
+    add_collectibles(&mut scene, board_id, gfx, level, sphere_shape);
+    add_particle_emitters(&mut scene, board_id, gfx, level);
     add_lights(&mut scene, gfx, level);
     set_initial_camera_position(&mut scene, level);
-    return LabyrinthScene {
-        scene,
-        board_id,
-        ball_id,
-    };
+    return scene;
 }
 
 const BOARD_WALL_W: f32 = game::BALL_R; // width of board edge walls
 const WALL_H: f32 = game::BALL_R; // height of all walls
+const WALL_TEXTURE_SIZE: f32 = 4.0 * WALL_H; // size of one wall texture repetition
+const HOLE_DEPTH: f32 = 4.0 * game::BALL_R;
+const ZONE_TEXTURE_SIZE: f32 = 4.0 * game::BALL_R; // size of one surface zone texture repetition
+
+// Flat colors of the high contrast mode. Apart from the gray board, they are from the Okabe-Ito
+// palette, which stays distinguishable with the common types of color blindness. The holes are
+// black against the gray board.
+const HIGH_CONTRAST_WALL: [f32; 4] = [0.94, 0.89, 0.26, 1.0]; // yellow
+const HIGH_CONTRAST_BUMPER: [f32; 4] = [0.8, 0.47, 0.65, 1.0]; // reddish purple
+const HIGH_CONTRAST_BALL: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const HIGH_CONTRAST_BOARD: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+const HIGH_CONTRAST_HOLE: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const HIGH_CONTRAST_GOAL: [f32; 4] = [0.0, 0.62, 0.45, 0.8]; // bluish green
+const HIGH_CONTRAST_CHECKPOINT: [f32; 4] = [0.34, 0.71, 0.91, 0.6]; // sky blue
+const HIGH_CONTRAST_COIN: [f32; 4] = [0.9, 0.6, 0.0, 1.0]; // orange
+const HIGH_CONTRAST_PENALTY_COIN: [f32; 4] = [0.84, 0.37, 0.0, 1.0]; // vermillion
 
 fn add_outer_walls(
     scene: &mut graphics::Scene,
     gfx: &graphics::Instance,
     level: &game::Level,
-    cube_shape: &Rc<graphics::Shape>,
-    wall_tex: &Rc<graphics::Texture>,
+    wall_mat: &Rc<graphics::Material>,
 ) {
     let outer_wall_area = game::Size {
         w: level.size.w + 3.0 * BOARD_WALL_W,
         h: level.size.h + 3.0 * BOARD_WALL_W,
     };
-    scene.add_node(
-        board_wall(Side::Left, &outer_wall_area, gfx, cube_shape, wall_tex),
-        None,
-    );
-    scene.add_node(
-        board_wall(Side::Right, &outer_wall_area, gfx, cube_shape, wall_tex),
-        None,
-    );
-    scene.add_node(
-        board_wall(Side::Top, &outer_wall_area, gfx, cube_shape, wall_tex),
-        None,
-    );
-    scene.add_node(
-        board_wall(Side::Bottom, &outer_wall_area, gfx, cube_shape, wall_tex),
-        None,
-    );
+    let shape = Rc::new(gfx.create_shape(
+        "outer walls",
+        &level_geometry::frame_mesh(outer_wall_area, WALL_H, BOARD_WALL_W, WALL_TEXTURE_SIZE),
+    ));
+    let mut outer_walls = gfx.create_object(&shape, wall_mat);
+    outer_walls.set_name("outer walls");
+    scene.add_node(outer_walls, None);
 }
 
-fn add_edge_walls(
+// Add the level walls and the board edge walls as a single object, and the bumpers as another
+fn add_walls(
     scene: &mut graphics::Scene,
     parent_id: graphics::NodeId,
     gfx: &graphics::Instance,
     level: &game::Level,
-    cube_shape: &Rc<graphics::Shape>,
-    wall_tex: &Rc<graphics::Texture>,
+    wall_mat: &Rc<graphics::Material>,
+    bumper_mat: &Rc<graphics::Material>,
 ) {
-    scene.add_node(
-        board_wall(Side::Left, &level.size, gfx, cube_shape, wall_tex),
-        Some(parent_id),
-    );
-    scene.add_node(
-        board_wall(Side::Right, &level.size, gfx, cube_shape, wall_tex),
-        Some(parent_id),
-    );
-    scene.add_node(
-        board_wall(Side::Top, &level.size, gfx, cube_shape, wall_tex),
-        Some(parent_id),
-    );
-    scene.add_node(
-        board_wall(Side::Bottom, &level.size, gfx, cube_shape, wall_tex),
-        Some(parent_id),
-    );
+    let shape = Rc::new(gfx.create_shape(
+        "walls",
+        &level_geometry::walls_mesh(level, WALL_H, BOARD_WALL_W, WALL_TEXTURE_SIZE),
+    ));
+    let mut walls = gfx.create_object(&shape, wall_mat);
+    walls.set_name("walls");
+    scene.add_node(walls, Some(parent_id));
+
+    let bumpers_mesh = level_geometry::bumpers_mesh(level, WALL_H, WALL_TEXTURE_SIZE);
+    if !bumpers_mesh.indices.is_empty() {
+        let mut bumpers = gfx.create_object(
+            &Rc::new(gfx.create_shape("bumpers", &bumpers_mesh)),
+            bumper_mat,
+        );
+        bumpers.set_name("bumpers");
+        scene.add_node(bumpers, Some(parent_id));
+    }
 }
 
-fn add_walls(
+// Add the walls that move during the game. GameLoop finds their nodes by name and animates them.
+fn add_moving_walls(
     scene: &mut graphics::Scene,
     parent_id: graphics::NodeId,
     gfx: &graphics::Instance,
     level: &game::Level,
-    cube_shape: &Rc<graphics::Shape>,
-    wall_tex: &Rc<graphics::Texture>,
+    wall_mat: &Rc<graphics::Material>,
+    bumper_mat: &Rc<graphics::Material>,
 ) {
-    for wall in level.walls.iter() {
-        let mut obj = gfx.create_object(cube_shape, wall_tex);
-        obj.set_scaling(wall.size.w, WALL_H, wall.size.h);
-        obj.set_position(
-            wall.pos.x - level.size.w / 2.0 + wall.size.w / 2.0,
-            WALL_H / 2.0,
-            wall.pos.y - level.size.h / 2.0 + wall.size.h / 2.0,
+    let material = |restitution: f32| {
+        if game::is_bumper(restitution) {
+            bumper_mat
+        } else {
+            wall_mat
+        }
+    };
+    for (i, wall) in level.moving_walls.iter().enumerate() {
+        let shape = Rc::new(gfx.create_shape(
+            "moving wall",
+            &level_geometry::box_mesh(wall.size, WALL_H, WALL_TEXTURE_SIZE),
+        ));
+        let mut node = gfx.create_object(&shape, material(wall.restitution));
+        node.set_name(&format!("moving wall {}", i));
+        scene.add_node(node, Some(parent_id));
+    }
+    for (i, bar) in level.rotating_bars.iter().enumerate() {
+        let shape = Rc::new(gfx.create_shape(
+            "rotating bar",
+            &level_geometry::box_mesh(
+                game::Size {
+                    w: bar.length,
+                    h: bar.w,
+                },
+                WALL_H,
+                WALL_TEXTURE_SIZE,
+            ),
+        ));
+        let mut node = gfx.create_object(&shape, material(bar.restitution));
+        node.set_name(&format!("rotating bar {}", i));
+        node.set_position(
+            bar.center.x - level.size.w / 2.0,
+            0.0,
+            bar.center.y - level.size.h / 2.0,
         );
-        scene.add_node(obj, Some(parent_id));
+        scene.add_node(node, Some(parent_id));
     }
 }
 
+// Add the surface zones of the level, one object for each surface type
+fn add_surface_zones(
+    scene: &mut graphics::Scene,
+    parent_id: graphics::NodeId,
+    gfx: &graphics::Instance,
+    level: &game::Level,
+) {
+    for surface in [
+        game::Surface::Ice,
+        game::Surface::Carpet,
+        game::Surface::Sticky,
+    ]
+    .iter()
+    {
+        if !level.zones.iter().any(|z| z.surface == *surface) {
+            continue;
+        }
+        let name = format!("{:?} zones", surface).to_lowercase();
+        let img = surface_image(*surface);
+        let mut zones = gfx.create_object(
+            &Rc::new(gfx.create_shape(
+                &name,
+                // below the board markings so that they stay visible on top of the zones
+                &level_geometry::zones_mesh(
+                    level,
+                    *surface,
+                    game::BALL_R / 200.0,
+                    ZONE_TEXTURE_SIZE,
+                ),
+            )),
+            &Rc::new(gfx.create_material(
+                &name,
+                &graphics::MaterialConfig {
+                    normal_map: Some(tiling_texture_from_image(
+                        gfx,
+                        &name,
+                        &normal_map_from_height(&img, 1.0),
+                    )),
+                    specular: match surface {
+                        game::Surface::Ice => 0.8,
+                        game::Surface::Sticky => 0.4,
+                        _ => 0.0,
+                    },
+                    roughness: match surface {
+                        game::Surface::Ice => 0.1,
+                        game::Surface::Sticky => 0.3,
+                        _ => 1.0,
+                    },
+                    ..graphics::MaterialConfig::new(&tiling_texture_from_image(gfx, &name, &img))
+                },
+            )),
+        );
+        zones.set_name(&name);
+        scene.add_node(zones, Some(parent_id));
+    }
+}
+
+// Add the collectibles as flattened spheres standing on the board, i.e. coins. Collectibles that
+// add a time penalty are red. GameLoop finds the nodes by name, spins them and hides the collected ones.
+fn add_collectibles(
+    scene: &mut graphics::Scene,
+    parent_id: graphics::NodeId,
+    gfx: &graphics::Instance,
+    level: &game::Level,
+    sphere_shape: &Rc<graphics::Shape>,
+) {
+    let tex = white_texture(gfx, "collectible");
+    let coin_mat = Rc::new(gfx.create_material(
+        "coin",
+        &graphics::MaterialConfig {
+            base_color: [1.0, 0.8, 0.2, 1.0],
+            specular: 1.0,
+            roughness: 0.3,
+            high_contrast_color: Some(HIGH_CONTRAST_COIN),
+            ..graphics::MaterialConfig::new(&tex)
+        },
+    ));
+    let penalty_mat = Rc::new(gfx.create_material(
+        "penalty coin",
+        &graphics::MaterialConfig {
+            base_color: [0.8, 0.1, 0.1, 1.0],
+            specular: 1.0,
+            roughness: 0.3,
+            high_contrast_color: Some(HIGH_CONTRAST_PENALTY_COIN),
+            ..graphics::MaterialConfig::new(&tex)
+        },
+    ));
+    for (i, c) in level.collectibles.iter().enumerate() {
+        let mut coin = gfx.create_object(
+            sphere_shape,
+            if c.time > 0.0 {
+                &penalty_mat
+            } else {
+                &coin_mat
+            },
+        );
+        coin.set_name(&format!("collectible {}", i));
+        coin.set_scaling(
+            game::COLLECTIBLE_R,
+            game::COLLECTIBLE_R,
+            0.3 * game::COLLECTIBLE_R,
+        );
+        coin.set_position(
+            c.pos.x - level.size.w / 2.0,
+            game::COLLECTIBLE_R,
+            c.pos.y - level.size.h / 2.0,
+        );
+        scene.add_node(coin, Some(parent_id));
+    }
+}
+
+// Add the particle emitters for the effects: dust from hard impacts, sparkles at the goal, and a
+// puff from a hole when the ball falls in. GameLoop finds the emitters by name, moves the dust
+// emitter to where the ball hits, and triggers the effects based on the game events.
+fn add_particle_emitters(
+    scene: &mut graphics::Scene,
+    parent_id: graphics::NodeId,
+    gfx: &graphics::Instance,
+    level: &game::Level,
+) {
+    const R: f32 = game::BALL_R;
+    let tex = Rc::new(gfx.create_texture("particle", 32, 32, &particle_image(32)));
+    let mut add_emitter = |name: &str, cfg: &graphics::ParticleConfig, x: f32, z: f32| {
+        let mut emitter = gfx.create_emitter(name, cfg);
+        emitter.set_name(name);
+        emitter.set_position(x - level.size.w / 2.0, R, z - level.size.h / 2.0);
+        scene.add_node(emitter, Some(parent_id));
+    };
+    add_emitter(
+        "dust particles",
+        &graphics::ParticleConfig {
+            lifetime: 0.6,
+            spawn_radius: 0.5 * R,
+            velocity: [0.0, 1.0 * R, 0.0],
+            velocity_spread: 2.0 * R,
+            start_color: [0.6, 0.55, 0.5, 0.5],
+            end_color: [0.6, 0.55, 0.5, 0.0],
+            start_size: 0.3 * R,
+            end_size: 1.2 * R,
+            max_particles: 100,
+            ..graphics::ParticleConfig::new(&tex)
+        },
+        level.start.x,
+        level.start.y,
+    );
+    add_emitter(
+        "goal particles",
+        &graphics::ParticleConfig {
+            blend_mode: graphics::BlendMode::Additive,
+            lifetime: 1.5,
+            spawn_radius: 0.5 * level.end.size.w.min(level.end.size.h),
+            velocity: [0.0, 6.0 * R, 0.0],
+            velocity_spread: 3.0 * R,
+            acceleration: [0.0, -6.0 * R, 0.0],
+            start_color: [1.0, 0.9, 0.4, 1.0],
+            end_color: [1.0, 0.5, 0.1, 0.0],
+            start_size: 0.4 * R,
+            end_size: 0.1 * R,
+            max_particles: 200,
+            ..graphics::ParticleConfig::new(&tex)
+        },
+        level.end.pos.x + level.end.size.w / 2.0,
+        level.end.pos.y + level.end.size.h / 2.0,
+    );
+    add_emitter(
+        "hole particles",
+        &graphics::ParticleConfig {
+            lifetime: 0.5,
+            spawn_radius: 0.5 * game::HOLE_R,
+            velocity: [0.0, 3.0 * R, 0.0],
+            velocity_spread: 1.5 * R,
+            acceleration: [0.0, -4.0 * R, 0.0],
+            start_color: [0.3, 0.25, 0.2, 0.7],
+            end_color: [0.3, 0.25, 0.2, 0.0],
+            start_size: 0.3 * R,
+            end_size: 0.8 * R,
+            max_particles: 50,
+            ..graphics::ParticleConfig::new(&tex)
+        },
+        level.start.x,
+        level.start.y,
+    );
+}
+
 fn add_ball(
     scene: &mut graphics::Scene,
     parent_id: graphics::NodeId,
     gfx: &graphics::Instance,
     level: &game::Level,
     sphere_shape: &Rc<graphics::Shape>,
-    ball_tex: &Rc<graphics::Texture>,
-) -> graphics::NodeId {
-    let mut ball = gfx.create_object(sphere_shape, ball_tex);
+    ball_mat: &Rc<graphics::Material>,
+) {
+    let mut ball = gfx.create_object(sphere_shape, ball_mat);
+    ball.set_name("ball");
     ball.set_scaling(game::BALL_R, game::BALL_R, game::BALL_R);
     ball.set_position(
         level.start.x - level.size.w / 2.0,
         game::BALL_R,
         level.start.y - level.size.h / 2.0,
     );
-    return scene.add_node(ball, Some(parent_id));
+    scene.add_node(ball, Some(parent_id));
 }
 
 fn add_board_surface(
@@ -320,19 +890,118 @@ fn add_board_surface(
     board_img: &image::RgbaImage,
     board_markings_img: &image::RgbaImage,
 ) {
-    let mut board_surface =
-        gfx.create_object(quad_shape, &create_board_surface(gfx, board_img, level));
+    let board_tex = create_board_surface(gfx, board_img, level);
+    let mut board_surface = gfx.create_object(
+        &Rc::new(gfx.create_shape("board surface", &level_geometry::board_surface_mesh(level))),
+        &Rc::new(gfx.create_material(
+            "board",
+            &graphics::MaterialConfig {
+                specular: 0.05,
+                high_contrast_color: Some(HIGH_CONTRAST_BOARD),
+                ..graphics::MaterialConfig::new(&board_tex)
+            },
+        )),
+    );
     // extend board surface very slightly below board edge walls so that the background doesn't leak through from the seam
     board_surface.set_scaling(
-        level.size.w + BOARD_WALL_W / 100.0,
+        1.0 + BOARD_WALL_W / 100.0 / level.size.w,
         1.0,
-        level.size.h + BOARD_WALL_W / 100.0,
+        1.0 + BOARD_WALL_W / 100.0 / level.size.h,
     );
     scene.add_node(board_surface, Some(parent_id));
 
+    let holes = gfx.create_object(
+        &Rc::new(gfx.create_shape("holes", &level_geometry::holes_mesh(level, HOLE_DEPTH))),
+        &Rc::new(gfx.create_material(
+            "holes",
+            &graphics::MaterialConfig {
+                base_color: [0.3, 0.3, 0.3, 1.0],
+                high_contrast_color: Some(HIGH_CONTRAST_HOLE),
+                ..graphics::MaterialConfig::new(&board_tex)
+            },
+        )),
+    );
+    scene.add_node(holes, Some(parent_id));
+
+    // Trapdoor lids are hidden by GameLoop when the trapdoors open
+    let lid_mat = Rc::new(gfx.create_material(
+        "trapdoor lid",
+        &graphics::MaterialConfig {
+            base_color: [0.8, 0.8, 0.8, 1.0],
+            specular: 0.05,
+            high_contrast_color: Some(HIGH_CONTRAST_BOARD),
+            ..graphics::MaterialConfig::new(&board_tex)
+        },
+    ));
+    for (i, trapdoor) in level.trapdoors.iter().enumerate() {
+        let mut lid = gfx.create_object(
+            &Rc::new(gfx.create_shape(
+                "trapdoor lid",
+                &level_geometry::trapdoor_lid_mesh(level, trapdoor),
+            )),
+            &lid_mat,
+        );
+        lid.set_name(&format!("trapdoor lid {}", i));
+        scene.add_node(lid, Some(parent_id));
+    }
+
+    // Checkpoints are marked by translucent green areas on the board
+    let checkpoint_mat = Rc::new(gfx.create_material(
+        "checkpoint",
+        &graphics::MaterialConfig {
+            base_color: [0.3, 0.9, 0.3, 0.4],
+            alpha_mode: graphics::AlphaMode::Blend,
+            high_contrast_color: Some(HIGH_CONTRAST_CHECKPOINT),
+            ..graphics::MaterialConfig::new(&white_texture(gfx, "checkpoint"))
+        },
+    ));
+    for (i, checkpoint) in level.checkpoints.iter().enumerate() {
+        let mut marker = gfx.create_object(quad_shape, &checkpoint_mat);
+        marker.set_name(&format!("checkpoint {}", i));
+        marker.set_scaling(checkpoint.size.w, 1.0, checkpoint.size.h);
+        marker.set_position(
+            checkpoint.pos.x + checkpoint.size.w / 2.0 - level.size.w / 2.0,
+            game::BALL_R / 100.0,
+            checkpoint.pos.y + checkpoint.size.h / 2.0 - level.size.h / 2.0,
+        );
+        scene.add_node(marker, Some(parent_id));
+    }
+
+    // The goal is only shown by the board markings, except in the high contrast mode, where it
+    // is filled with a strong color
+    let mut goal_marker = gfx.create_object(
+        quad_shape,
+        &Rc::new(gfx.create_material(
+            "goal",
+            &graphics::MaterialConfig {
+                base_color: [0.0, 0.0, 0.0, 0.0],
+                alpha_mode: graphics::AlphaMode::Blend,
+                high_contrast_color: Some(HIGH_CONTRAST_GOAL),
+                ..graphics::MaterialConfig::new(&white_texture(gfx, "goal"))
+            },
+        )),
+    );
+    goal_marker.set_scaling(level.end.size.w, 1.0, level.end.size.h);
+    goal_marker.set_position(
+        level.end.pos.x + level.end.size.w / 2.0 - level.size.w / 2.0,
+        game::BALL_R / 100.0,
+        level.end.pos.y + level.end.size.h / 2.0 - level.size.h / 2.0,
+    );
+    scene.add_node(goal_marker, Some(parent_id));
+
     let mut board_markings = gfx.create_object(
         quad_shape,
-        &texture_from_image(gfx, "markings", board_markings_img),
+        &Rc::new(gfx.create_material(
+            "markings",
+            &graphics::MaterialConfig {
+                alpha_mode: graphics::AlphaMode::Blend,
+                ..graphics::MaterialConfig::new(&texture_from_image(
+                    gfx,
+                    "markings",
+                    board_markings_img,
+                ))
+            },
+        )),
     );
     board_markings.set_scaling(level.size.w, 1.0, level.size.h);
     // lift the marking very slightly above the board surface so that there's no z-fighting and the markings are visible
@@ -381,51 +1050,8 @@ fn set_initial_camera_position(scene: &mut graphics::Scene, level: &game::Level)
     );
 }
 
-enum Side {
-    Left,
-    Right,
-    Top,
-    Bottom,
-}
-
-// Create a board edge wall scene node
-fn board_wall(
-    side: Side,
-    board_size: &game::Size,
-    gfx: &graphics::Instance,
-    shape: &Rc<graphics::Shape>,
-    texture: &Rc<graphics::Texture>,
-) -> graphics::Node {
-    let mut node = gfx.create_object(shape, texture);
-    node.set_scaling(
-        match side {
-            Side::Left | Side::Right => BOARD_WALL_W,
-            Side::Top | Side::Bottom => board_size.w + 2.0 * BOARD_WALL_W,
-        },
-        WALL_H,
-        match side {
-            Side::Left | Side::Right => board_size.h,
-            Side::Top | Side::Bottom => BOARD_WALL_W,
-        },
-    );
-    node.set_position(
-        match side {
-            Side::Left => -board_size.w / 2.0 - BOARD_WALL_W / 2.0,
-            Side::Right => board_size.w / 2.0 + BOARD_WALL_W / 2.0,
-            Side::Top | Side::Bottom => 0.0,
-        },
-        WALL_H / 2.0,
-        match side {
-            Side::Left | Side::Right => 0.0,
-            Side::Top => board_size.h / 2.0 + BOARD_WALL_W / 2.0,
-            Side::Bottom => -board_size.h / 2.0 - BOARD_WALL_W / 2.0,
-        },
-    );
-    return node;
-}
-
 // Create a texture for the board surface by creating a copy of orig_img cropped to the shape of
-// the level and draw transparent circles to it based on the level hole locations.
+// the level.
 fn create_board_surface(
     gfx: &graphics::Instance,
     orig_img: &image::RgbaImage,
@@ -442,27 +1068,26 @@ fn create_board_surface(
     } else {
         (orig_img.width() as f32 / level_aspect) as u32
     };
-    let mut img = image::imageops::crop_imm(orig_img, 0, 0, cropped_w, cropped_h).to_image();
-    let scale = img.width() as f32 / level.size.w;
-    let hole_r = scale * game::HOLE_R;
-    for hole in level.holes.iter() {
-        let u_mid = scale * hole.x;
-        let v_mid = scale * (level.size.h - hole.y); // board and texture coordinates have opposite y-direction
-        let u_max = (u_mid + hole_r) as u32;
-        let u_min = (u_mid - hole_r) as u32;
-        let v_min = (v_mid - hole_r) as u32;
-        let v_max = (v_mid + hole_r) as u32;
-        for u in u_min..u_max + 1 {
-            for v in v_min..v_max + 1 {
-                if (u_mid - u as f32).powi(2) + (v_mid - v as f32).powi(2) < hole_r.powi(2) {
-                    img.get_pixel_mut(u, v)[3] = 0;
-                }
-            }
-        }
-    }
+    let img = image::imageops::crop_imm(orig_img, 0, 0, cropped_w, cropped_h).to_image();
     return texture_from_image(gfx, "board", &img);
 }
 
+// White round dot fading out towards the edges, for particles
+fn particle_image(size: u32) -> image::RgbaImage {
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        let r = glm::distance(
+            &glm::vec2(x as f32 + 0.5, y as f32 + 0.5),
+            &glm::vec2(size as f32 / 2.0, size as f32 / 2.0),
+        ) / (size as f32 / 2.0);
+        image::Rgba([255, 255, 255, (255.0 * (1.0 - r).max(0.0).powi(2)) as u8])
+    })
+}
+
+// 1x1 white texture for materials that only have a base color
+fn white_texture(gfx: &graphics::Instance, name: &str) -> Rc<graphics::Texture> {
+    Rc::new(gfx.create_texture(name, 1, 1, &[255, 255, 255, 255]))
+}
+
 fn texture_from_image(
     gfx: &graphics::Instance,
     name: &str,
@@ -471,6 +1096,101 @@ fn texture_from_image(
     Rc::new(gfx.create_texture(name, img.width(), img.height(), img))
 }
 
+fn tiling_texture_from_image(
+    gfx: &graphics::Instance,
+    name: &str,
+    img: &image::RgbaImage,
+) -> Rc<graphics::Texture> {
+    Rc::new(gfx.create_tiling_texture(name, img.width(), img.height(), img))
+}
+
+// Create a tangent space normal map from the luminance of img, treating it as a height map.
+// strength scales the slopes: larger values produce more pronounced bumps.
+fn normal_map_from_height(img: &image::RgbaImage, strength: f32) -> image::RgbaImage {
+    let height = |x: i64, y: i64| {
+        let p = img.get_pixel(
+            x.rem_euclid(img.width() as i64) as u32,
+            y.rem_euclid(img.height() as i64) as u32,
+        );
+        (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) / 255.0
+    };
+    image::RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let dx = strength * (height(x + 1, y) - height(x - 1, y));
+        let dy = strength * (height(x, y + 1) - height(x, y - 1));
+        let n = glm::normalize(&glm::vec3(-dx, -dy, 1.0));
+        image::Rgba([
+            (127.5 * (n.x + 1.0)) as u8,
+            (127.5 * (n.y + 1.0)) as u8,
+            (127.5 * (n.z + 1.0)) as u8,
+            255,
+        ])
+    })
+}
+
+// Generate a tiling texture image for a surface zone: pale ice with scratches, a fibrous carpet
+// or dark, blotchy tar
+fn surface_image(surface: game::Surface) -> image::RgbaImage {
+    const SIZE: u32 = 64;
+    // Cheap deterministic per-pixel noise in 0...1
+    let noise = |x: u32, y: u32| {
+        let h = (x.wrapping_mul(374761393) ^ y.wrapping_mul(668265263)).wrapping_mul(1274126177);
+        (h >> 24) as f32 / 255.0
+    };
+    let shade = |rgb: [f32; 3], f: f32| {
+        image::Rgba([
+            (rgb[0] * f).min(255.0) as u8,
+            (rgb[1] * f).min(255.0) as u8,
+            (rgb[2] * f).min(255.0) as u8,
+            255,
+        ])
+    };
+    image::RgbaImage::from_fn(SIZE, SIZE, |x, y| match surface {
+        game::Surface::Ice => {
+            let scratch = (x + 2 * y) % 23 == 0 || (3 * x + SIZE - y) % 37 == 0;
+            shade(
+                [200.0, 225.0, 245.0],
+                if scratch {
+                    1.1
+                } else {
+                    0.95 + 0.05 * noise(x, y)
+                },
+            )
+        }
+        game::Surface::Carpet => shade([140.0, 40.0, 50.0], 0.7 + 0.3 * noise(x, y)),
+        game::Surface::Sticky => {
+            // Blotches on a regular grid, wrapping around the texture edges
+            let (dx, dy) = ((x % 16) as f32 - 8.0, (y % 16) as f32 - 8.0);
+            let blotch = dx * dx + dy * dy < 20.0 + 20.0 * noise(x / 16, y / 16);
+            shade(
+                [90.0, 60.0, 20.0],
+                if blotch { 0.6 } else { 0.9 + 0.1 * noise(x, y) },
+            )
+        }
+        game::Surface::Board => shade([255.0, 255.0, 255.0], 1.0),
+    })
+}
+
+fn load_ply(bytes: &[u8]) -> mesh::Mesh {
+    mesh::Mesh::from_ply(bytes).unwrap_or_else(|e| panic!("Failed to load built-in mesh: {}", e))
+}
+
+// Load an image file given in the level assets. The path is relative to the level file.
+fn load_asset_image(level_file: Option<&str>, path: &str) -> Result<image::RgbaImage, String> {
+    let path = match level_file.and_then(|f| std::path::Path::new(f).parent()) {
+        Some(dir) => dir.join(path),
+        None => std::path::PathBuf::from(path),
+    };
+    match image::open(&path) {
+        Ok(img) => Ok(img.flipv().into_rgba8()),
+        Err(e) => Err(format!(
+            "Failed to load level asset {}: {}",
+            path.display(),
+            e
+        )),
+    }
+}
+
 // Create an image suitable for texture use from raw image file bytes
 fn create_image(bytes: &[u8], format: image::ImageFormat) -> image::RgbaImage {
     image::load_from_memory_with_format(bytes, format)
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// Localization of the UI strings. The message catalogs use the Fluent syntax
+// (https://projectfluent.org), limited to simple messages: `id = text`, where the text can
+// continue on indented lines and contain `{ $variable }` placeables. Messages missing from a
+// catalog fall back to English.
+
+/// The supported languages as language codes and the names of the languages in themselves
+pub const LANGUAGES: [(&str, &str); 2] = [("en", "English"), ("fi", "Suomi")];
+const DEFAULT_LANGUAGE: &str = "en";
+
+fn catalog_source(lang: &str) -> &'static str {
+    match lang {
+        "fi" => include_str!("fi.ftl"),
+        _ => include_str!("en.ftl"),
+    }
+}
+
+/// Messages by id. Panics on Fluent syntax that is not supported.
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn parse(source: &str) -> Catalog {
+        let mut messages = HashMap::new();
+        let mut current: Option<String> = None; // id of the message that can continue
+        for (i, line) in source.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                current = None;
+            } else if line.starts_with(char::is_whitespace) {
+                // Continuation lines are joined with newlines
+                let id = current
+                    .as_ref()
+                    .unwrap_or_else(|| panic!("Unexpected indentation on line {}", i + 1));
+                let text: &mut String = messages.get_mut(id).unwrap();
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+                text.push_str(line.trim());
+            } else {
+                let eq = line
+                    .find('=')
+                    .unwrap_or_else(|| panic!("Expected a message on line {}", i + 1));
+                let (id, text) = (line[..eq].trim(), &line[eq + 1..]);
+                assert!(
+                    id.starts_with(|c: char| c.is_ascii_alphabetic())
+                        && id
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+                    "Invalid message id \"{}\" on line {}",
+                    id,
+                    i + 1
+                );
+                messages.insert(String::from(id), String::from(text.trim()));
+                current = Some(String::from(id));
+            }
+        }
+        Catalog { messages }
+    }
+}
+
+/// The UI strings in one language
+pub struct Locale {
+    pub lang: &'static str,
+    catalog: Catalog,
+    fallback: Catalog,
+}
+
+impl Locale {
+    /// Locale for a language code in LANGUAGES. Other languages get the default language.
+    pub fn new(lang: &str) -> Locale {
+        let lang = supported_language(lang).unwrap_or(DEFAULT_LANGUAGE);
+        Locale {
+            lang,
+            catalog: Catalog::parse(catalog_source(lang)),
+            fallback: Catalog::parse(catalog_source(DEFAULT_LANGUAGE)),
+        }
+    }
+
+    /// The message with the given id
+    pub fn get(&self, id: &str) -> String {
+        self.format(id, &[])
+    }
+
+    /// The message with the given id, with its variables replaced by the values in args.
+    /// Unknown ids give the id itself so that missing translations are easy to spot.
+    pub fn format(&self, id: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+        let text = match self
+            .catalog
+            .messages
+            .get(id)
+            .or_else(|| self.fallback.messages.get(id))
+        {
+            Some(text) => text,
+            None => return String::from(id),
+        };
+        let mut result = String::new();
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find('{') {
+            result.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            let placeable = rest[start + 1..end].trim();
+            match args
+                .iter()
+                .find(|(name, _)| placeable.strip_prefix('$') == Some(*name))
+            {
+                Some((_, val)) => result.push_str(&val.to_string()),
+                None => result.push_str(&rest[start..=end]),
+            }
+            rest = &rest[end + 1..];
+        }
+        result.push_str(rest);
+        result
+    }
+}
+
+// The language of a language tag like "fi", "fi-FI" or "fi_FI.UTF-8", if it is supported
+fn supported_language(tag: &str) -> Option<&'static str> {
+    let primary = tag.split(['-', '_', '.']).next().unwrap().to_lowercase();
+    LANGUAGES
+        .iter()
+        .map(|&(lang, _)| lang)
+        .find(|&lang| lang == primary)
+}
+
+/// The language of the system if it is supported, otherwise the default language
+pub fn detect_language() -> &'static str {
+    match system_language() {
+        Some(tag) => {
+            println!("System language {}", tag);
+            supported_language(&tag).unwrap_or(DEFAULT_LANGUAGE)
+        }
+        None => DEFAULT_LANGUAGE,
+    }
+}
+
+// The POSIX locale environment variables in the order of precedence. Windows doesn't set them,
+// so the default language is used there.
+#[cfg(not(any(target_arch = "wasm32", target_os = "android")))]
+fn system_language() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|val| !val.is_empty())
+}
+
+// The preferred language of the browser
+#[cfg(target_arch = "wasm32")]
+fn system_language() -> Option<String> {
+    web_sys::window()?.navigator().language()
+}
+
+// The default locale of the Java VM, which follows the system settings
+#[cfg(target_os = "android")]
+fn system_language() -> Option<String> {
+    let activity = ndk_glue::native_activity();
+    let vm = unsafe { jni::JavaVM::from_raw(activity.vm() as *mut jni::sys::JavaVM) }.ok()?;
+    let env = vm.attach_current_thread_permanently().ok()?;
+    let get_language = || -> jni::errors::Result<String> {
+        let locale = env
+            .call_static_method(
+                "java/util/Locale",
+                "getDefault",
+                "()Ljava/util/Locale;",
+                &[],
+            )?
+            .l()?;
+        let lang = env
+            .call_method(locale, "getLanguage", "()Ljava/lang/String;", &[])?
+            .l()?;
+        Ok(env.get_string(lang.into())?.into())
+    };
+    match get_language() {
+        Ok(lang) => Some(lang),
+        Err(e) => {
+            println!("Failed to get the system language: {}", e);
+            let _ = env.exception_clear();
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn catalogs_parse_with_the_same_messages() {
+        let ids = |lang: &str| -> BTreeSet<String> {
+            Catalog::parse(catalog_source(lang))
+                .messages
+                .keys()
+                .cloned()
+                .collect()
+        };
+        let en = ids("en");
+        assert!(!en.is_empty());
+        for (lang, _) in LANGUAGES.iter() {
+            assert_eq!(ids(lang), en, "{}", lang);
+        }
+    }
+
+    #[test]
+    fn continuation_lines_are_joined() {
+        let catalog =
+            Catalog::parse("# Comment\nhelp = First line\n    second line\n\nquit = Quit\n");
+        assert_eq!(catalog.messages["help"], "First line\nsecond line");
+        assert_eq!(catalog.messages["quit"], "Quit");
+    }
+
+    #[test]
+    fn format_substitutes_variables() {
+        let locale = Locale::new("en");
+        assert_eq!(
+            locale.format("level-author", &[("author", &"Kalle")]),
+            "by Kalle"
+        );
+        // Placeables without a value are left as they are
+        assert_eq!(locale.get("level-author"), "by { $author }");
+        assert_eq!(
+            locale.format("level-author", &[("name", &"Kalle")]),
+            "by { $author }"
+        );
+        assert_eq!(locale.get("no-such-message"), "no-such-message");
+    }
+
+    #[test]
+    fn language_tags() {
+        assert_eq!(supported_language("fi_FI.UTF-8"), Some("fi"));
+        assert_eq!(supported_language("fi-FI"), Some("fi"));
+        assert_eq!(supported_language("EN"), Some("en"));
+        assert_eq!(supported_language("de_DE"), None);
+        assert_eq!(Locale::new("sv").lang, DEFAULT_LANGUAGE);
+    }
+}
@@ -0,0 +1,274 @@
+use nalgebra_glm as glm;
+
+use crate::game;
+
+// Rasterizes the board markings of a level: the route along the AI path with numbered waypoints
+// and an arrow at the goal, "START" and "FINISH" labels, rings around the holes and the outline
+// of the goal. The markings are black on a transparent background, drawn in the orientation of
+// the level, i.e. the first image row is at level y = 0.
+
+const LINE_W: f32 = 0.25 * game::BALL_R;
+const RING_R: f32 = game::HOLE_R + 0.3 * game::BALL_R;
+const LABEL_H: f32 = 0.8 * game::BALL_R; // height of the waypoint numbers
+const TITLE_H: f32 = 1.2 * game::BALL_R; // height of "START" and "FINISH"
+const ARROW_LEN: f32 = 1.2 * game::BALL_R;
+
+/// Draw the markings of level into a width x height image covering the whole board
+pub fn markings_image(level: &game::Level, width: u32, height: u32) -> image::RgbaImage {
+    let mut canvas = Canvas::new(level, width, height);
+    for segment in level.path.windows(2) {
+        canvas.line(&segment[0], &segment[1], LINE_W);
+    }
+    if let [.., from, to] = level.path.as_slice() {
+        draw_arrow_head(&mut canvas, from, to);
+    }
+    // The first and the last path points are at the start and the goal, which have their own
+    // labels
+    for (i, p) in level.path.iter().enumerate().skip(1) {
+        if i + 1 < level.path.len() {
+            canvas.text(
+                &i.to_string(),
+                &game::Point {
+                    x: p.x + LABEL_H,
+                    y: p.y - LABEL_H,
+                },
+                LABEL_H,
+            );
+        }
+    }
+    for hole in level.holes.iter() {
+        canvas.ring(hole, RING_R, 0.5 * LINE_W);
+    }
+
+    let end = &level.end;
+    let corners = [
+        game::Point {
+            x: end.pos.x,
+            y: end.pos.y,
+        },
+        game::Point {
+            x: end.pos.x + end.size.w,
+            y: end.pos.y,
+        },
+        game::Point {
+            x: end.pos.x + end.size.w,
+            y: end.pos.y + end.size.h,
+        },
+        game::Point {
+            x: end.pos.x,
+            y: end.pos.y + end.size.h,
+        },
+    ];
+    for i in 0..corners.len() {
+        canvas.line(&corners[i], &corners[(i + 1) % corners.len()], LINE_W);
+    }
+
+    // The titles go above the start and the goal, or below them at the top edge of the board
+    let title_pos = |x: f32, top: f32, bottom: f32| game::Point {
+        x,
+        y: if top - 1.5 * TITLE_H >= 0.0 {
+            top - TITLE_H
+        } else {
+            bottom + TITLE_H
+        },
+    };
+    canvas.text(
+        "START",
+        &title_pos(
+            level.start.x,
+            level.start.y - game::BALL_R,
+            level.start.y + game::BALL_R,
+        ),
+        TITLE_H,
+    );
+    canvas.text(
+        "FINISH",
+        &title_pos(
+            end.pos.x + end.size.w / 2.0,
+            end.pos.y,
+            end.pos.y + end.size.h,
+        ),
+        TITLE_H,
+    );
+    canvas.into_image()
+}
+
+// Draw an arrow head at the end of the line from a to b
+fn draw_arrow_head(canvas: &mut Canvas, from: &game::Point, to: &game::Point) {
+    let (a, b) = (glm::Vec2::from(from), glm::Vec2::from(to));
+    if a == b {
+        return;
+    }
+    let back = glm::normalize(&(a - b)) * ARROW_LEN;
+    for angle in [0.5f32, -0.5].iter() {
+        let wing = b + glm::rotate_vec2(&back, *angle);
+        canvas.line(to, &game::Point::from(&wing), LINE_W);
+    }
+}
+
+// Coverage of the markings for each pixel, 0...1. Shapes are given in level coordinates and
+// antialiased by their distance from the pixel centers.
+struct Canvas {
+    width: u32,
+    height: u32,
+    size: game::Size, // level size
+    scale: glm::Vec2, // pixels per level unit
+    coverage: Vec<f32>,
+}
+
+impl Canvas {
+    fn new(level: &game::Level, width: u32, height: u32) -> Canvas {
+        Canvas {
+            width,
+            height,
+            size: level.size,
+            scale: glm::vec2(width as f32 / level.size.w, height as f32 / level.size.h),
+            coverage: vec![0.0; (width * height) as usize],
+        }
+    }
+
+    fn to_pixels(&self, p: &game::Point) -> glm::Vec2 {
+        glm::Vec2::from(p).component_mul(&self.scale)
+    }
+
+    // Line widths are scaled with the smaller scale so that they stay even on stretched images
+    fn to_pixel_width(&self, w: f32) -> f32 {
+        w * self.scale.x.min(self.scale.y)
+    }
+
+    // Cover the pixels within the box from min to max using the distance function from the
+    // pixel center to the shape outline, negative inside the shape
+    fn fill(&mut self, min: glm::Vec2, max: glm::Vec2, distance: impl Fn(glm::Vec2) -> f32) {
+        let x0 = min.x.floor().max(0.0) as u32;
+        let y0 = min.y.floor().max(0.0) as u32;
+        let x1 = (max.x.ceil().max(0.0) as u32).min(self.width);
+        let y1 = (max.y.ceil().max(0.0) as u32).min(self.height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let d = distance(glm::vec2(x as f32 + 0.5, y as f32 + 0.5));
+                let c = &mut self.coverage[(y * self.width + x) as usize];
+                *c = c.max(glm::clamp_scalar(0.5 - d, 0.0, 1.0));
+            }
+        }
+    }
+
+    // Line from a to b with round ends
+    fn line(&mut self, a: &game::Point, b: &game::Point, w: f32) {
+        let (a, b) = (self.to_pixels(a), self.to_pixels(b));
+        let r = self.to_pixel_width(w) / 2.0;
+        let margin = glm::vec2(r + 1.0, r + 1.0);
+        let ab = b - a;
+        let len2 = glm::length2(&ab);
+        self.fill(
+            glm::min2(&a, &b) - margin,
+            glm::max2(&a, &b) + margin,
+            |p| {
+                let t = if len2 > 0.0 {
+                    glm::clamp_scalar(glm::dot(&(p - a), &ab) / len2, 0.0, 1.0)
+                } else {
+                    0.0
+                };
+                glm::distance(&p, &(a + t * ab)) - r
+            },
+        );
+    }
+
+    // Circle outline with radius r
+    fn ring(&mut self, center: &game::Point, r: f32, w: f32) {
+        let c = self.to_pixels(center);
+        let radius = self.to_pixels(&game::Point { x: r, y: r });
+        let half_w = self.to_pixel_width(w) / 2.0;
+        let margin = radius + glm::vec2(half_w + 1.0, half_w + 1.0);
+        self.fill(c - margin, c + margin, |p| {
+            // Distance to an ellipse, approximated by scaling to a unit circle
+            let q = (p - c).component_div(&radius);
+            let len = glm::length(&q);
+            let outline = if len > 0.0 {
+                c + (p - c) / len
+            } else {
+                c + radius
+            };
+            glm::distance(&p, &outline) - half_w
+        });
+    }
+
+    // Axis aligned rectangle from min to max
+    fn rect(&mut self, min: &game::Point, max: &game::Point) {
+        let (min, max) = (self.to_pixels(min), self.to_pixels(max));
+        let center = (min + max) / 2.0;
+        let half_size = (max - min) / 2.0;
+        self.fill(min, max + glm::vec2(1.0, 1.0), |p| {
+            let d = (p - center).abs() - half_size;
+            d.x.max(d.y)
+        });
+    }
+
+    // Text centered at center. Only the characters in GLYPHS are drawn.
+    fn text(&mut self, text: &str, center: &game::Point, height: f32) {
+        let px = height / GLYPH_ROWS as f32; // size of one font pixel
+        let advance = (GLYPH_COLS + 1) as f32 * px;
+        let width = text.chars().count() as f32 * advance - px;
+        // Keep the text inside the board
+        let x0 = glm::clamp_scalar(center.x - width / 2.0, 0.0, (self.size.w - width).max(0.0));
+        let y0 = glm::clamp_scalar(
+            center.y - height / 2.0,
+            0.0,
+            (self.size.h - height).max(0.0),
+        );
+        for (i, c) in text.chars().enumerate() {
+            let rows = match GLYPHS.iter().find(|(g, _)| *g == c) {
+                Some((_, rows)) => rows,
+                None => continue,
+            };
+            for (row, bits) in rows.iter().enumerate() {
+                for col in 0..GLYPH_COLS {
+                    if bits & (1 << (GLYPH_COLS - 1 - col)) != 0 {
+                        let x = x0 + i as f32 * advance + col as f32 * px;
+                        let y = y0 + row as f32 * px;
+                        self.rect(
+                            &game::Point { x, y },
+                            &game::Point {
+                                x: x + px,
+                                y: y + px,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn into_image(self) -> image::RgbaImage {
+        let coverage = self.coverage;
+        let width = self.width;
+        image::RgbaImage::from_fn(self.width, self.height, |x, y| {
+            image::Rgba([0, 0, 0, (255.0 * coverage[(y * width + x) as usize]) as u8])
+        })
+    }
+}
+
+// 5 x 7 pixel font for the characters needed in the markings. Each row is a bit mask with the
+// leftmost pixel in the highest bit.
+const GLYPH_COLS: usize = 5;
+const GLYPH_ROWS: usize = 7;
+#[rustfmt::skip]
+const GLYPHS: [(char, [u8; GLYPH_ROWS]); 18] = [
+    ('0', [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110]),
+    ('1', [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('2', [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111]),
+    ('3', [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110]),
+    ('4', [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010]),
+    ('5', [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110]),
+    ('6', [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110]),
+    ('7', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000]),
+    ('8', [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110]),
+    ('9', [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100]),
+    ('A', [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('F', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('H', [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('I', [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('N', [0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001, 0b10001]),
+    ('R', [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001]),
+    ('S', [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110]),
+    ('T', [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100]),
+];
@@ -0,0 +1,472 @@
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Triangle mesh data in CPU memory. All vertex attribute vectors have the same length, and
+/// every three consecutive entries in `indices` form one counter-clockwise triangle. Texture
+/// coordinates follow the convention of the rest of the graphics code: (0, 0) is the bottom-left
+/// corner of the image.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// One drawable part of a glTF model: the mesh geometry with node transformations applied, and
+/// the material properties that Labyrinth's graphics can make use of.
+pub struct GltfPrimitive {
+    pub mesh: Mesh,
+    pub base_color: [f32; 4],
+    pub texture: Option<image::RgbaImage>, // Flipped vertically like all the other textures
+    pub metallic: f32,
+    pub roughness: f32,
+    pub alpha_mode: gltf::material::AlphaMode,
+    pub alpha_cutoff: f32,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Ply(String),
+    Obj { line: usize, msg: String },
+    Gltf(gltf::Error),
+    InvalidMesh(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Ply(msg) => write!(f, "Invalid PLY data: {}", msg),
+            Error::Obj { line, msg } => write!(f, "Invalid OBJ data on line {}: {}", line, msg),
+            Error::Gltf(e) => write!(f, "Invalid glTF data: {}", e),
+            Error::InvalidMesh(msg) => write!(f, "Invalid mesh: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<gltf::Error> for Error {
+    fn from(e: gltf::Error) -> Error {
+        Error::Gltf(e)
+    }
+}
+
+impl Mesh {
+    /// Load a mesh from ASCII or binary PLY data. The vertex element must have x, y and z
+    /// properties of any numeric type. Normals (nx, ny, nz) and texture coordinates (s, t or
+    /// u, v) are optional: missing normals are calculated from the faces, and missing texture
+    /// coordinates are set to zero. Polygonal faces are triangulated.
+    pub fn from_ply(bytes: &[u8]) -> Result<Mesh, Error> {
+        use ply_rs::ply::Property;
+
+        fn as_f32(property: &Property) -> Option<f32> {
+            match property {
+                Property::Char(v) => Some(*v as f32),
+                Property::UChar(v) => Some(*v as f32),
+                Property::Short(v) => Some(*v as f32),
+                Property::UShort(v) => Some(*v as f32),
+                Property::Int(v) => Some(*v as f32),
+                Property::UInt(v) => Some(*v as f32),
+                Property::Float(v) => Some(*v),
+                Property::Double(v) => Some(*v as f32),
+                _ => None,
+            }
+        }
+        fn as_index_list(property: &Property) -> Option<Vec<i64>> {
+            match property {
+                Property::ListChar(v) => Some(v.iter().map(|&i| i as i64).collect()),
+                Property::ListUChar(v) => Some(v.iter().map(|&i| i as i64).collect()),
+                Property::ListShort(v) => Some(v.iter().map(|&i| i as i64).collect()),
+                Property::ListUShort(v) => Some(v.iter().map(|&i| i as i64).collect()),
+                Property::ListInt(v) => Some(v.iter().map(|&i| i as i64).collect()),
+                Property::ListUInt(v) => Some(v.iter().map(|&i| i as i64).collect()),
+                _ => None,
+            }
+        }
+        // Read the first of the given property names that the element has
+        fn read(
+            element: &ply_rs::ply::DefaultElement,
+            names: &[&str],
+        ) -> Result<Option<f32>, Error> {
+            match names.iter().find_map(|n| element.get(*n)) {
+                Some(p) => as_f32(p)
+                    .map(Some)
+                    .ok_or_else(|| Error::Ply(format!("property {} is not a number", names[0]))),
+                None => Ok(None),
+            }
+        }
+
+        let mut reader = bytes;
+        let ply =
+            ply_rs::parser::Parser::<ply_rs::ply::DefaultElement>::new().read_ply(&mut reader)?;
+
+        let vertices = ply
+            .payload
+            .get("vertex")
+            .ok_or_else(|| Error::Ply(String::from("no vertex element")))?;
+        let mut mesh = Mesh::default();
+        let mut has_normals = true;
+        for v in vertices.iter() {
+            let coord = |name: &str| {
+                read(v, &[name])?
+                    .ok_or_else(|| Error::Ply(format!("vertex has no {} property", name)))
+            };
+            mesh.positions.push([coord("x")?, coord("y")?, coord("z")?]);
+            match (read(v, &["nx"])?, read(v, &["ny"])?, read(v, &["nz"])?) {
+                (Some(nx), Some(ny), Some(nz)) => mesh.normals.push([nx, ny, nz]),
+                _ => has_normals = false,
+            }
+            mesh.tex_coords.push([
+                read(v, &["s", "u", "texture_u"])?.unwrap_or(0.0),
+                read(v, &["t", "v", "texture_v"])?.unwrap_or(0.0),
+            ]);
+        }
+
+        if let Some(faces) = ply.payload.get("face") {
+            for f in faces.iter() {
+                let polygon = f
+                    .get("vertex_indices")
+                    .or_else(|| f.get("vertex_index"))
+                    .and_then(as_index_list)
+                    .ok_or_else(|| Error::Ply(String::from("face has no vertex index list")))?;
+                let polygon = polygon
+                    .iter()
+                    .map(|&i| check_index(i, mesh.positions.len()))
+                    .collect::<Result<Vec<u32>, Error>>()?;
+                triangulate(&polygon, &mut mesh.indices);
+            }
+        }
+
+        if !has_normals {
+            mesh.compute_normals();
+        }
+        mesh.validate()?;
+        Ok(mesh)
+    }
+
+    /// Load a mesh from Wavefront OBJ data. Only the geometry (v, vt, vn and f statements) is
+    /// read, everything else is ignored. Polygonal faces are triangulated, and normals are
+    /// calculated if the data doesn't contain them for all face vertices.
+    pub fn from_obj(obj: &str) -> Result<Mesh, Error> {
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+        let mut mesh = Mesh::default();
+        let mut has_normals = true;
+        // OBJ indexes the attributes separately -> create one mesh vertex per unique combination
+        let mut vertex_ids: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+
+        for (line_index, line) in obj.lines().enumerate() {
+            let line_number = line_index + 1;
+            let err = |msg: &str| Error::Obj {
+                line: line_number,
+                msg: String::from(msg),
+            };
+            let mut tokens = line.split_whitespace();
+            let floats = |tokens: std::str::SplitWhitespace| {
+                tokens
+                    .map(|t| t.parse::<f32>().map_err(|_| err("invalid number")))
+                    .collect::<Result<Vec<f32>, Error>>()
+            };
+            match tokens.next() {
+                Some("v") => match floats(tokens)?.as_slice() {
+                    [x, y, z] | [x, y, z, _] => positions.push([*x, *y, *z]),
+                    _ => return Err(err("vertex position needs 3 coordinates")),
+                },
+                Some("vt") => match floats(tokens)?.as_slice() {
+                    [u] => tex_coords.push([*u, 0.0]),
+                    [u, v] | [u, v, _] => tex_coords.push([*u, *v]),
+                    _ => return Err(err("texture coordinate needs 1...3 components")),
+                },
+                Some("vn") => match floats(tokens)?.as_slice() {
+                    [x, y, z] => normals.push([*x, *y, *z]),
+                    _ => return Err(err("normal needs 3 components")),
+                },
+                Some("f") => {
+                    let mut polygon = Vec::new();
+                    for vertex in tokens {
+                        let mut parts = vertex.split('/');
+                        let mut index = |count: usize, required: bool| match parts
+                            .next()
+                            .filter(|p| !p.is_empty())
+                        {
+                            Some(p) => p
+                                .parse::<i64>()
+                                .ok()
+                                .and_then(|i| resolve_obj_index(i, count))
+                                .map(Some)
+                                .ok_or_else(|| err("invalid face vertex index")),
+                            None if required => Err(err("face vertex has no position")),
+                            None => Ok(None),
+                        };
+                        let key = (
+                            index(positions.len(), true)?.unwrap(),
+                            index(tex_coords.len(), false)?,
+                            index(normals.len(), false)?,
+                        );
+                        let id = match vertex_ids.get(&key) {
+                            Some(id) => *id,
+                            None => {
+                                let id = mesh.positions.len() as u32;
+                                mesh.positions.push(positions[key.0]);
+                                mesh.tex_coords
+                                    .push(key.1.map(|i| tex_coords[i]).unwrap_or([0.0, 0.0]));
+                                match key.2 {
+                                    Some(i) => mesh.normals.push(normals[i]),
+                                    None => has_normals = false,
+                                }
+                                vertex_ids.insert(key, id);
+                                id
+                            }
+                        };
+                        polygon.push(id);
+                    }
+                    if polygon.len() < 3 {
+                        return Err(err("face needs at least 3 vertices"));
+                    }
+                    triangulate(&polygon, &mut mesh.indices);
+                }
+                _ => (), // Comments, groups, materials etc.
+            }
+        }
+
+        if !has_normals {
+            mesh.compute_normals();
+        }
+        mesh.validate()?;
+        Ok(mesh)
+    }
+
+    /// Load all triangle mesh primitives of the default scene (or the first scene) of a glTF 2.0
+    /// model. Both .gltf files with embedded (data URI) buffers and .glb files are supported.
+    /// Node transformations are applied to the vertex data.
+    pub fn from_gltf(bytes: &[u8]) -> Result<Vec<GltfPrimitive>, Error> {
+        let (document, buffers, images) = gltf::import_slice(bytes)?;
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .ok_or_else(|| Error::InvalidMesh(String::from("glTF data contains no scenes")))?;
+
+        let mut primitives = Vec::new();
+        let mut stack: Vec<(gltf::Node, glm::Mat4)> =
+            scene.nodes().map(|n| (n, glm::identity())).collect();
+        while let Some((node, parent_transform)) = stack.pop() {
+            let transform = parent_transform * glm::Mat4::from(node.transform().matrix());
+            if let Some(mesh) = node.mesh() {
+                for primitive in mesh.primitives() {
+                    primitives.push(gltf_primitive(&primitive, &transform, &buffers, &images)?);
+                }
+            }
+            stack.extend(node.children().map(|n| (n, transform)));
+        }
+        Ok(primitives)
+    }
+
+    /// Calculate smooth vertex normals by averaging the normals of the faces sharing each vertex,
+    /// weighted by face area.
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![glm::vec3(0.0, 0.0, 0.0); self.positions.len()];
+        for triangle in self.indices.chunks(3) {
+            let p: Vec<glm::Vec3> = triangle
+                .iter()
+                .map(|&i| glm::Vec3::from(self.positions[i as usize]))
+                .collect();
+            // Length of the cross product is twice the triangle area
+            let face_normal = glm::cross(&(p[1] - p[0]), &(p[2] - p[0]));
+            for &i in triangle.iter() {
+                normals[i as usize] += face_normal;
+            }
+        }
+        self.normals = normals
+            .iter()
+            .map(|n| {
+                if glm::length(n) > 0.0 {
+                    glm::normalize(n).into()
+                } else {
+                    [0.0, 1.0, 0.0]
+                }
+            })
+            .collect();
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.indices.is_empty() {
+            return Err(Error::InvalidMesh(String::from("mesh has no triangles")));
+        }
+        if self.normals.len() != self.positions.len()
+            || self.tex_coords.len() != self.positions.len()
+        {
+            return Err(Error::InvalidMesh(String::from(
+                "vertex attribute counts don't match",
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn gltf_primitive(
+    primitive: &gltf::Primitive,
+    transform: &glm::Mat4,
+    buffers: &[gltf::buffer::Data],
+    images: &[gltf::image::Data],
+) -> Result<GltfPrimitive, Error> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let normal_transform = glm::transpose(&glm::inverse(transform));
+
+    let mut mesh = Mesh::default();
+    mesh.positions = reader
+        .read_positions()
+        .ok_or_else(|| Error::InvalidMesh(String::from("glTF primitive has no positions")))?
+        .map(|p| (transform * glm::vec4(p[0], p[1], p[2], 1.0)).xyz().into())
+        .collect();
+    mesh.tex_coords = match reader.read_tex_coords(0) {
+        // glTF texture coordinate origin is in the top-left corner of the image
+        Some(tc) => tc.into_f32().map(|[u, v]| [u, 1.0 - v]).collect(),
+        None => vec![[0.0, 0.0]; mesh.positions.len()],
+    };
+    let vertex_count = mesh.positions.len() as u32;
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertex_count).collect(),
+    };
+    mesh.indices = match primitive.mode() {
+        gltf::mesh::Mode::Triangles => indices,
+        gltf::mesh::Mode::TriangleStrip => (2..indices.len())
+            .flat_map(|i| {
+                // Every other triangle in a strip has opposite winding
+                if i % 2 == 0 {
+                    vec![indices[i - 2], indices[i - 1], indices[i]]
+                } else {
+                    vec![indices[i - 1], indices[i - 2], indices[i]]
+                }
+            })
+            .collect(),
+        gltf::mesh::Mode::TriangleFan => {
+            let mut triangles = Vec::new();
+            triangulate(&indices, &mut triangles);
+            triangles
+        }
+        mode => {
+            return Err(Error::InvalidMesh(format!(
+                "unsupported glTF primitive mode {:?}",
+                mode
+            )))
+        }
+    };
+    for &i in mesh.indices.iter() {
+        check_index(i as i64, mesh.positions.len())?;
+    }
+    match reader.read_normals() {
+        Some(normals) => {
+            mesh.normals = normals
+                .map(|n| {
+                    let n = (normal_transform * glm::vec4(n[0], n[1], n[2], 0.0)).xyz();
+                    glm::normalize(&n).into()
+                })
+                .collect()
+        }
+        None => mesh.compute_normals(),
+    }
+    mesh.validate()?;
+
+    let material = primitive.material();
+    let pbr = material.pbr_metallic_roughness();
+    let texture = match pbr.base_color_texture() {
+        Some(info) => Some(gltf_image_to_rgba(
+            &images[info.texture().source().index()],
+        )?),
+        None => None,
+    };
+    Ok(GltfPrimitive {
+        mesh,
+        base_color: pbr.base_color_factor(),
+        texture,
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        alpha_mode: material.alpha_mode(),
+        alpha_cutoff: material.alpha_cutoff(),
+    })
+}
+
+fn gltf_image_to_rgba(data: &gltf::image::Data) -> Result<image::RgbaImage, Error> {
+    use gltf::image::Format;
+    let rgba: Vec<u8> = match data.format {
+        Format::R8 => data
+            .pixels
+            .iter()
+            .flat_map(|&r| vec![r, r, r, 255])
+            .collect(),
+        Format::R8G8 => data
+            .pixels
+            .chunks(2)
+            .flat_map(|p| vec![p[0], p[1], 0, 255])
+            .collect(),
+        Format::R8G8B8 => data
+            .pixels
+            .chunks(3)
+            .flat_map(|p| vec![p[0], p[1], p[2], 255])
+            .collect(),
+        Format::R8G8B8A8 => data.pixels.clone(),
+        Format::B8G8R8 => data
+            .pixels
+            .chunks(3)
+            .flat_map(|p| vec![p[2], p[1], p[0], 255])
+            .collect(),
+        Format::B8G8R8A8 => data
+            .pixels
+            .chunks(4)
+            .flat_map(|p| vec![p[2], p[1], p[0], p[3]])
+            .collect(),
+        format => {
+            return Err(Error::InvalidMesh(format!(
+                "unsupported glTF image format {:?}",
+                format
+            )))
+        }
+    };
+    let img = image::RgbaImage::from_raw(data.width, data.height, rgba)
+        .ok_or_else(|| Error::InvalidMesh(String::from("glTF image data has wrong size")))?;
+    Ok(image::imageops::flip_vertical(&img))
+}
+
+// Split a convex polygon to a triangle fan
+fn triangulate(polygon: &[u32], indices: &mut Vec<u32>) {
+    for i in 2..polygon.len() {
+        indices.extend_from_slice(&[polygon[0], polygon[i - 1], polygon[i]]);
+    }
+}
+
+fn check_index(index: i64, vertex_count: usize) -> Result<u32, Error> {
+    if index >= 0 && (index as usize) < vertex_count {
+        Ok(index as u32)
+    } else {
+        Err(Error::InvalidMesh(format!(
+            "vertex index {} out of range",
+            index
+        )))
+    }
+}
+
+// OBJ indices are 1-based, and negative indices are relative to the end of the list
+fn resolve_obj_index(index: i64, count: usize) -> Option<usize> {
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved >= 0 && (resolved as usize) < count {
+        Some(resolved as usize)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,35 @@
+#version 450
+
+// Per-instance particle data
+layout(location=0) in vec3 position;
+layout(location=1) in float size;
+layout(location=2) in vec4 color;
+
+layout(location=0) out vec2 fragTexCoords;
+layout(location=1) out vec4 fragColor;
+
+layout(set=0, binding=0) uniform SceneUniforms {
+    mat4 viewProjection;
+    uvec4 numLights;
+    vec4 cameraPosWorldSpace;
+    vec4 cameraRightWorldSpace;
+    vec4 cameraUpWorldSpace;
+};
+
+// The corners of the two triangles forming a particle quad
+const vec2 CORNERS[6] = vec2[6](
+    vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5),
+    vec2(-0.5, -0.5), vec2(0.5, 0.5), vec2(-0.5, 0.5)
+);
+
+void main()
+{
+    vec2 corner = CORNERS[gl_VertexIndex];
+    // Billboarding: the quad is always parallel to the view plane
+    vec3 p = position + size * (corner.x * cameraRightWorldSpace.xyz + corner.y * cameraUpWorldSpace.xyz);
+
+    fragTexCoords = vec2(corner.x + 0.5, 0.5 - corner.y);
+    fragColor = color;
+
+    gl_Position = viewProjection * vec4(p, 1.0);
+}